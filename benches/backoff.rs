@@ -0,0 +1,113 @@
+// Two-thread throughput with a small buffer that the producer keeps filling,
+// so that nearly every write has to wait for the consumer to free up space.
+// Compares retrying at the call site against each Backoff strategy, and
+// against blocking on a Condvar until the consumer signals. Spinning only
+// pays off when the consumer is running on another core: on a single core it
+// just burns the time the consumer needs to make room.
+//
+// Run with `cargo bench --bench backoff`.
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use bbuf::buffer::Backoff;
+
+const CAPACITY: usize = 1 << 10;
+const MESSAGE: usize = 64;
+const MESSAGES: usize = 2_000_000;
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name:>15}: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn run(name: &str, backoff: Option<Backoff>) {
+    let (mut reader, mut writer) = bbuf::buffer::create(CAPACITY);
+    if let Some(backoff) = backoff {
+        writer.set_backoff(backoff);
+    }
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let message = [0x42; MESSAGE];
+            for _ in 0..MESSAGES {
+                if backoff.is_some() {
+                    while writer.write_retry(&message).is_err() {
+                        std::thread::yield_now();
+                    }
+                } else {
+                    while !writer.try_write(&message) {
+                        std::thread::yield_now();
+                    }
+                }
+            }
+        });
+        let mut received = 0;
+        while received < MESSAGES * MESSAGE {
+            match reader.read() {
+                Some(l) => received += l.view.len(),
+                None => std::thread::yield_now(),
+            }
+        }
+    });
+    report(name, start.elapsed());
+}
+
+fn condvar() {
+    let queue = Mutex::new(VecDeque::with_capacity(CAPACITY));
+    let freed = Condvar::new();
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let message = [0x42; MESSAGE];
+            for _ in 0..MESSAGES {
+                let mut q = queue.lock().unwrap();
+                while q.len() + MESSAGE > CAPACITY {
+                    q = freed.wait(q).unwrap();
+                }
+                q.extend(message);
+            }
+        });
+        let mut received = 0;
+        while received < MESSAGES * MESSAGE {
+            let n = {
+                let mut q = queue.lock().unwrap();
+                let n = q.len();
+                q.clear();
+                n
+            };
+            if n == 0 {
+                std::thread::yield_now();
+            } else {
+                freed.notify_one();
+                received += n;
+            }
+        }
+    });
+    report("condvar", start.elapsed());
+}
+
+fn main() {
+    run("caller retry", None);
+    run("spin", Some(Backoff::Spin { max_iters: 1 << 12 }));
+    run(
+        "spin then yield",
+        Some(Backoff::SpinThenYield {
+            spins: 1 << 10,
+            yields: 1 << 10,
+        }),
+    );
+    run(
+        "spin then park",
+        Some(Backoff::SpinThenPark {
+            spins: 1 << 10,
+            timeout: Duration::from_millis(10),
+        }),
+    );
+    condvar();
+}
@@ -0,0 +1,301 @@
+// Compares the bbuf buffers against std::sync::mpsc, crossbeam::channel and a
+// Mutex<VecDeque<u8>> on the same workloads:
+//   - single-threaded write+read throughput for 16B, 256B and 4KB messages,
+//     with the bbuf buffers sized so that they invert every few writes
+//   - two-thread throughput
+//   - two-thread latency, measured by having the consumer compare the
+//     timestamp each message was sent with against when it arrived
+// The two-thread numbers only mean much on a machine with at least two idle
+// cores.
+//
+// Run with `cargo bench --bench compare`.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const PAYLOADS: [usize; 3] = [16, 256, 4096];
+// How many bytes each single-threaded and throughput run moves in total.
+const BYTES: usize = 1 << 30;
+// The two-thread runs use a buffer with room for this many messages.
+const DEPTH: usize = 256;
+const LATENCY_SAMPLES: usize = 20_000;
+const LATENCY_INTERVAL: Duration = Duration::from_micros(10);
+
+// Queue is the minimal interface every implementation is benchmarked through:
+// a non-blocking write, and a non-blocking read that hands everything it got
+// to `f` (before releasing it) and returns how many bytes that was.
+trait Queue {
+    fn name() -> &'static str;
+    fn create(capacity: usize) -> (impl Tx, impl Rx);
+}
+trait Tx: Send {
+    fn write(&mut self, p: &[u8]) -> bool;
+}
+trait Rx {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize;
+}
+
+struct Mutexed;
+impl Queue for Mutexed {
+    fn name() -> &'static str {
+        "bbuf::buffer"
+    }
+    fn create(capacity: usize) -> (impl Tx, impl Rx) {
+        let (r, w) = bbuf::buffer::create(capacity);
+        (w, r)
+    }
+}
+impl Tx for bbuf::buffer::Writer {
+    fn write(&mut self, p: &[u8]) -> bool {
+        self.try_write(p)
+    }
+}
+impl Rx for bbuf::buffer::Reader {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize {
+        self.read().map_or(0, |l| {
+            f(l.view);
+            l.view.len()
+        })
+    }
+}
+
+struct Spsc;
+impl Queue for Spsc {
+    fn name() -> &'static str {
+        "bbuf::spsc"
+    }
+    fn create(capacity: usize) -> (impl Tx, impl Rx) {
+        let (r, w) = bbuf::spsc::create(capacity);
+        (w, r)
+    }
+}
+impl Tx for bbuf::spsc::Writer {
+    fn write(&mut self, p: &[u8]) -> bool {
+        self.try_write(p)
+    }
+}
+impl Rx for bbuf::spsc::Reader {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize {
+        self.read().map_or(0, |l| {
+            f(l.view);
+            l.view.len()
+        })
+    }
+}
+
+struct StdMpsc;
+impl Queue for StdMpsc {
+    fn name() -> &'static str {
+        "std::sync::mpsc"
+    }
+    fn create(capacity: usize) -> (impl Tx, impl Rx) {
+        // Channels hold messages rather than bytes; assume 16B messages so
+        // that they never have less room than the buffers.
+        std::sync::mpsc::sync_channel::<Box<[u8]>>(capacity.div_ceil(16))
+    }
+}
+impl Tx for std::sync::mpsc::SyncSender<Box<[u8]>> {
+    fn write(&mut self, p: &[u8]) -> bool {
+        self.try_send(p.into()).is_ok()
+    }
+}
+impl Rx for std::sync::mpsc::Receiver<Box<[u8]>> {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize {
+        self.try_recv().map_or(0, |m| {
+            f(&m);
+            m.len()
+        })
+    }
+}
+
+struct Crossbeam;
+impl Queue for Crossbeam {
+    fn name() -> &'static str {
+        "crossbeam::channel"
+    }
+    fn create(capacity: usize) -> (impl Tx, impl Rx) {
+        crossbeam::channel::bounded::<Box<[u8]>>(capacity.div_ceil(16))
+    }
+}
+impl Tx for crossbeam::channel::Sender<Box<[u8]>> {
+    fn write(&mut self, p: &[u8]) -> bool {
+        self.try_send(p.into()).is_ok()
+    }
+}
+impl Rx for crossbeam::channel::Receiver<Box<[u8]>> {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize {
+        self.try_recv().map_or(0, |m| {
+            f(&m);
+            m.len()
+        })
+    }
+}
+
+struct Deque;
+struct DequeHalf {
+    q: Arc<Mutex<VecDeque<u8>>>,
+    capacity: usize,
+    scratch: Vec<u8>,
+}
+impl Queue for Deque {
+    fn name() -> &'static str {
+        "Mutex<VecDeque<u8>>"
+    }
+    fn create(capacity: usize) -> (impl Tx, impl Rx) {
+        let q = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+        let half = |q| DequeHalf {
+            q,
+            capacity,
+            scratch: Vec::with_capacity(capacity),
+        };
+        (half(q.clone()), half(q))
+    }
+}
+impl Tx for DequeHalf {
+    fn write(&mut self, p: &[u8]) -> bool {
+        let mut q = self.q.lock().unwrap();
+        if q.len() + p.len() > self.capacity {
+            return false;
+        }
+        q.extend(p);
+        true
+    }
+}
+impl Rx for DequeHalf {
+    fn read(&mut self, f: &mut dyn FnMut(&[u8])) -> usize {
+        self.scratch.clear();
+        self.scratch.extend(self.q.lock().unwrap().drain(..));
+        if !self.scratch.is_empty() {
+            f(&self.scratch);
+        }
+        self.scratch.len()
+    }
+}
+
+fn report(name: &str, what: &str, messages: usize, bytes: usize, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "{what:>18} {name:>20}: {:7.2} M messages/s {:8.1} MB/s",
+        messages as f64 / secs / 1e6,
+        bytes as f64 / secs / 1e6,
+    );
+}
+
+// single writes and reads back one message at a time on the same thread. The
+// buffer has room for 3.5 messages, and each message is written while the
+// previous one is still being read, so the writer keeps wrapping around
+// behind the reader.
+fn single<Q: Queue>(payload: usize) {
+    let (mut tx, mut rx) = Q::create(payload * 7 / 2);
+    let message = vec![0xa5; payload];
+    let messages = BYTES / payload;
+    assert!(tx.write(&message));
+    let mut next = |p: &[u8]| {
+        std::hint::black_box(p);
+        assert!(tx.write(&message));
+    };
+    let start = Instant::now();
+    for _ in 0..messages {
+        assert_eq!(rx.read(&mut next), payload);
+    }
+    report(
+        Q::name(),
+        &format!("single {payload}B"),
+        messages,
+        messages * payload,
+        start.elapsed(),
+    );
+}
+
+fn throughput<Q: Queue>(payload: usize) {
+    let (mut tx, mut rx) = Q::create(payload * DEPTH);
+    let messages = BYTES / payload;
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let message = vec![0xa5; payload];
+            for _ in 0..messages {
+                while !tx.write(&message) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut received = 0;
+        while received < messages * payload {
+            match rx.read(&mut |p| {
+                std::hint::black_box(p);
+            }) {
+                0 => std::thread::yield_now(),
+                n => received += n,
+            }
+        }
+    });
+    report(
+        Q::name(),
+        &format!("threaded {payload}B"),
+        messages,
+        messages * payload,
+        start.elapsed(),
+    );
+}
+
+// latency sends a 16B message holding its send time every LATENCY_INTERVAL,
+// and reports percentiles of how long each one took to arrive.
+fn latency<Q: Queue>() {
+    let (mut tx, mut rx) = Q::create(16 * DEPTH);
+    let epoch = Instant::now();
+    let mut samples = Vec::with_capacity(LATENCY_SAMPLES);
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for i in 0..LATENCY_SAMPLES as u32 {
+                while epoch.elapsed() < LATENCY_INTERVAL * i {
+                    std::hint::spin_loop();
+                }
+                let mut message = [0; 16];
+                message[..8].copy_from_slice(&(epoch.elapsed().as_nanos() as u64).to_le_bytes());
+                while !tx.write(&message) {
+                    std::hint::spin_loop();
+                }
+            }
+        });
+        while samples.len() < LATENCY_SAMPLES {
+            rx.read(&mut |p| {
+                let now = epoch.elapsed().as_nanos() as u64;
+                for m in p.chunks(16) {
+                    let sent = u64::from_le_bytes(m[..8].try_into().unwrap());
+                    samples.push(now - sent);
+                }
+            });
+        }
+    });
+    samples.sort_unstable();
+    let percentile = |p: usize| samples[samples.len() * p / 100];
+    println!(
+        "{:>18} {:>20}: p50 {:6} ns  p99 {:8} ns",
+        "latency 16B",
+        Q::name(),
+        percentile(50),
+        percentile(99),
+    );
+}
+
+fn all<Q: Queue>() {
+    for payload in PAYLOADS {
+        single::<Q>(payload);
+    }
+    for payload in PAYLOADS {
+        throughput::<Q>(payload);
+    }
+    latency::<Q>();
+}
+
+fn main() {
+    all::<Mutexed>();
+    all::<Spsc>();
+    all::<StdMpsc>();
+    all::<Crossbeam>();
+    all::<Deque>();
+}
@@ -0,0 +1,54 @@
+// Throughput of the Mutex-based buffer as more and more writers fight over
+// its lock, with one consumer draining it. This is the number to watch when
+// changing how the tracker is locked: the uncontended case is dominated by
+// copying, but with several writers most of the time goes to the lock
+// itself. Like the mpsc bench, this only means much on a machine with enough
+// idle cores for every thread.
+//
+// There's no parking_lot build to compare it with yet: this is only the
+// std::sync::Mutex side of that comparison.
+//
+// Run with `cargo bench --bench contended`.
+
+use std::time::Instant;
+
+const CAPACITY: usize = 1 << 16;
+const MESSAGE: usize = 16;
+const MESSAGES: usize = 2_000_000;
+
+fn run(writers: usize) {
+    let (mut reader, writer) = bbuf::buffer::create(CAPACITY);
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..writers {
+            let mut writer = writer.clone();
+            scope.spawn(move || {
+                let message = [0x42; MESSAGE];
+                for _ in 0..MESSAGES / writers {
+                    while !writer.try_write(&message) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+        }
+        drop(writer);
+        let mut received = 0;
+        while received < MESSAGES / writers * writers * MESSAGE {
+            match reader.read() {
+                Some(l) => received += l.view.len(),
+                None => std::thread::yield_now(),
+            }
+        }
+    });
+    let elapsed = start.elapsed();
+    println!(
+        "{writers} writers: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    for writers in [1, 2, 4, 8] {
+        run(writers);
+    }
+}
@@ -0,0 +1,39 @@
+// Measures how long it takes to create a large buffer, comparing the default
+// (uninitialized) storage against handing in zeroed storage.
+//
+// Run with `cargo bench --bench create`.
+
+use std::time::{Duration, Instant};
+
+const CAPACITY: usize = 256 << 20;
+const ITERATIONS: u32 = 20;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        f();
+        total += start.elapsed();
+    }
+    println!("{name:>26}: {:?} per iteration", total / ITERATIONS);
+}
+
+fn main() {
+    bench("create", || {
+        let (mut reader, mut writer) = bbuf::buffer::create(CAPACITY);
+        assert!(writer.try_write(b"asdf"));
+        assert!(reader.read().is_some());
+    });
+    bench("create_from_vec (zeroed)", || {
+        let (mut reader, mut writer) = bbuf::buffer::create_from_vec(vec![0; CAPACITY]);
+        assert!(writer.try_write(b"asdf"));
+        assert!(reader.read().is_some());
+    });
+    bench("zeroed vec, pages touched", || {
+        let mut v = vec![0u8; CAPACITY];
+        // Touch every page so the allocator can't get away with lazily
+        // zeroed pages, which is what a non-calloc allocator would do.
+        v.iter_mut().step_by(4096).for_each(|b| *b = 1);
+        std::hint::black_box(v);
+    });
+}
@@ -0,0 +1,34 @@
+// Measures alternating write/read of small payloads. When the reader keeps up,
+// every write to the default buffer lands on an idle buffer and skips the
+// mutex. The power-of-two tracker doesn't reset its offsets when it drains,
+// so its writes almost always take the mutex.
+//
+// Run with `cargo bench --bench fastpath`.
+
+use std::time::Instant;
+
+use bbuf::buffer::{Reader, Writer};
+
+const CAPACITY: usize = 1 << 12;
+const MESSAGE: usize = 24;
+const MESSAGES: usize = 20_000_000;
+
+fn run(name: &str, (mut reader, mut writer): (Reader, Writer)) {
+    let message = [0xa5; MESSAGE];
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        assert!(writer.try_write(&message));
+        let l = reader.read().unwrap();
+        std::hint::black_box(l.view);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>7}: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    run("default", bbuf::buffer::create(CAPACITY));
+    run("pow2", bbuf::buffer::create_pow2(CAPACITY));
+}
@@ -0,0 +1,46 @@
+// Compares the standard and mirrored buffers on a packet-capture style
+// workload: fixed-size packets, with the reader lagging far enough behind the
+// writer that the standard buffer inverts regularly.
+//
+// Run with `cargo bench --features mmap --bench mirrored`.
+
+use std::time::Instant;
+
+use bbuf::buffer::{Reader, Writer};
+
+const CAPACITY: usize = 1 << 16;
+const PACKET: usize = 1500;
+const TOTAL: usize = 1 << 30;
+
+fn run(name: &str, (mut reader, mut writer): (Reader, Writer)) {
+    let packet = [0x5a; PACKET];
+    let mut written = 0;
+    let mut read = 0;
+    let mut leases = 0;
+    let start = Instant::now();
+    while read < TOTAL {
+        while written < TOTAL && writer.try_write(&packet) {
+            written += PACKET;
+        }
+        // Only drain half of what's buffered, so the writer keeps running
+        // into the end of the buffer.
+        let mut drained = 0;
+        while drained < CAPACITY / 2 {
+            let Some(l) = reader.read() else { break };
+            drained += l.view.len();
+            leases += 1;
+            std::hint::black_box(l.view);
+        }
+        read += drained;
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>9}: {:.2} GiB/s, {leases} leases",
+        read as f64 / elapsed.as_secs_f64() / (1 << 30) as f64,
+    );
+}
+
+fn main() {
+    run("standard", bbuf::buffer::create(CAPACITY));
+    run("mirrored", bbuf::buffer::create_mirrored(CAPACITY).unwrap());
+}
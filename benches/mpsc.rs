@@ -0,0 +1,70 @@
+// Throughput of small messages from 8 producer threads to one consumer,
+// through the Mutex-based buffer, the lock-free MPSC buffer, and a bounded
+// crossbeam channel of fixed-size messages. Like the spsc bench, this only
+// means much on a machine with enough idle cores for every thread.
+//
+// Run with `cargo bench --bench mpsc`.
+
+use std::time::Instant;
+
+const PRODUCERS: usize = 8;
+const CAPACITY: usize = 1 << 16;
+const MESSAGE: usize = 64;
+const MESSAGES: usize = 1_000_000;
+
+fn run<W, R>(name: &str, writer: W, mut reader: R)
+where
+    W: FnMut(&[u8]) -> bool + Clone + Send,
+    R: FnMut() -> usize,
+{
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..PRODUCERS {
+            let mut writer = writer.clone();
+            scope.spawn(move || {
+                let message = [0x42; MESSAGE];
+                for _ in 0..MESSAGES / PRODUCERS {
+                    while !writer(&message) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+        }
+        drop(writer);
+        let mut received = 0;
+        while received < MESSAGES * MESSAGE {
+            match reader() {
+                0 => std::thread::yield_now(),
+                n => received += n,
+            }
+        }
+    });
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>9}: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let (mut reader, mut writer) = bbuf::buffer::create(CAPACITY);
+    run(
+        "mutex",
+        move |p| writer.try_write(p),
+        move || reader.read().map_or(0, |l| l.view.len()),
+    );
+
+    let (mut reader, mut writer) = bbuf::mpsc::create(CAPACITY);
+    run(
+        "mpsc",
+        move |p| writer.try_write(p),
+        move || reader.read().map_or(0, |l| l.view.len()),
+    );
+
+    let (tx, rx) = crossbeam::channel::bounded::<[u8; MESSAGE]>(CAPACITY / MESSAGE);
+    run(
+        "crossbeam",
+        move |p| tx.try_send(p.try_into().unwrap()).is_ok(),
+        move || rx.try_recv().map_or(0, |m| m.len()),
+    );
+}
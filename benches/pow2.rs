@@ -0,0 +1,41 @@
+// Compares the default tracker against the power-of-two tracker on a high
+// message rate workload: many small writes, read back in batches.
+//
+// Run with `cargo bench --bench pow2`.
+
+use std::time::Instant;
+
+use bbuf::buffer::{Reader, Writer};
+
+const CAPACITY: usize = 1 << 12;
+const MESSAGE: usize = 24;
+const MESSAGES: usize = 50_000_000;
+
+fn run(name: &str, (mut reader, mut writer): (Reader, Writer)) {
+    let message = [0xa5; MESSAGE];
+    let mut sent = 0;
+    let start = Instant::now();
+    while sent < MESSAGES {
+        while sent < MESSAGES && writer.try_write(&message) {
+            sent += 1;
+        }
+        // Read back one lease at a time, so the writer keeps wrapping around
+        // behind the reader.
+        if let Some(l) = reader.read() {
+            std::hint::black_box(l.view);
+        }
+    }
+    while let Some(l) = reader.read() {
+        std::hint::black_box(l.view);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>7}: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    run("default", bbuf::buffer::create(CAPACITY));
+    run("pow2", bbuf::buffer::create_pow2(CAPACITY));
+}
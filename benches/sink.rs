@@ -0,0 +1,100 @@
+// Writes through a sink into a file, comparing the File's own vectored
+// writes against a wrapper that writes one slice at a time, like most
+// writers do. A drain whose data wraps around the end of the buffer is a
+// single write call for the former, and two for the latter.
+//
+// Each call takes at least LATENCY, standing in for a slower device, and the
+// producer pauses between messages, so that the sink keeps falling a little
+// behind: a sink that drains a full buffer in one go leaves it empty, and
+// the data never wraps. How often it does wrap depends on how the threads
+// interleave, so that's counted too.
+//
+// Run with `cargo bench --bench sink`.
+
+use std::{
+    fs::File,
+    io::{IoSlice, Write},
+    time::{Duration, Instant},
+};
+
+const CAPACITY: usize = 4000;
+const MESSAGE: usize = 100;
+const MESSAGES: usize = 20_000;
+const LATENCY: Duration = Duration::from_micros(200);
+const PAUSE: Duration = Duration::from_micros(10);
+
+// Counted counts the calls that reach the file, and the drains they were
+// for. If `vectored` is unset, it does what write_vectored does by default,
+// which is to only write the first slice.
+struct Counted {
+    file: File,
+    vectored: bool,
+    calls: usize,
+    drains: usize,
+    wraps: usize,
+}
+impl Counted {
+    fn call<T>(&mut self, f: impl FnOnce(&mut File) -> T) -> T {
+        self.calls += 1;
+        std::thread::sleep(LATENCY);
+        f(&mut self.file)
+    }
+}
+impl Write for Counted {
+    // The sink only calls write for a drain that didn't wrap.
+    fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+        self.drains += 1;
+        self.call(|f| f.write(p))
+    }
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        // It passes both parts of a wrapped drain, and then whatever's left
+        // of them after a short write.
+        if bufs.len() > 1 {
+            self.drains += 1;
+            self.wraps += 1;
+        }
+        if self.vectored {
+            return self.call(|f| f.write_vectored(bufs));
+        }
+        let first = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| b);
+        self.call(|f| f.write(first))
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn run(name: &str, vectored: bool) {
+    let path = std::env::temp_dir().join(format!("bbuf-bench-sink-{}", std::process::id()));
+    let out = Counted {
+        file: File::create(&path).unwrap(),
+        vectored,
+        calls: 0,
+        drains: 0,
+        wraps: 0,
+    };
+    let message = [0x42; MESSAGE];
+    let start = Instant::now();
+    let (out, report) = std::thread::scope(|scope| {
+        let (mut h, sink) = bbuf::sink::spawn_sink(scope, CAPACITY, out);
+        for _ in 0..MESSAGES {
+            while h.write(&message).is_err() {
+                std::thread::yield_now();
+            }
+            std::thread::sleep(PAUSE);
+        }
+        sink.shutdown()
+    });
+    let elapsed = start.elapsed();
+    assert_eq!(report.unwrap().written, (MESSAGE * MESSAGES) as u64);
+    std::fs::remove_file(&path).unwrap();
+    println!(
+        "{name:>10}: {elapsed:?}, {} write calls for {} drains ({} wrapped)",
+        out.calls, out.drains, out.wraps
+    );
+}
+
+fn main() {
+    run("vectored", true);
+    run("sequential", false);
+}
@@ -0,0 +1,58 @@
+// Two-thread throughput of small messages through the Mutex-based buffer and
+// the lock-free SPSC buffer. The producer and consumer should end up on
+// different cores, so run this on a machine with at least two idle ones;
+// that's where the separate cache lines for the two sides' offsets pay off.
+//
+// Run with `cargo bench --bench spsc`.
+
+use std::time::Instant;
+
+const CAPACITY: usize = 1 << 16;
+const MESSAGE: usize = 64;
+const MESSAGES: usize = 5_000_000;
+
+fn run<W, R>(name: &str, mut writer: W, mut reader: R)
+where
+    W: FnMut(&[u8]) -> bool + Send,
+    R: FnMut() -> usize,
+{
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let message = [0x42; MESSAGE];
+            for _ in 0..MESSAGES {
+                while !writer(&message) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut received = 0;
+        while received < MESSAGES * MESSAGE {
+            match reader() {
+                0 => std::thread::yield_now(),
+                n => received += n,
+            }
+        }
+    });
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>6}: {:.1} M messages/s",
+        MESSAGES as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let (mut reader, mut writer) = bbuf::buffer::create(CAPACITY);
+    run(
+        "mutex",
+        move |p| writer.try_write(p),
+        move || reader.read().map_or(0, |l| l.view.len()),
+    );
+
+    let (mut reader, mut writer) = bbuf::spsc::create(CAPACITY);
+    run(
+        "spsc",
+        move |p| writer.try_write(p),
+        move || reader.read().map_or(0, |l| l.view.len()),
+    );
+}
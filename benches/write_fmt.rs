@@ -0,0 +1,35 @@
+// Formats log-like lines into a sink, comparing `write!` on the Handle,
+// which formats into a scratch space that it keeps, against formatting each
+// line into a String with `format!` and writing that.
+//
+// Run with `cargo bench --bench write_fmt`.
+
+use std::time::Instant;
+
+use bbuf::sink::{Handle, spawn_owned};
+
+const CAPACITY: usize = 1 << 16;
+const LINES: usize = 1_000_000;
+
+fn run(name: &str, mut write: impl FnMut(&mut Handle, usize) -> bool) {
+    let (mut h, sink) = spawn_owned(CAPACITY, std::io::sink());
+    let start = Instant::now();
+    for i in 0..LINES {
+        while !write(&mut h, i) {
+            std::thread::yield_now();
+        }
+    }
+    let elapsed = start.elapsed();
+    sink.shutdown().1.unwrap();
+    println!("{name:>9}: {:?} per line", elapsed / LINES as u32);
+}
+
+fn main() {
+    run("format!", |h, i| {
+        let line = format!("request {i} took {}us from {:?}", i % 977, "10.0.0.1");
+        h.write(line.as_bytes()).is_ok()
+    });
+    run("write!", |h, i| {
+        write!(h, "request {i} took {}us from {:?}", i % 977, "10.0.0.1").is_ok()
+    });
+}
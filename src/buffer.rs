@@ -1,132 +1,4885 @@
 use std::{
     cell::UnsafeCell,
-    sync::{Arc, Mutex},
+    collections::VecDeque,
+    fmt,
+    ops::{Deref, DerefMut, Range},
+    thread::Thread,
+    time::{Duration, Instant},
 };
 
-use crate::tracker::{ReadLease, Tracker};
+pub use crate::error::{
+    ContiguousError, CreateError, RecordError, ResizeError, Utf8Error, WriteError,
+};
+use crate::{
+    error::validate_capacity,
+    storage::Storage,
+    sync::{Arc, AtomicBool, AtomicUsize, Mutex, MutexGuard, Ordering, lock, spin_loop, try_lock},
+    tracker::{AnyTracker, Pow2Tracker, ReadLease, Tracker, WriteLease},
+};
+
+// We solemnly swear that the users of Buffer will avoid data races on the
+// `data` field by always following access patterns vetted by the `tracker`
+struct Buffer {
+    tracker: Mutex<AnyTracker>,
+    // fast lets a writer skip the mutex entirely when the buffer is idle (see
+    // `Tracker::is_idle`). It's one of the FAST_* states below; the tracker
+    // is only authoritative in FAST_OFF, and whoever holds the lock moves it
+    // back there (see `lock`) before touching the tracker.
+    fast: AtomicUsize,
+    // releases counts how many leases have been released (or transactions
+    // finished), so that a Writer backing off can cheaply tell when there
+    // might be new free space.
+    releases: AtomicUsize,
+    // waiters are the Writers parked until the next release, and `waiting`
+    // is how many of them there are, so that releases can skip locking
+    // `waiters` in the common case.
+    waiting: AtomicUsize,
+    waiters: Mutex<Vec<Thread>>,
+    counters: Counters,
+    // leased is whether the reader holds a Lease. It's only accessed while
+    // holding the tracker lock.
+    leased: AtomicBool,
+    // disconnected is whether the Reader has been dropped. It's only set to
+    // true while holding the tracker lock.
+    disconnected: AtomicBool,
+    // paused is whether the Control has paused writes. It's only changed
+    // while holding the tracker lock.
+    paused: AtomicBool,
+    // compact is whether the Control asked for the unread data to be moved
+    // to the start of the buffer as soon as nothing is in the way.
+    compact: AtomicBool,
+    // controls is how many Controls there are.
+    controls: AtomicUsize,
+    overflow: OverflowPolicy,
+    // records is the side ring for record mode (see BufferOptions::records).
+    // It's only locked while holding the tracker lock.
+    records: Option<Mutex<Records>>,
+    // frame_size is the size that every write must be a multiple of (see
+    // BufferOptions::frame_size), or 1.
+    frame_size: usize,
+    // checksums is whether each record ends with a CRC32C of its contents
+    // (see BufferOptions::checksums).
+    checksums: bool,
+    // zeroize is whether released data is zeroed (see
+    // BufferOptions::zeroize_on_release).
+    zeroize: bool,
+    // clock stamps each write, if set (see BufferOptions::clock).
+    clock: Option<std::sync::Arc<dyn Clock>>,
+    // stamps are the times of the unread writes, oldest first, if there's a
+    // clock. Record mode keeps them in the records instead. It's only locked
+    // while holding the tracker lock.
+    stamps: Option<Mutex<VecDeque<Stamp>>>,
+    // history is a copy of the most recently read bytes, if enabled (see
+    // BufferOptions::history). It's only locked while holding the tracker
+    // lock, or by Reader::replay.
+    history: Option<Mutex<History>>,
+    // txn is the space held by an open Transaction, if any. It's only locked
+    // while holding the tracker lock.
+    txn: Mutex<Option<Reservation>>,
+    // pinned is the lease behind what Reader::fill_buf last returned, until
+    // it's consumed. It's never locked while holding the tracker lock.
+    pinned: Mutex<Option<ReadLease>>,
+    // parts tracks Leases that have been split (see Lease::split_at). It's
+    // only locked while holding the tracker lock.
+    parts: Mutex<Parts>,
+    // data is only ever replaced by `resize`, which holds the tracker lock
+    // while nobody else can be using it: there's no Lease, lock-free write,
+    // or Transaction (see `data`).
+    data: UnsafeCell<Storage>,
+}
+// The UnsafeCell is only there for `resize`, which is careful not to race
+// with anything.
+unsafe impl Sync for Buffer {}
+
+// Reservation is space that's been committed to the tracker on behalf of a
+// Transaction, but that the reader mustn't see yet. Since every other write
+// fails while it exists, it's always the newest data in the buffer.
+struct Reservation {
+    start: usize,
+    len: usize,
+    // inverted is whether reserving this space inverted the buffer.
+    inverted: bool,
+}
+impl Reservation {
+    fn lease(&self) -> WriteLease {
+        WriteLease {
+            start: self.start,
+            len: self.len,
+        }
+    }
+}
+
+// Clock is where record timestamps come from (see BufferOptions::clock). It
+// must never go backwards.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+// MonotonicClock is the real clock: Instant::now.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonotonicClock;
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Stamp is when a write was made, in a buffer with a clock. Rather than
+// where the write is in the buffer, it records where it ends in the stream of
+// everything ever written (i.e. `bytes_written` right after it), so that it
+// doesn't matter whether the buffer has inverted since.
+struct Stamp {
+    end: usize,
+    at: Instant,
+}
+
+// Records are the unread records in a record-mode buffer, oldest first.
+// Every write is one record, so records never straddle an inversion, and
+// every tracker segment is a whole number of records.
+struct Records {
+    unread: VecDeque<Record>,
+    max: usize,
+    // next_seq is the sequence number of the next write. Every write
+    // attempt uses one up, even if it fails, so that the reader can tell
+    // that something is missing.
+    next_seq: u64,
+    // partial is how much of the oldest record has been released so far.
+    partial: usize,
+}
+struct Record {
+    seq: u64,
+    len: usize,
+    // stamp is when the record was written, if the buffer has a clock.
+    stamp: Option<Instant>,
+}
+impl Records {
+    fn is_full(&self) -> bool {
+        self.unread.len() == self.max
+    }
+
+    // consumed forgets the records making up the first `len` unread bytes.
+    // A record that's only partly consumed (by part of a split Lease) stays
+    // until the rest of it is.
+    fn consumed(&mut self, len: usize) {
+        self.partial += len;
+        while self.partial > 0 {
+            let r = self
+                .unread
+                .front()
+                .expect("released bytes must be recorded");
+            if self.partial < r.len {
+                break;
+            }
+            self.partial -= r.len;
+            self.unread.pop_front();
+        }
+    }
+
+    // unread_lens is how much of each record is unread, oldest first. Only
+    // the oldest can have been partly consumed (see BufRead for Reader).
+    fn unread_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        let partial = self.partial;
+        (self.unread.iter().enumerate()).map(move |(i, r)| match i {
+            0 => r.len - partial,
+            _ => r.len,
+        })
+    }
+
+    // whole_records is how many of the first `len` unread bytes make up
+    // whole records.
+    fn whole_records(&self, len: usize) -> usize {
+        let mut end = 0;
+        for n in self.unread_lens() {
+            if end + n > len {
+                break;
+            }
+            end += n;
+        }
+        end
+    }
+
+    // is_boundary is whether the first `len` unread bytes are a whole number
+    // of records.
+    fn is_boundary(&self, len: usize) -> bool {
+        let mut end = 0;
+        for n in self.unread_lens() {
+            if end >= len {
+                break;
+            }
+            end += n;
+        }
+        end == len
+    }
+}
+
+// Parts are the pieces of split Leases. They can be dropped in any order,
+// but the tracker can only release data oldest first, so a part dropped
+// early is parked until everything in front of it has been released.
+struct Parts {
+    // extra is how many more parts are alive than the Leases they came from.
+    extra: usize,
+    parked: Vec<Parked>,
+}
+struct Parked {
+    at: usize,
+    lease: ReadLease,
+    wrapped: Option<ReadLease>,
+}
+
+// History is the last `max` bytes that the reader released, oldest first.
+struct History {
+    bytes: VecDeque<u8>,
+    max: usize,
+}
+impl History {
+    fn push(&mut self, p: &[u8]) {
+        let p = &p[p.len().saturating_sub(self.max)..];
+        let excess = (self.bytes.len() + p.len()).saturating_sub(self.max);
+        self.bytes.drain(..excess);
+        self.bytes.extend(p);
+    }
+}
+
+// Counters are the running totals behind `Stats`. They're only ever updated
+// with relaxed atomic operations, so reading them never has to wait for the
+// tracker lock.
+struct Counters {
+    bytes_written: AtomicUsize,
+    bytes_read: AtomicUsize,
+    writes_rejected: AtomicUsize,
+    bytes_rejected: AtomicUsize,
+    bytes_overwritten: AtomicUsize,
+    inversions: AtomicUsize,
+    max_occupancy: AtomicUsize,
+    lease_count: AtomicUsize,
+}
+
+// Stats is a snapshot of a buffer's counters. Each field is read separately,
+// so unless it came from `stats_exact` the snapshot as a whole may not
+// correspond to any single moment: e.g. `bytes_read` may include a read that
+// happened after `bytes_written` was taken. The byte counters wrap around on
+// targets with 32-bit pointers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // bytes_written is the total size of every successful write.
+    pub bytes_written: usize,
+    // bytes_read is the total size of every released lease. Data discarded
+    // by `BipBuffer::clear` counts as read.
+    pub bytes_read: usize,
+    // writes_rejected is how many writes failed for lack of space.
+    pub writes_rejected: usize,
+    // bytes_rejected is the total size of those writes.
+    pub bytes_rejected: usize,
+    // bytes_overwritten is how many unread bytes were discarded to make room
+    // for new writes (see OverflowPolicy::Overwrite).
+    pub bytes_overwritten: usize,
+    // inversions is how many times a write wrapped around to the start of
+    // the buffer while there was still data to read at the end.
+    pub inversions: usize,
+    // max_occupancy is the most bytes that have ever been unread at once.
+    pub max_occupancy: usize,
+    // lease_count is how many leases the reader has been handed.
+    pub lease_count: usize,
+}
+impl Stats {
+    // occupancy is how many bytes were unread at the time of the snapshot.
+    pub fn occupancy(&self) -> usize {
+        self.bytes_written
+            .wrapping_sub(self.bytes_read)
+            .wrapping_sub(self.bytes_overwritten)
+    }
+}
+
+// FRAME_HEADER is the size of the little-endian u32 length that precedes
+// each frame (see Writer::write_framed).
+const FRAME_HEADER: usize = 4;
+
+// CHECKSUM is the size of the little-endian CRC32C that follows each record
+// when checksums are enabled.
+const CHECKSUM: usize = 4;
+
+// The tracker is authoritative.
+const FAST_OFF: usize = 0;
+// The buffer is idle, and the first writer to CAS this to FAST_CLAIMED may
+// write at offset 0 without taking the lock.
+const FAST_IDLE: usize = 1;
+// A writer is copying into the start of the buffer without the lock.
+const FAST_CLAIMED: usize = 2;
+// FAST_COMMITTED + n means that a lock-free writer has finished writing n
+// bytes at offset 0, which the tracker doesn't know about yet.
+const FAST_COMMITTED: usize = 3;
+
+// Locked is a lock on the tracker which has absorbed any lock-free write.
+// When it's released, it re-enables the fast path if the buffer is idle.
+struct Locked<'a> {
+    buffer: &'a Buffer,
+    guard: MutexGuard<'a, AnyTracker>,
+}
+impl Deref for Locked<'_> {
+    type Target = AnyTracker;
+    fn deref(&self) -> &AnyTracker {
+        &self.guard
+    }
+}
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut AnyTracker {
+        &mut self.guard
+    }
+}
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        // The fast path has nowhere to record a record's length, or when a
+        // write was made, and doesn't check for pausing.
+        let buffer = self.buffer;
+        if self.guard.is_idle()
+            && buffer.records.is_none()
+            && buffer.clock.is_none()
+            && !buffer.paused.load(Ordering::Relaxed)
+        {
+            buffer.fast.store(FAST_IDLE, Ordering::Release);
+        }
+    }
+}
+
+pub struct Reader(Arc<Buffer>);
+#[derive(Clone)]
+pub struct Writer {
+    buffer: Arc<Buffer>,
+    backoff: Backoff,
+    // scratch is where read_from reads to.
+    scratch: Vec<u8>,
+}
+
+// Backoff is what `Writer::write_retry` does while the buffer is full. Every
+// strategy re-checks for free space as soon as the reader releases a lease.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backoff {
+    // Give up immediately, like try_write.
+    #[default]
+    None,
+    // Busy-wait for up to `max_iters` spins.
+    Spin {
+        max_iters: u32,
+    },
+    // Busy-wait for up to `spins` spins, then yield the thread up to `yields`
+    // times.
+    SpinThenYield {
+        spins: u32,
+        yields: u32,
+    },
+    // Busy-wait for up to `spins` spins, then park the thread until the
+    // reader releases a lease, for up to `timeout` in total.
+    SpinThenPark {
+        spins: u32,
+        timeout: Duration,
+    },
+}
+
+pub fn try_create(capacity: usize) -> Result<(Reader, Writer), CreateError> {
+    Ok(BipBuffer::try_new(capacity)?.split())
+}
+
+// create is like try_create, but panics if the capacity is invalid.
+pub fn create(capacity: usize) -> (Reader, Writer) {
+    match try_create(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// try_create_with_control is like try_create, but also returns a Control for
+// the buffer.
+pub fn try_create_with_control(capacity: usize) -> Result<(Reader, Writer, Control), CreateError> {
+    let (reader, writer) = try_create(capacity)?;
+    let control = Control::new(writer.buffer.clone());
+    Ok((reader, writer, control))
+}
+
+// create_with_control is like try_create_with_control, but panics if the
+// capacity is invalid.
+pub fn create_with_control(capacity: usize) -> (Reader, Writer, Control) {
+    match try_create_with_control(capacity) {
+        Ok(triple) => triple,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// OverflowPolicy is what a write does when there's not enough free space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // Fail the write.
+    #[default]
+    Fail,
+    // Discard just enough of the oldest unread data to make room, e.g. for a
+    // flight recorder that only cares about the most recent data. The reader
+    // isn't told: it just never sees the discarded bytes. Writes still fail
+    // while the reader holds a Lease, since the leased data can't be
+    // discarded out from under it, and a write larger than the whole buffer
+    // still fails.
+    Overwrite,
+    // Make `Writer::write_retry` wait for as long as it takes, instead of
+    // giving up once its backoff strategy runs out. It still fails if the
+    // write is larger than the whole buffer, or once the Reader is dropped.
+    // `Writer::try_write` never blocks: it fails as with Fail.
+    Block,
+}
+
+// BufferOptions tweaks how a buffer's storage is set up.
+#[derive(Debug, Clone, Default)]
+pub struct BufferOptions {
+    // prefault touches every page at creation time, so that a real-time
+    // producer doesn't take a page fault on its first write into each page.
+    pub prefault: bool,
+    // mlock pins the storage in RAM (via mlock(2)) until the buffer is
+    // dropped. Creation fails if this is requested but not possible.
+    pub mlock: bool,
+    // backoff is the Writer's initial backoff strategy (see
+    // `Writer::set_backoff`).
+    pub backoff: Backoff,
+    // overflow is what writes do when the buffer is full.
+    pub overflow: OverflowPolicy,
+    // records enables record mode, remembering up to this many unread
+    // writes so that `Reader::read_record` can hand them back one at a time.
+    // Writes fail if this many records are already unread, even if there's
+    // space for their bytes, and empty writes always fail.
+    pub records: Option<usize>,
+    // frame_size makes the buffer deal only in whole frames of this many
+    // bytes, e.g. for PCM audio: writes whose length isn't a multiple of it
+    // fail, the capacity is rounded down to a multiple of it, and so every
+    // lease is a whole number of frames.
+    pub frame_size: Option<usize>,
+    // checksums appends a CRC32C to every record, which `read_record`
+    // verifies. It only applies in record mode, and the checksums are
+    // visible to `read`.
+    #[cfg(feature = "crc")]
+    pub checksums: bool,
+    // zeroize_on_release zeroes data as soon as it's been read (or
+    // discarded, e.g. by OverflowPolicy::Overwrite), and the whole buffer
+    // when it's dropped, so that sensitive data doesn't linger in memory.
+    // The zeroing can't be optimized away, and costs a pass over every byte
+    // read.
+    pub zeroize_on_release: bool,
+    // clock stamps every write with the time it was made, so that
+    // `Reader::lag_duration` can tell how far behind the reader is, and in
+    // record mode, `Reader::read_older_than` can leave recent records alone.
+    // Writes never take the lock-free path in a buffer with a clock.
+    pub clock: Option<std::sync::Arc<dyn Clock>>,
+    // history keeps a copy of the last this many bytes that were read, even
+    // once they've been released, so that `Reader::replay` can show recent
+    // traffic, e.g. from a panic handler. Every release copies (up to) this
+    // many bytes. It can't be combined with zeroize_on_release.
+    pub history: usize,
+}
+impl BufferOptions {
+    // frame_size sets `frame_size`, for chaining.
+    pub fn frame_size(mut self, n: usize) -> Self {
+        self.frame_size = Some(n);
+        self
+    }
+}
+
+pub fn create_with_options(
+    capacity: usize,
+    options: BufferOptions,
+) -> std::io::Result<(Reader, Writer)> {
+    let frame_size = options.frame_size.unwrap_or(1);
+    if frame_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "frame size must be non-zero",
+        ));
+    }
+    let mut data = Storage::alloc(capacity - capacity % frame_size, 1)?;
+    if options.prefault {
+        data.prefault();
+    }
+    if options.mlock {
+        data.mlock()?;
+    }
+    if options.zeroize_on_release {
+        if options.history > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "history can't be kept if data is zeroized",
+            ));
+        }
+        data.scrub_on_drop();
+    }
+    let mut buffer = Buffer::new(data);
+    if options.history > 0 {
+        buffer.history = Some(Mutex::new(History {
+            bytes: VecDeque::with_capacity(options.history),
+            max: options.history,
+        }));
+    }
+    buffer.overflow = options.overflow;
+    buffer.zeroize = options.zeroize_on_release;
+    buffer.frame_size = frame_size;
+    if let Some(max) = options.records {
+        if max == 0 {
+            return Err(CreateError::ZeroCapacity.into());
+        }
+        buffer.records = Some(Mutex::new(Records {
+            unread: VecDeque::with_capacity(max),
+            max,
+            next_seq: 0,
+            partial: 0,
+        }));
+    }
+    #[cfg(feature = "crc")]
+    {
+        buffer.checksums = options.checksums && buffer.records.is_some();
+    }
+    if options.clock.is_some() && buffer.records.is_none() {
+        buffer.stamps = Some(Mutex::new(VecDeque::new()));
+    }
+    buffer.clock = options.clock;
+    let (reader, mut writer) = BipBuffer(Arc::new(buffer)).split();
+    writer.set_backoff(options.backoff);
+    Ok((reader, writer))
+}
+
+// try_create_pow2 creates a buffer whose capacity is rounded up to a power of
+// two. Its offsets are tracked with masked counters, which is cheaper than the
+// default tracker's inversion logic at high message rates. Writes that don't
+// fit at the end of the buffer skip the remainder of it, so in the worst case
+// almost half of a write's size can be wasted at each wraparound.
+pub fn try_create_pow2(capacity: usize) -> Result<(Reader, Writer), CreateError> {
+    validate_capacity(capacity)?;
+    let capacity = capacity
+        .checked_next_power_of_two()
+        .ok_or(CreateError::CapacityTooLarge(capacity))?;
+    let data = Storage::alloc(capacity, 1)?;
+    let tracker = AnyTracker::Pow2(Pow2Tracker::new(capacity));
+    Ok(BipBuffer(Arc::new(Buffer::with_tracker(data, tracker))).split())
+}
+
+// create_pow2 is like try_create_pow2, but panics if the capacity is invalid.
+pub fn create_pow2(capacity: usize) -> (Reader, Writer) {
+    match try_create_pow2(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// try_create_from builds a buffer over caller-provided storage instead of
+// allocating. The capacity is the length of the storage, which can be
+// recovered later via `BipBuffer::into_inner`.
+pub fn try_create_from(storage: Box<[u8]>) -> Result<(Reader, Writer), CreateError> {
+    Ok(BipBuffer::try_from_storage(storage)?.split())
+}
+
+// create_from is like try_create_from, but panics if the storage is empty.
+pub fn create_from(storage: Box<[u8]>) -> (Reader, Writer) {
+    match try_create_from(storage) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// try_create_aligned allocates a buffer whose data region starts at a multiple
+// of `align`, e.g. 64 to keep it off of other allocations' cache lines, or 4096
+// for O_DIRECT I/O.
+pub fn try_create_aligned(capacity: usize, align: usize) -> Result<(Reader, Writer), CreateError> {
+    Ok(BipBuffer::with_storage(Storage::alloc(capacity, align)?).split())
+}
+
+// try_create_framed is try_create_aligned, but only accepting writes that
+// are a multiple of `frame_size` bytes, which `capacity` must be too.
+pub(crate) fn try_create_framed(
+    capacity: usize,
+    frame_size: usize,
+    align: usize,
+) -> Result<(Reader, Writer), CreateError> {
+    debug_assert!(capacity.is_multiple_of(frame_size));
+    let mut buffer = Buffer::new(Storage::alloc(capacity, align)?);
+    buffer.frame_size = frame_size;
+    Ok(BipBuffer(Arc::new(buffer)).split())
+}
+
+// create_aligned is like try_create_aligned, but panics on invalid arguments.
+pub fn create_aligned(capacity: usize, align: usize) -> (Reader, Writer) {
+    match try_create_aligned(capacity, align) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer layout: {err}"),
+    }
+}
+
+// create_mmap builds a buffer over a shared mapping of the file at `path`,
+// which is created or extended to `capacity` bytes. This lets very large
+// buffers be paged out by the OS instead of pinning RAM. Durability is
+// best-effort: nothing is ever fsync'd.
+#[cfg(feature = "mmap")]
+pub fn create_mmap(path: &std::path::Path, capacity: usize) -> std::io::Result<(Reader, Writer)> {
+    Ok(BipBuffer::with_storage(Storage::mmap(path, capacity)?).split())
+}
+
+// create_mirrored builds a buffer whose memory is mapped twice, back to back,
+// so that reads never need to be split at the end of the buffer: every lease
+// is a single contiguous slice. The capacity is rounded up to a whole number of
+// pages. Only linux is supported so far; elsewhere this returns an
+// `Unsupported` error.
+#[cfg(feature = "mmap")]
+pub fn create_mirrored(capacity: usize) -> std::io::Result<(Reader, Writer)> {
+    Ok(BipBuffer::with_storage(Storage::mirrored(capacity)?).split())
+}
+
+// try_create_in builds a buffer over a region that lives forever, such as a
+// linker-placed static or an mlocked/DMA-capable region handed out by a
+// driver. Unlike create_from, the region is never freed when the halves are
+// dropped.
+pub fn try_create_in(storage: &'static mut [u8]) -> Result<(Reader, Writer), CreateError> {
+    validate_capacity(storage.len())?;
+    Ok(BipBuffer::with_storage(Storage::borrowed(storage)).split())
+}
+
+// create_in is like try_create_in, but panics if the region is empty.
+pub fn create_in(storage: &'static mut [u8]) -> (Reader, Writer) {
+    match try_create_in(storage) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// create_from_vec is create_from for a Vec, using its `len()` (not its
+// allocated capacity) as the buffer capacity.
+pub fn create_from_vec(storage: Vec<u8>) -> (Reader, Writer) {
+    create_from(storage.into_boxed_slice())
+}
+
+impl Buffer {
+    fn new(data: Storage) -> Self {
+        let tracker = if data.is_mirrored() {
+            Tracker::new_mirrored(data.len())
+        } else {
+            Tracker::new(data.len())
+        };
+        Self::with_tracker(data, AnyTracker::Bip(tracker))
+    }
+
+    fn with_tracker(data: Storage, tracker: AnyTracker) -> Self {
+        Self {
+            tracker: Mutex::new(tracker),
+            fast: AtomicUsize::new(FAST_OFF),
+            releases: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            waiters: Mutex::new(Vec::new()),
+            counters: Counters {
+                bytes_written: AtomicUsize::new(0),
+                bytes_read: AtomicUsize::new(0),
+                writes_rejected: AtomicUsize::new(0),
+                bytes_rejected: AtomicUsize::new(0),
+                bytes_overwritten: AtomicUsize::new(0),
+                inversions: AtomicUsize::new(0),
+                max_occupancy: AtomicUsize::new(0),
+                lease_count: AtomicUsize::new(0),
+            },
+            leased: AtomicBool::new(false),
+            disconnected: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            compact: AtomicBool::new(false),
+            controls: AtomicUsize::new(0),
+            overflow: OverflowPolicy::Fail,
+            records: None,
+            frame_size: 1,
+            checksums: false,
+            zeroize: false,
+            clock: None,
+            stamps: None,
+            history: None,
+            txn: Mutex::new(None),
+            pinned: Mutex::new(None),
+            parts: Mutex::new(Parts {
+                extra: 0,
+                parked: Vec::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn lock(&self) -> Locked<'_> {
+        let mut guard = lock(&self.tracker);
+        loop {
+            match self.fast.load(Ordering::Acquire) {
+                FAST_OFF => break,
+                FAST_IDLE => {
+                    if self
+                        .fast
+                        .compare_exchange(FAST_IDLE, FAST_OFF, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        break;
+                    }
+                }
+                // A lock-free write is only ever a single copy, so it's
+                // worth waiting for rather than failing.
+                FAST_CLAIMED => spin_loop(),
+                committed => {
+                    let len = committed - FAST_COMMITTED;
+                    guard.commit(WriteLease { start: 0, len });
+                    self.fast.store(FAST_OFF, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+        Locked {
+            buffer: self,
+            guard,
+        }
+    }
+
+    // data is the buffer's storage. Callers must stop using it before
+    // `resize` could replace it: that is, they must be holding the tracker
+    // lock, a Lease, or a claim on the lock-free path.
+    fn data(&self) -> &Storage {
+        unsafe { &*self.data.get() }
+    }
+
+    fn try_write(&self, p: &[u8]) -> bool {
+        self.try_write_parts(&[p]).is_ok()
+    }
+
+    // try_write_parts writes the concatenation of `parts` as a single write,
+    // so that the reader either sees all of it or none of it.
+    fn try_write_parts(&self, parts: &[&[u8]]) -> Result<(), WriteError> {
+        let len = parts.iter().map(|p| p.len()).sum::<usize>();
+        if len.is_multiple_of(self.frame_size)
+            && self
+                .fast
+                .compare_exchange(
+                    FAST_IDLE,
+                    FAST_CLAIMED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            // We own the (empty) buffer until we publish our write, and it
+            // can't be resized until then either.
+            if len <= self.data().len() {
+                unsafe { self.write_parts(0, parts) };
+                self.wrote(len);
+                self.fast.store(FAST_COMMITTED + len, Ordering::Release);
+                return Ok(());
+            }
+            self.fast.store(FAST_IDLE, Ordering::Release);
+        }
+        let mut guard = self.lock();
+        if self.paused.load(Ordering::Relaxed) {
+            return Err(WriteError::Paused);
+        }
+        let mut records = self.records.as_ref().map(lock);
+        let seq = records.as_mut().map(|r| {
+            r.next_seq += 1;
+            r.next_seq - 1
+        });
+        let stamp = self.clock.as_ref().map(|c| c.now());
+        let was_inverted = guard.is_inverted();
+        let checksum = if self.checksums { CHECKSUM } else { 0 };
+        let Some(w) = self.reserve(&mut guard, records.as_deref_mut(), len + checksum) else {
+            self.rejected(len);
+            return Err(WriteError::Full);
+        };
+        unsafe { self.write_parts(w.start, parts) };
+        #[cfg(feature = "crc")]
+        if self.checksums {
+            let mut crc = crate::crc::Crc32c::new();
+            parts.iter().for_each(|p| crc.update(p));
+            unsafe {
+                self.data()
+                    .write(w.start + len, &crc.finish().to_le_bytes())
+            };
+        }
+        guard.commit(w);
+        if let (Some(records), Some(seq)) = (&mut records, seq) {
+            records.unread.push_back(Record {
+                seq,
+                len: len + checksum,
+                stamp,
+            });
+        }
+        if !was_inverted && guard.is_inverted() {
+            self.counters.inversions.fetch_add(1, Ordering::Relaxed);
+        }
+        self.wrote(len + checksum);
+        Ok(())
+    }
+
+    // Safety: the caller must hold a write lease covering all of `parts`,
+    // starting at `offset`.
+    unsafe fn write_parts(&self, mut offset: usize, parts: &[&[u8]]) {
+        for p in parts {
+            unsafe { self.data().write(offset, p) };
+            offset += p.len();
+        }
+    }
+
+    // reserve finds room for a write of `sz` bytes, or fails if there isn't
+    // any or the write isn't allowed. In record mode, there must also be room
+    // for another record, and nothing can be reserved while a Transaction
+    // holds a reservation or while writes are paused.
+    fn reserve(
+        &self,
+        tracker: &mut AnyTracker,
+        records: Option<&mut Records>,
+        sz: usize,
+    ) -> Option<WriteLease> {
+        if !sz.is_multiple_of(self.frame_size)
+            || (records.is_some() && sz == 0)
+            || lock(&self.txn).is_some()
+            || self.paused.load(Ordering::Relaxed)
+        {
+            return None;
+        }
+        if !records.as_ref().is_some_and(|r| r.is_full())
+            && let Some(w) = tracker.write(sz)
+        {
+            return Some(w);
+        }
+        self.overwrite(tracker, records, sz)
+    }
+
+    // overwrite discards the oldest unread data until a write of `sz` bytes
+    // fits, if the overflow policy allows it. It only ever discards whole
+    // frames, or in record mode, whole records.
+    fn overwrite(
+        &self,
+        tracker: &mut AnyTracker,
+        mut records: Option<&mut Records>,
+        sz: usize,
+    ) -> Option<WriteLease> {
+        if self.overflow != OverflowPolicy::Overwrite || self.leased.load(Ordering::Relaxed) {
+            return None;
+        }
+        // Check up front that discarding will actually work, so that we
+        // never throw away data for a write that still fails.
+        tracker.shortfall(sz)?;
+        loop {
+            if !records.as_ref().is_some_and(|r| r.is_full())
+                && let Some(w) = tracker.write(sz)
+            {
+                return Some(w);
+            }
+            // Unread data may be split into two regions, and discarding the
+            // first one may change what's needed from the second, so go one
+            // region at a time.
+            let need = tracker.shortfall(sz)?;
+            let r = tracker.read()?;
+            let len = match &mut records {
+                Some(records) => records.unread.pop_front()?.len,
+                None => need.next_multiple_of(self.frame_size).min(r.len),
+            };
+            self.scrub(r.start, len);
+            tracker.release(ReadLease {
+                start: r.start,
+                len,
+            });
+            self.counters
+                .bytes_overwritten
+                .fetch_add(len, Ordering::Relaxed);
+        }
+    }
+
+    // wrote updates the counters after a successful write.
+    fn wrote(&self, len: usize) {
+        let c = &self.counters;
+        let written = c
+            .bytes_written
+            .fetch_add(len, Ordering::Relaxed)
+            .wrapping_add(len);
+        let occupancy = written.wrapping_sub(c.bytes_read.load(Ordering::Relaxed));
+        c.max_occupancy.fetch_max(occupancy, Ordering::Relaxed);
+        if let Some(mut stamps) = self.stamps() {
+            let at = self.clock.as_ref().expect("stamps need a clock").now();
+            stamps.push_back(Stamp { end: written, at });
+        }
+    }
+
+    // stamps locks the stamps of the unread writes, first forgetting those
+    // whose bytes have all been read or overwritten. The caller must hold
+    // the tracker lock.
+    fn stamps(&self) -> Option<MutexGuard<'_, VecDeque<Stamp>>> {
+        let mut stamps = lock(self.stamps.as_ref()?);
+        let c = &self.counters;
+        let written = c.bytes_written.load(Ordering::Relaxed);
+        let unread = written
+            .wrapping_sub(c.bytes_read.load(Ordering::Relaxed))
+            .wrapping_sub(c.bytes_overwritten.load(Ordering::Relaxed));
+        while let Some(s) = stamps.front()
+            && written.wrapping_sub(s.end) >= unread
+        {
+            stamps.pop_front();
+        }
+        Some(stamps)
+    }
+
+    // available is the size of the largest write that would succeed right
+    // now without overwriting anything (see Writer::available).
+    fn available(&self) -> usize {
+        self.room(AnyTracker::available)
+    }
+
+    // room is what `space` says would fit in the tracker, less anything
+    // else that the next write would need, and rounded down to a whole
+    // number of frames.
+    fn room(&self, space: impl FnOnce(&AnyTracker) -> usize) -> usize {
+        let guard = self.lock();
+        if self.paused.load(Ordering::Relaxed)
+            || lock(&self.txn).is_some()
+            || self.records.as_ref().is_some_and(|r| lock(r).is_full())
+        {
+            return 0;
+        }
+        let checksum = if self.checksums { CHECKSUM } else { 0 };
+        let n = space(&guard).saturating_sub(checksum);
+        n - n % self.frame_size
+    }
+
+    // lag_duration is how long ago the oldest unread byte was written.
+    fn lag_duration(&self) -> Duration {
+        let clock = self
+            .clock
+            .as_ref()
+            .expect("lag_duration requires a buffer with a clock");
+        let _guard = self.lock();
+        let oldest = match &self.records {
+            Some(records) => lock(records)
+                .unread
+                .front()
+                .map(|r| r.stamp.expect("records are stamped")),
+            None => self.stamps().and_then(|s| s.front().map(|s| s.at)),
+        };
+        oldest.map_or(Duration::ZERO, |at| {
+            clock.now().saturating_duration_since(at)
+        })
+    }
+
+    // rejected updates the counters after a write of `len` bytes failed.
+    fn rejected(&self, len: usize) {
+        let c = &self.counters;
+        c.writes_rejected.fetch_add(1, Ordering::Relaxed);
+        c.bytes_rejected.fetch_add(len, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> Stats {
+        let c = &self.counters;
+        Stats {
+            bytes_written: c.bytes_written.load(Ordering::Relaxed),
+            bytes_read: c.bytes_read.load(Ordering::Relaxed),
+            writes_rejected: c.writes_rejected.load(Ordering::Relaxed),
+            bytes_rejected: c.bytes_rejected.load(Ordering::Relaxed),
+            bytes_overwritten: c.bytes_overwritten.load(Ordering::Relaxed),
+            inversions: c.inversions.load(Ordering::Relaxed),
+            max_occupancy: c.max_occupancy.load(Ordering::Relaxed),
+            lease_count: c.lease_count.load(Ordering::Relaxed),
+        }
+    }
+
+    // stats_exact is like stats, but holds the tracker lock so that nothing
+    // can change while the snapshot is taken.
+    fn stats_exact(&self) -> Stats {
+        let _guard = self.lock();
+        self.stats()
+    }
+
+    // retry_write keeps trying to write `p` according to `backoff`, or
+    // indefinitely if `block` is set.
+    fn retry_write(&self, p: &[u8], backoff: Backoff, block: bool) -> Result<(), WriteError> {
+        let (spins, yields, timeout) = match backoff {
+            Backoff::None => (0, 0, None),
+            Backoff::Spin { max_iters } => (max_iters, 0, None),
+            Backoff::SpinThenYield { spins, yields } => (spins, yields, None),
+            Backoff::SpinThenPark { spins, timeout } => (spins, 0, Some(timeout)),
+        };
+        let mut releases = self.releases.load(Ordering::Acquire);
+        // Pausing ends the retries, just like success does.
+        let write = || match self.try_write_parts(&[p]) {
+            Err(WriteError::Full) => None,
+            res => Some(res),
+        };
+        if let Some(res) = write() {
+            return res;
+        }
+        if block && p.len() > self.lock().capacity() {
+            return Err(WriteError::Full);
+        }
+        // Only retry once something has been released: until then, the
+        // buffer can't have any more room than it did.
+        let mut released = || {
+            let now = self.releases.load(Ordering::Acquire);
+            std::mem::replace(&mut releases, now) != now
+        };
+        for _ in 0..spins {
+            spin_loop();
+            if released()
+                && let Some(res) = write()
+            {
+                return res;
+            }
+        }
+        for _ in 0..yields {
+            std::thread::yield_now();
+            if released()
+                && let Some(res) = write()
+            {
+                return res;
+            }
+        }
+        if timeout.is_none() && !block {
+            return Err(WriteError::Full);
+        }
+        let deadline = timeout.filter(|_| !block).map(|t| Instant::now() + t);
+        let me = std::thread::current();
+        lock(&self.waiters).push(me.clone());
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        // Any release (or disconnect, or pause) after this try_write will see
+        // that we're waiting and unpark us: try_write took the tracker lock,
+        // which the release also has to take.
+        let mut result = Err(WriteError::Full);
+        loop {
+            if let Some(res) = write() {
+                result = res;
+                break;
+            }
+            if self.disconnected.load(Ordering::Relaxed) {
+                result = Err(WriteError::Disconnected);
+                break;
+            }
+            match deadline {
+                None => std::thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    std::thread::park_timeout(deadline - now);
+                }
+            }
+        }
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        let mut waiters = lock(&self.waiters);
+        if let Some(i) = waiters.iter().position(|t| t.id() == me.id()) {
+            waiters.swap_remove(i);
+        }
+        result
+    }
+
+    // release gives a Lease's data back to the writer. If the Lease
+    // continued past the wrap, `wrapped` is the part at the start of the
+    // buffer.
+    fn release(&self, lease: ReadLease, wrapped: Option<ReadLease>) {
+        {
+            let mut guard = self.lock();
+            self.release_locked(&mut guard, lease, wrapped);
+            self.leased.store(false, Ordering::Relaxed);
+            self.maybe_compact(&mut guard);
+        }
+        self.releases.fetch_add(1, Ordering::Release);
+        self.wake_waiters();
+    }
+
+    // release_part is release for a Lease that may have been split, whose
+    // first byte is at offset `at` of the stream. It's parked instead if
+    // something in front of it is still leased, and the buffer stays leased
+    // until every part is gone.
+    fn release_part(&self, at: usize, lease: ReadLease, wrapped: Option<ReadLease>) {
+        {
+            let mut guard = self.lock();
+            let mut parts = lock(&self.parts);
+            let last = parts.extra == 0;
+            parts.extra = parts.extra.saturating_sub(1);
+            if at != self.counters.bytes_read.load(Ordering::Relaxed) {
+                parts.parked.push(Parked { at, lease, wrapped });
+                return;
+            }
+            self.release_locked(&mut guard, lease, wrapped);
+            while let Some(i) = parts
+                .parked
+                .iter()
+                .position(|p| p.at == self.counters.bytes_read.load(Ordering::Relaxed))
+            {
+                let p = parts.parked.swap_remove(i);
+                self.release_locked(&mut guard, p.lease, p.wrapped);
+            }
+            if last {
+                self.leased.store(false, Ordering::Relaxed);
+                self.maybe_compact(&mut guard);
+            }
+        }
+        self.releases.fetch_add(1, Ordering::Release);
+        self.wake_waiters();
+    }
+
+    // release_locked does the work of release, but leaves the buffer leased.
+    fn release_locked(&self, guard: &mut AnyTracker, lease: ReadLease, wrapped: Option<ReadLease>) {
+        let len = lease.len + wrapped.as_ref().map_or(0, |r| r.len);
+        if let Some(history) = &self.history {
+            let mut history = lock(history);
+            for r in [Some(&lease), wrapped.as_ref()].into_iter().flatten() {
+                history.push(unsafe { self.data().slice(r.start, r.len) });
+            }
+        }
+        for r in [Some(lease), wrapped].into_iter().flatten() {
+            if r.len == 0 {
+                continue;
+            }
+            // This moves the reader past any padding, e.g. to the start of
+            // the buffer for the wrapped part.
+            let _ = guard.read();
+            self.scrub(r.start, r.len);
+            guard.release(r);
+        }
+        if let Some(records) = &self.records {
+            lock(records).consumed(len);
+        }
+        self.counters.bytes_read.fetch_add(len, Ordering::Relaxed);
+    }
+
+    // unlease ends a Lease without consuming any of it.
+    fn unlease(&self) {
+        let moved = {
+            let mut guard = self.lock();
+            self.leased.store(false, Ordering::Relaxed);
+            self.maybe_compact(&mut guard)
+        };
+        if moved > 0 {
+            self.releases.fetch_add(1, Ordering::Release);
+            self.wake_waiters();
+        }
+    }
+
+    // scrub zeroes unread data that's about to be given back to the writer,
+    // if the buffer zeroizes. The caller must hold the tracker lock, and
+    // nobody may be reading the data.
+    fn scrub(&self, start: usize, len: usize) {
+        if self.zeroize {
+            unsafe { self.data().scrub(start, len) };
+        }
+    }
+
+    // resize moves the unread data into new storage of `capacity` bytes
+    // (see Control::grow).
+    fn resize(&self, capacity: usize) -> Result<(), ResizeError> {
+        let mut guard = self.lock();
+        if self.leased.load(Ordering::Relaxed) {
+            return Err(ResizeError::Leased);
+        }
+        if lock(&self.txn).is_some() {
+            return Err(ResizeError::InTransaction);
+        }
+        let mut tracker = match &*guard {
+            AnyTracker::Bip(_) => {
+                AnyTracker::Bip(Tracker::new(capacity - capacity % self.frame_size))
+            }
+            AnyTracker::Pow2(_) => {
+                AnyTracker::Pow2(Pow2Tracker::new(capacity.max(1).next_power_of_two()))
+            }
+        };
+        let capacity = tracker.capacity();
+        let unread = guard.unread();
+        let occupancy = unread.iter().map(|r| r.len()).sum();
+        if occupancy > capacity {
+            return Err(ResizeError::TooSmall { occupancy });
+        }
+        let data = self
+            .data()
+            .resized(capacity)
+            .ok_or(ResizeError::Unsupported)??;
+        if let Some(w) = tracker.write(occupancy).filter(|w| w.len > 0) {
+            let mut offset = w.start;
+            for r in unread {
+                unsafe { data.write(offset, self.data().slice(r.start, r.len())) };
+                offset += r.len();
+            }
+            tracker.commit(w);
+        }
+        *guard = tracker;
+        // Nothing else can be using the old storage: see `data`.
+        unsafe { *self.data.get() = data };
+        self.compact.store(false, Ordering::Relaxed);
+        drop(guard);
+        self.releases.fetch_add(1, Ordering::Release);
+        self.wake_waiters();
+        Ok(())
+    }
+
+    // set_paused pauses or resumes writes (see Control::pause).
+    fn set_paused(&self, paused: bool) {
+        {
+            let _guard = self.lock();
+            self.paused.store(paused, Ordering::Relaxed);
+        }
+        // Blocked writers give up when paused, and may have room once
+        // resumed.
+        self.wake_waiters();
+    }
+
+    // maybe_compact moves the unread data to the start of the buffer, if
+    // that's been asked for and nothing is using its current position: the
+    // reader has no Lease, and there's no Transaction reservation. It
+    // returns how many bytes it moved. The caller must hold the tracker
+    // lock.
+    fn maybe_compact(&self, tracker: &mut AnyTracker) -> usize {
+        if !self.compact.load(Ordering::Relaxed)
+            || self.leased.load(Ordering::Relaxed)
+            || lock(&self.txn).is_some()
+        {
+            return 0;
+        }
+        self.compact.store(false, Ordering::Relaxed);
+        let [head, tail] = tracker.unread();
+        if head.start == 0 {
+            // The tail can only be non-empty if the head doesn't start at 0.
+            return 0;
+        }
+        // Everything in 0..head.end is either the tail, which has to end up
+        // right after the head, or free. Rotating it puts the head first.
+        let moved = head.len() + tail.len();
+        unsafe { self.data().slice_mut(0, head.end) }.rotate_left(head.start);
+        tracker.clear();
+        let w = tracker
+            .write(moved)
+            .expect("unread data must fit in an empty buffer");
+        tracker.commit(w);
+        moved
+    }
+
+    fn wake_waiters(&self) {
+        if self.waiting.load(Ordering::Relaxed) > 0 {
+            for waiter in lock(&self.waiters).iter() {
+                waiter.unpark();
+            }
+        }
+    }
+
+    // disconnect marks the Reader as gone, so that blocked writers give up.
+    fn disconnect(&self) {
+        {
+            let _guard = self.lock();
+            self.disconnected.store(true, Ordering::Relaxed);
+        }
+        self.wake_waiters();
+    }
+
+    // Callers must ensure that at most one Lease exists at a time, which is
+    // why only `&mut Reader` and `&mut BipBuffer` can get here.
+    fn read(&self) -> Option<Lease<'_>> {
+        self.read_as(|r| {
+            let view = r.start..r.start + r.len;
+            Some((r, view))
+        })
+    }
+
+    // read_record is like read, but only leases the oldest record, along
+    // with its sequence number. If the record's checksum doesn't match, it's
+    // discarded instead.
+    fn read_record(&self) -> Option<Result<(u64, Lease<'_>), RecordError>> {
+        self.read_record_if(|_| true)
+    }
+
+    // read_older_than is like read_record, but only if the oldest record was
+    // written more than `age` ago.
+    fn read_older_than(&self, age: Duration) -> Option<Result<(u64, Lease<'_>), RecordError>> {
+        let clock = self
+            .clock
+            .as_ref()
+            .expect("read_older_than requires a buffer with a clock");
+        let now = clock.now();
+        self.read_record_if(|r| {
+            let stamp = r.stamp.expect("records are stamped");
+            now.saturating_duration_since(stamp) > age
+        })
+    }
+
+    // read_record_if is read_record, but leaves the oldest record unread
+    // unless it passes `ready`.
+    fn read_record_if(
+        &self,
+        ready: impl FnOnce(&Record) -> bool,
+    ) -> Option<Result<(u64, Lease<'_>), RecordError>> {
+        let records = self.records();
+        let mut seq = 0;
+        let mut corrupt = None;
+        let lease = self.read_as(|r| {
+            let records = lock(records);
+            let record = &records.unread[0];
+            if !ready(record) {
+                return None;
+            }
+            seq = record.seq;
+            let len = record.len;
+            let r = ReadLease {
+                start: r.start,
+                len,
+            };
+            let checksum = if self.checksums { CHECKSUM } else { 0 };
+            let view = r.start..r.start + len - checksum;
+            if self.checksums && !self.checksum_ok(view.clone()) {
+                corrupt = Some(r);
+                return None;
+            }
+            Some((r, view))
+        });
+        if let Some(r) = corrupt {
+            self.release(r, None);
+            return Some(Err(RecordError::Corrupt { seq }));
+        }
+        Some(Ok((seq, lease?)))
+    }
+
+    // checksum_ok is whether the CRC32C right after `view` matches it. The
+    // caller must hold the tracker lock, with `view` unread.
+    #[cfg(feature = "crc")]
+    fn checksum_ok(&self, view: Range<usize>) -> bool {
+        let (p, sum) = unsafe {
+            (
+                self.data().slice(view.start, view.len()),
+                self.data().slice(view.end, CHECKSUM),
+            )
+        };
+        let mut crc = crate::crc::Crc32c::new();
+        crc.update(p);
+        crc.finish().to_le_bytes() == sum
+    }
+
+    // Without the crc feature, checksums are never enabled.
+    #[cfg(not(feature = "crc"))]
+    fn checksum_ok(&self, _view: Range<usize>) -> bool {
+        true
+    }
+
+    fn next_seq(&self) -> u64 {
+        let _guard = self.lock();
+        lock(self.records()).next_seq
+    }
+
+    fn records(&self) -> &Mutex<Records> {
+        self.records
+            .as_ref()
+            .expect("only buffers in record mode have records")
+    }
+
+    // read_contiguous is like read, but only leases if at least `min` bytes
+    // are contiguous. Otherwise the error says whether that's because too
+    // little is unread, or because what's unread wraps around the end.
+    fn read_contiguous(&self, min: usize) -> Result<Lease<'_>, ContiguousError> {
+        let mut err = None;
+        let lease = self.read_as(|r| {
+            if r.len >= min {
+                let view = r.start..r.start + r.len;
+                return Some((r, view));
+            }
+            // We hold the lock, so the counters are consistent.
+            let unread = self.stats().occupancy();
+            err = Some(if unread >= min {
+                ContiguousError::Fragmented {
+                    contiguous: r.len,
+                    unread,
+                }
+            } else {
+                ContiguousError::Short { unread }
+            });
+            None
+        });
+        lease.ok_or(err.unwrap_or(ContiguousError::Short { unread: 0 }))
+    }
+
+    fn read_exact(&self, n: usize) -> Option<Lease<'_>> {
+        assert!(
+            self.records.is_none(),
+            "try_read_exact doesn't support record mode"
+        );
+        self.read_as(|r| {
+            if r.len < n {
+                return None;
+            }
+            let view = r.start..r.start + n;
+            Some((ReadLease { len: n, ..r }, view))
+        })
+    }
+
+    fn read_with<R>(&self, f: impl FnOnce(&[u8]) -> (usize, R)) -> Option<R> {
+        let mut lease = self.read()?;
+        // If `f` panics, the Lease is dropped as it unwinds, and this makes
+        // sure that doesn't consume anything.
+        let len = std::mem::replace(&mut lease.consume, 0);
+        let (n, out) = f(lease.view);
+        assert!(n <= len, "read_with consumed {n} of {len} bytes");
+        if let Some(records) = &self.records {
+            assert!(
+                lock(records).is_boundary(n),
+                "read_with must consume whole records"
+            );
+        }
+        lease.consume = n;
+        Some(out)
+    }
+
+    // read_framed is like read, but only leases the oldest frame (see
+    // Writer::write_framed), or nothing if that frame is incomplete.
+    fn read_framed(&self) -> Option<Lease<'_>> {
+        self.read_as(|r| {
+            if r.len < FRAME_HEADER {
+                return None;
+            }
+            // We hold the lock, so nobody else can be touching this.
+            let header = unsafe { self.data().slice(r.start, FRAME_HEADER) };
+            let len = FRAME_HEADER + u32::from_le_bytes(header.try_into().unwrap()) as usize;
+            (len <= r.len).then_some((
+                ReadLease {
+                    start: r.start,
+                    len,
+                },
+                r.start + FRAME_HEADER..r.start + len,
+            ))
+        })
+    }
+
+    // debug_snapshot dumps up to `max_bytes` of the unread data, oldest
+    // first. It only holds the lock long enough to copy the data out.
+    #[cfg(feature = "debug-utils")]
+    fn debug_snapshot(&self, max_bytes: usize) -> String {
+        let (unread, segments) = {
+            let guard = self.lock();
+            let (mut unread, mut copied) = (0, 0);
+            let mut segments = Vec::new();
+            for segment in self.visible(&guard) {
+                unread += segment.len();
+                let n = segment.len().min(max_bytes - copied);
+                segments.push(unsafe { self.data().slice(segment.start, n) }.to_vec());
+                copied += n;
+            }
+            (unread, segments)
+        };
+        let mut out = format!("{unread} unread bytes\n");
+        let mut offset = 0;
+        for (i, p) in segments.iter().enumerate() {
+            if i > 0 && !p.is_empty() {
+                out.push_str("-------- wraps to the start of the buffer --------\n");
+            }
+            crate::hexdump::hexdump(&mut out, offset, p);
+            offset += p.len();
+        }
+        if offset < unread {
+            out.push_str(&format!("({} more bytes)\n", unread - offset));
+        }
+        out
+    }
+
+    // visible is the tracker's unread segments, oldest first, minus a
+    // Transaction's reservation. The caller must hold the tracker lock.
+    fn visible(&self, tracker: &AnyTracker) -> [Range<usize>; 2] {
+        let mut segments = tracker.unread();
+        if let Some(res) = &*lock(&self.txn) {
+            // The reservation is always the newest data, and in a mirrored
+            // buffer, it may show up in the mirror.
+            let starts = [res.start, res.start + self.data().len()];
+            for segment in &mut segments {
+                if let Some(&start) = starts.iter().find(|s| segment.contains(s)) {
+                    segment.end = start;
+                    break;
+                }
+            }
+        }
+        segments
+    }
+
+    // peek_after copies up to `n` of the unread bytes that follow a Lease
+    // ending at `end`, which may be on the other side of the wrap.
+    fn peek_after(&self, end: usize, n: usize) -> Vec<u8> {
+        let guard = self.lock();
+        let [head, tail] = self.visible(&guard);
+        (end..head.end.max(end))
+            .chain(tail)
+            .take(n)
+            .map(|i| unsafe { self.data().slice(i, 1) }[0])
+            .collect()
+    }
+
+    fn read_to_string(&self, out: &mut String) -> Result<usize, Utf8Error> {
+        assert!(
+            self.records.is_none(),
+            "read_to_string doesn't support record mode"
+        );
+        let mut total = 0;
+        loop {
+            let Some(mut lease) = self.read() else {
+                return Ok(total);
+            };
+            let (valid, err) = match std::str::from_utf8(lease.view) {
+                Ok(s) => (s.len(), None),
+                Err(err) => (err.valid_up_to(), Some(err)),
+            };
+            out.push_str(std::str::from_utf8(&lease.view[..valid]).unwrap());
+            total += valid;
+            lease.consume = valid;
+            match err {
+                None => {}
+                Some(err) if err.error_len().is_some() => {
+                    return Err(Utf8Error { offset: total });
+                }
+                // Go around again for the incomplete character on its own.
+                Some(_) if valid > 0 => {}
+                Some(_) => {
+                    // The Lease is just the start of a character, which may
+                    // continue after the wrap.
+                    let start = lease.view.to_vec();
+                    let end = lease.lease.as_ref().map(|r| r.start + r.len).unwrap();
+                    let mut p = start.clone();
+                    p.extend(self.peek_after(end, 4 - start.len()));
+                    let incomplete = |n: usize| {
+                        std::str::from_utf8(&p[..n]).is_err_and(|err| err.error_len().is_none())
+                    };
+                    let Some(len) = (start.len() + 1..=p.len()).find(|&n| !incomplete(n)) else {
+                        // It hasn't all been written yet.
+                        return Ok(total);
+                    };
+                    let Ok(c) = std::str::from_utf8(&p[..len]) else {
+                        return Err(Utf8Error { offset: total });
+                    };
+                    out.push_str(c);
+                    total += len;
+                    lease.consume = start.len();
+                    drop(lease);
+                    self.read_with(|_| (len - start.len(), ()));
+                }
+            }
+        }
+    }
+
+    // unhidden trims a readable segment to exclude a Transaction's
+    // reservation. The caller must hold the tracker lock.
+    fn unhidden(&self, r: ReadLease) -> Option<ReadLease> {
+        let Some(res) = &*lock(&self.txn) else {
+            return Some(r);
+        };
+        // In a mirrored buffer, the reservation may show up in the mirror.
+        for start in [res.start, res.start + self.data().len()] {
+            if (r.start..r.start + r.len).contains(&start) {
+                return (start > r.start).then_some(ReadLease {
+                    start: r.start,
+                    len: start - r.start,
+                });
+            }
+        }
+        Some(r)
+    }
+
+    // txn_append adds `p` to a Transaction that has appended `len` bytes so
+    // far, and holds a reservation if `reserved`. If `p` doesn't fit in the
+    // reservation, it moves everything to a bigger one.
+    fn txn_append(&self, reserved: bool, len: usize, p: &[u8]) -> bool {
+        let mut guard = self.lock();
+        let checksum = if self.checksums { CHECKSUM } else { 0 };
+        let need = len + p.len() + checksum;
+        let old = if reserved {
+            lock(&self.txn).take()
+        } else {
+            None
+        };
+        let res = match old {
+            Some(res) if need <= res.len => res,
+            _ if need == 0 => return true,
+            old => {
+                let saved = old
+                    .as_ref()
+                    .map(|res| unsafe { self.data().slice(res.start, len) }.to_vec());
+                if let Some(res) = &old {
+                    guard.truncate(res.lease(), 0);
+                }
+                let mut records = self.records.as_ref().map(lock);
+                let was_inverted = guard.is_inverted();
+                let size = |n: usize| n.next_multiple_of(self.frame_size);
+                // Double the reservation when growing it, so that appending
+                // many pieces doesn't take quadratic time.
+                let grown = old.as_ref().map_or(0, |res| 2 * res.len);
+                let w = self
+                    .reserve(&mut guard, records.as_deref_mut(), size(need.max(grown)))
+                    .or_else(|| self.reserve(&mut guard, records.as_deref_mut(), size(need)));
+                let Some(w) = w else {
+                    // Put back what we had: that space can only have gotten
+                    // freer since we gave it back.
+                    if let Some(old) = old {
+                        let w = self
+                            .reserve(&mut guard, records.as_deref_mut(), old.len)
+                            .expect("space that was just given back must still be free");
+                        unsafe { self.data().write(w.start, saved.as_deref().unwrap()) };
+                        let res = Reservation {
+                            start: w.start,
+                            len: w.len,
+                            inverted: old.inverted,
+                        };
+                        guard.commit(w);
+                        *lock(&self.txn) = Some(res);
+                    }
+                    return false;
+                };
+                if let Some(saved) = &saved {
+                    unsafe { self.data().write(w.start, saved) };
+                }
+                let res = Reservation {
+                    start: w.start,
+                    len: w.len,
+                    inverted: false,
+                };
+                guard.commit(w);
+                Reservation {
+                    inverted: !was_inverted && guard.is_inverted(),
+                    ..res
+                }
+            }
+        };
+        unsafe { self.data().write(res.start + len, p) };
+        *lock(&self.txn) = Some(res);
+        true
+    }
+
+    // txn_commit makes a Transaction's `len` bytes visible to the reader, and
+    // gives back the rest of its reservation.
+    fn txn_commit(&self, reserved: bool, len: usize) -> bool {
+        let ok = {
+            let mut guard = self.lock();
+            let res = if reserved {
+                lock(&self.txn).take()
+            } else {
+                None
+            };
+            let mut records = self.records.as_ref().map(lock);
+            let seq = records.as_mut().map(|r| {
+                r.next_seq += 1;
+                r.next_seq - 1
+            });
+            match res {
+                _ if !len.is_multiple_of(self.frame_size) || (records.is_some() && len == 0) => {
+                    if let Some(res) = res {
+                        guard.truncate(res.lease(), 0);
+                    }
+                    self.rejected(len);
+                    false
+                }
+                // Nothing was appended.
+                None => true,
+                Some(res) => {
+                    let checksum = if self.checksums { CHECKSUM } else { 0 };
+                    #[cfg(feature = "crc")]
+                    if self.checksums {
+                        let mut crc = crate::crc::Crc32c::new();
+                        crc.update(unsafe { self.data().slice(res.start, len) });
+                        unsafe {
+                            self.data()
+                                .write(res.start + len, &crc.finish().to_le_bytes())
+                        };
+                    }
+                    guard.truncate(res.lease(), len + checksum);
+                    if let (Some(records), Some(seq)) = (&mut records, seq) {
+                        records.unread.push_back(Record {
+                            seq,
+                            len: len + checksum,
+                            stamp: self.clock.as_ref().map(|c| c.now()),
+                        });
+                    }
+                    if res.inverted && guard.is_inverted() {
+                        self.counters.inversions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    self.wrote(len + checksum);
+                    true
+                }
+            }
+        };
+        self.txn_finished();
+        ok
+    }
+
+    // txn_abort gives back a Transaction's reservation, if it has one.
+    fn txn_abort(&self, reserved: bool) {
+        if !reserved {
+            return;
+        }
+        {
+            let mut guard = self.lock();
+            if let Some(res) = lock(&self.txn).take() {
+                guard.truncate(res.lease(), 0);
+            }
+        }
+        self.txn_finished();
+    }
+
+    // txn_finished lets writers that failed during a Transaction try again.
+    fn txn_finished(&self) {
+        if self.compact.load(Ordering::Relaxed) {
+            self.maybe_compact(&mut self.lock());
+        }
+        self.releases.fetch_add(1, Ordering::Release);
+        self.wake_waiters();
+    }
+
+    // claim marks whatever `trim` picks out of the next readable segment as
+    // leased, and returns it.
+    fn claim(
+        &self,
+        trim: impl FnOnce(ReadLease) -> Option<(ReadLease, Range<usize>)>,
+    ) -> Option<(ReadLease, Range<usize>)> {
+        let mut guard = self.lock();
+        let r = trim(self.unhidden(guard.read()?)?)?;
+        self.leased.store(true, Ordering::Relaxed);
+        self.counters.lease_count.fetch_add(1, Ordering::Relaxed);
+        Some(r)
+    }
+
+    // fill_buf pins the next readable segment until `consume`, and returns
+    // it.
+    fn fill_buf(&self) -> &[u8] {
+        let mut pinned = lock(&self.pinned);
+        if pinned.is_none() {
+            *pinned = self
+                .claim(|r| {
+                    let view = r.start..r.start + r.len;
+                    Some((r, view))
+                })
+                .map(|(r, _)| r);
+        }
+        match &*pinned {
+            Some(r) => unsafe { self.data().slice(r.start, r.len) },
+            None => &[],
+        }
+    }
+
+    // readable is how many of the first `len` bytes fill_buf pinned can be
+    // consumed at once: all of them, or in record mode, as many as make up
+    // whole records.
+    fn readable(&self, len: usize) -> usize {
+        match &self.records {
+            Some(records) => lock(records).whole_records(len),
+            None => len,
+        }
+    }
+
+    // consume releases the first `amt` bytes of what fill_buf pinned.
+    fn consume(&self, amt: usize) {
+        if amt == 0 {
+            return;
+        }
+        let mut pinned = lock(&self.pinned);
+        let len = pinned.as_ref().expect("consume must follow fill_buf").len;
+        assert!(amt <= len, "consumed {amt} of {len} bytes");
+        // In record mode, this can stop partway through a record, e.g. at a
+        // newline for read_line. The rest of it comes next.
+        let mut r = pinned.take().unwrap();
+        drop(pinned);
+        r.len = amt;
+        self.release(r, None);
+    }
+
+    // read_until leases the oldest unread data up to and including the
+    // first `delim`, even if it wraps.
+    fn read_until(&self, delim: u8) -> Option<Lease<'_>> {
+        assert!(
+            self.records.is_none(),
+            "read_until doesn't support record mode"
+        );
+        self.read_split(|[head, tail]| {
+            let find = |r: &Range<usize>| {
+                let p = unsafe { self.data().slice(r.start, r.len()) };
+                p.iter().position(|&b| b == delim).map(|i| i + 1)
+            };
+            Some(match find(&head) {
+                Some(n) => (head.start..head.start + n, None),
+                None => (head, Some(tail.start..tail.start + find(&tail)?)),
+            })
+        })
+    }
+
+    // read_wrapped leases all of the unread data, on both sides of the wrap.
+    fn read_wrapped(&self) -> Option<Lease<'_>> {
+        if self.records.is_some() {
+            return self.read();
+        }
+        self.read_split(|[head, tail]| Some((head, (!tail.is_empty()).then_some(tail))))
+    }
+
+    // read_split leases whatever `pick` picks out of the visible unread data:
+    // a range before the wrap, and maybe one after it.
+    fn read_split(
+        &self,
+        pick: impl FnOnce([Range<usize>; 2]) -> Option<(Range<usize>, Option<Range<usize>>)>,
+    ) -> Option<Lease<'_>> {
+        self.unpin();
+        let (r, wrapped) = {
+            let mut guard = self.lock();
+            // This moves the reader past any padding.
+            guard.read()?;
+            let (r, wrapped) = pick(self.visible(&guard))?;
+            self.leased.store(true, Ordering::Relaxed);
+            self.counters.lease_count.fetch_add(1, Ordering::Relaxed);
+            let lease = |r: Range<usize>| ReadLease {
+                start: r.start,
+                len: r.len(),
+            };
+            (lease(r), wrapped.map(lease))
+        };
+        let slice = |r: &ReadLease| unsafe { self.data().slice(r.start, r.len) };
+        Some(Lease {
+            buffer: self,
+            at: self.counters.bytes_read.load(Ordering::Relaxed),
+            consume: r.len,
+            view: slice(&r),
+            wrapped_view: wrapped.as_ref().map_or(&[], slice),
+            lease: Some(r),
+            wrapped,
+        })
+    }
+
+    // read_array reads exactly N bytes, copying them out even if they wrap.
+    fn read_array<const N: usize>(&self) -> Option<[u8; N]> {
+        assert!(
+            self.records.is_none(),
+            "fixed-size reads don't support record mode"
+        );
+        self.unpin();
+        let mut out = [0; N];
+        let (r, wrapped) = {
+            let mut guard = self.lock();
+            // This moves the reader past any padding.
+            guard.read()?;
+            let [head, tail] = self.visible(&guard);
+            if head.len() + tail.len() < N {
+                return None;
+            }
+            let n = head.len().min(N);
+            unsafe {
+                out[..n].copy_from_slice(self.data().slice(head.start, n));
+                out[n..].copy_from_slice(self.data().slice(tail.start, N - n));
+            }
+            // Hold on to the bytes until they're released.
+            self.leased.store(true, Ordering::Relaxed);
+            let r = ReadLease {
+                start: head.start,
+                len: n,
+            };
+            let wrapped = (n < N).then_some(ReadLease {
+                start: tail.start,
+                len: N - n,
+            });
+            (r, wrapped)
+        };
+        self.release(r, wrapped);
+        Some(out)
+    }
+
+    // unpin gives back whatever fill_buf pinned, if anything.
+    fn unpin(&self) {
+        if lock(&self.pinned).take().is_some() {
+            self.unlease();
+        }
+    }
+
+    // read_as leases whatever `trim` picks out of the next readable segment,
+    // if anything, only showing the reader the part of it in the returned
+    // range.
+    fn read_as(
+        &self,
+        trim: impl FnOnce(ReadLease) -> Option<(ReadLease, Range<usize>)>,
+    ) -> Option<Lease<'_>> {
+        // Leases replace whatever fill_buf pinned.
+        self.unpin();
+        let (r, view) = self.claim(trim)?;
+        let view = unsafe { self.data().slice(view.start, view.len()) };
+        Some(Lease {
+            buffer: self,
+            at: self.counters.bytes_read.load(Ordering::Relaxed),
+            consume: r.len,
+            lease: Some(r),
+            wrapped: None,
+            view,
+            wrapped_view: &[],
+        })
+    }
+}
+
+impl Writer {
+    pub fn try_write(&mut self, p: &[u8]) -> bool {
+        self.buffer.try_write(p)
+    }
+
+    // push writes a single byte.
+    pub fn push(&mut self, byte: u8) -> bool {
+        self.buffer.try_write(&[byte])
+    }
+
+    // try_write_many writes the concatenation of `parts` as a single write
+    // (and in record mode, a single record), so that the reader sees all of
+    // the parts together, or none of them. If the parts are all empty, it
+    // writes nothing at all.
+    pub fn try_write_many(&mut self, parts: &[&[u8]]) -> Result<(), WriteError> {
+        if parts.iter().all(|p| p.is_empty()) {
+            return Ok(());
+        }
+        self.buffer.try_write_parts(parts)
+    }
+
+    // write_framed is like try_write, but prefixes `p` with its length as a
+    // little-endian u32, so that `Reader::read_framed` can pick it back out
+    // of the byte stream. The prefix and payload are a single write. It fails
+    // if `p` is longer than u32::MAX bytes.
+    pub fn write_framed(&mut self, p: &[u8]) -> bool {
+        let Ok(len) = u32::try_from(p.len()) else {
+            return false;
+        };
+        self.buffer
+            .try_write_parts(&[&len.to_le_bytes(), p])
+            .is_ok()
+    }
+
+    // write_retry is like try_write, but if the buffer is full it keeps
+    // retrying according to this Writer's backoff strategy before giving up
+    // (or indefinitely, under OverflowPolicy::Block).
+    pub fn write_retry(&mut self, p: &[u8]) -> Result<(), WriteError> {
+        let block = self.buffer.overflow == OverflowPolicy::Block;
+        self.buffer.retry_write(p, self.backoff, block)
+    }
+
+    // write_partial writes as much of `p` as fits right now, and returns how
+    // much that was, so that the caller can try the rest later. If there's
+    // room both before the end of the buffer and at its start, it fills the
+    // end first and then carries on at the start, so that's two writes (and
+    // in record mode, two records).
+    pub fn write_partial(&mut self, p: &[u8]) -> usize {
+        let mut done = 0;
+        while done < p.len() {
+            let room = self.buffer.room(|t| match t.contiguous() {
+                0 => t.available(),
+                n => n,
+            });
+            let n = room.min(p.len() - done);
+            if n == 0 || !self.try_write(&p[done..done + n]) {
+                break;
+            }
+            done += n;
+        }
+        done
+    }
+
+    // write_blocking is like write_retry under OverflowPolicy::Block,
+    // whatever the buffer's policy: it waits as long as it takes for room,
+    // so nothing is lost. It fails straight away if `p` is bigger than the
+    // buffer, and with Disconnected if it would have to wait for a Reader
+    // that's gone.
+    pub fn write_blocking(&mut self, p: &[u8]) -> Result<(), WriteError> {
+        self.buffer.retry_write(p, self.backoff, true)
+    }
+
+    // write_timeout is like write_retry, but parks until there's room for
+    // up to `timeout`, whatever this Writer's backoff strategy. It fails
+    // with Full once the time's up.
+    pub fn write_timeout(&mut self, p: &[u8], timeout: Duration) -> Result<(), WriteError> {
+        let backoff = Backoff::SpinThenPark { spins: 0, timeout };
+        self.buffer.retry_write(p, backoff, false)
+    }
+
+    // begin starts a Transaction: a write assembled from several pieces.
+    pub fn begin(&mut self) -> Transaction<'_> {
+        Transaction {
+            buffer: &self.buffer,
+            len: 0,
+            reserved: false,
+        }
+    }
+
+    // read_from reads up to `max` bytes from `src`, as many as would fit in
+    // the buffer right now (see available), and writes them as a single
+    // write. It returns how many it read. Nothing is written if `src` is at
+    // EOF (i.e. this returns Ok(0)) or fails. If there's no room at all,
+    // this fails with WouldBlock. `src` is read into a scratch space that's
+    // kept for the next call, not into the buffer itself, so that a slow
+    // read doesn't hold up other Writers. If one of them takes the space in
+    // the meantime, what was read is lost, and this fails with WouldBlock
+    // too. It doesn't support buffers with a frame size.
+    pub fn read_from(
+        &mut self,
+        src: &mut impl std::io::Read,
+        max: usize,
+    ) -> std::io::Result<usize> {
+        if self.buffer.frame_size > 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "read_from doesn't support frame sizes",
+            ));
+        }
+        let len = max.min(self.available());
+        if len == 0 {
+            return match max {
+                0 => Ok(0),
+                _ => Err(WriteError::Full.into()),
+            };
+        }
+        self.scratch.resize(len, 0);
+        let n = loop {
+            match src.read(&mut self.scratch) {
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                res => break res?,
+            }
+        };
+        if n > 0 && !self.buffer.try_write(&self.scratch[..n]) {
+            return Err(WriteError::Full.into());
+        }
+        Ok(n)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.lock().capacity()
+    }
+
+    // available is the size of the largest write that would succeed right
+    // now, without waiting or overwriting anything. Once the buffer has
+    // inverted, that's only the space in front of the unread data, not all
+    // of the free space. Other Writers may use it up before this one does.
+    pub fn available(&self) -> usize {
+        self.buffer.available()
+    }
+
+    // next_seq is the sequence number that the next write will get (see
+    // Reader::read_record). Clones of this Writer share the same sequence.
+    // It panics unless the buffer was created in record mode.
+    pub fn next_seq(&self) -> u64 {
+        self.buffer.next_seq()
+    }
+
+    // set_backoff changes how write_retry waits for free space. Clones of
+    // this Writer made afterwards inherit it; existing clones are unaffected.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    // stats is Reader::stats, from the other side.
+    pub fn stats(&self) -> Stats {
+        self.buffer.stats()
+    }
+
+    pub fn stats_exact(&self) -> Stats {
+        self.buffer.stats_exact()
+    }
+}
+// Reader is also a std::io::BufRead, reading the buffer in place. The slice
+// from fill_buf stays leased until it's consumed, or until some other read.
+// In record mode, consume can stop partway through a record, but read only
+// copies whole records (or the rest of a partly consumed one), and fails
+// with InvalidInput if `buf` is too small for the oldest one. That record
+// stays unread.
+impl std::io::Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let p = self.0.fill_buf();
+        let n = self.0.readable(p.len().min(buf.len()));
+        if n == 0 && !p.is_empty() && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "buffer is too small for the next record",
+            ));
+        }
+        buf[..n].copy_from_slice(&p[..n]);
+        self.0.consume(n);
+        Ok(n)
+    }
+}
+impl std::io::BufRead for Reader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(self.0.fill_buf())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt);
+    }
+}
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.0.disconnect();
+    }
+}
+impl Reader {
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        self.0.read()
+    }
+
+    // read_record leases exactly one record: the oldest unread write, along
+    // with its sequence number. Sequence numbers go up by one for every
+    // write, so a jump means that writes were rejected or overwritten in
+    // between. If checksums are enabled and the record's doesn't match, the
+    // record is skipped and this returns a Corrupt error instead, so the next
+    // call moves on to the next record. It panics unless the buffer was
+    // created in record mode (see BufferOptions::records). `read` still works
+    // in record mode, and may return several records at once.
+    pub fn read_record(&mut self) -> Option<Result<(u64, Lease<'_>), RecordError>> {
+        self.0.read_record()
+    }
+
+    // read_older_than is like read_record, but returns None instead if the
+    // oldest record was written `age` ago or less, leaving it (and every
+    // record after it) unread. It panics unless the buffer was created with
+    // a clock (see BufferOptions::clock).
+    pub fn read_older_than(
+        &mut self,
+        age: Duration,
+    ) -> Option<Result<(u64, Lease<'_>), RecordError>> {
+        self.0.read_older_than(age)
+    }
+
+    // lag_bytes is how many bytes are waiting to be read.
+    pub fn lag_bytes(&self) -> usize {
+        self.0.stats_exact().occupancy()
+    }
+
+    // len is lag_bytes, including data on both sides of the wrap.
+    pub fn len(&self) -> usize {
+        self.lag_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // lag_duration is how long ago the oldest unread byte was written, or
+    // zero if there's nothing to read. It panics unless the buffer was
+    // created with a clock (see BufferOptions::clock).
+    pub fn lag_duration(&self) -> Duration {
+        self.0.lag_duration()
+    }
+
+    // read_contiguous is like read, but only returns a Lease if it's at
+    // least `min` bytes long. Once the buffer has inverted, the oldest data
+    // runs up to the point where the writer wrapped around, and the data
+    // after the wrap isn't contiguous with it, so this can fail with
+    // Fragmented even though `min` bytes are unread. Reading what's there (or
+    // compacting the buffer with Control::compact) fixes that. A mirrored
+    // buffer is never fragmented.
+    pub fn read_contiguous(&mut self, min: usize) -> Result<Lease<'_>, ContiguousError> {
+        self.0.read_contiguous(min)
+    }
+
+    // try_read_exact leases exactly the next `n` bytes, if they're readable
+    // and contiguous, and otherwise returns None without reading anything.
+    // It never copies: if the `n` bytes are split by the wrap, this returns
+    // None until what's before the wrap has been read some other way (or the
+    // buffer is compacted; see Control::compact). A mirrored buffer is never
+    // split. It panics if the buffer is in record mode.
+    pub fn try_read_exact(&mut self, n: usize) -> Option<Lease<'_>> {
+        self.0.read_exact(n)
+    }
+
+    // read_with passes what `read` would lease to `f`, which returns how
+    // many of those bytes to consume along with its result. That way no Lease
+    // has to outlive the call. If `f` panics, nothing is consumed. In record
+    // mode, `f` must consume a whole number of records.
+    pub fn read_with<R>(&mut self, f: impl FnOnce(&[u8]) -> (usize, R)) -> Option<R> {
+        self.0.read_with(f)
+    }
+
+    // write_to moves everything currently readable into `dst`, and returns
+    // how many bytes that was. It only consumes what `dst` accepts, so
+    // partial writes are fine, and if `dst` fails, whatever it didn't take
+    // stays unread. Interrupted writes are retried. In record mode, `dst`
+    // must accept whole records (see read_with).
+    pub fn write_to(&mut self, dst: &mut impl std::io::Write) -> std::io::Result<u64> {
+        let mut total = 0;
+        loop {
+            let res = self.read_with(|p| match dst.write(p) {
+                Ok(n) => (n, Ok(n)),
+                Err(err) => (0, Err(err)),
+            });
+            match res {
+                None => return Ok(total),
+                Some(Ok(0)) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Some(Ok(n)) => total += n as u64,
+                Some(Err(err)) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Some(Err(err)) => return Err(err),
+            }
+        }
+    }
+
+    // read_to_end reads everything that's currently readable, in the order
+    // it was written. It doesn't wait for more.
+    pub fn read_to_end(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.read_to_end_into(&mut out);
+        out
+    }
+
+    // read_to_end_into is like read_to_end, but appends to `out`, and returns
+    // how many bytes it appended.
+    pub fn read_to_end_into(&mut self, out: &mut Vec<u8>) -> usize {
+        let before = out.len();
+        while let Some(lease) = self.read() {
+            out.extend_from_slice(lease.view);
+        }
+        out.len() - before
+    }
+
+    // read_to_string appends everything that's currently readable to `out`,
+    // and returns how many bytes that was. A character that hasn't been
+    // completely written yet is left unread for the next call, even if it's
+    // split by the wrap. If the data isn't valid UTF-8, this stops in front
+    // of the invalid bytes and fails, leaving them unread. It panics if the
+    // buffer is in record mode.
+    pub fn read_to_string(&mut self, out: &mut String) -> Result<usize, Utf8Error> {
+        self.0.read_to_string(out)
+    }
+
+    // read_until leases the oldest unread data up to and including the first
+    // `delim`, or returns None if there isn't one yet. If that data wraps
+    // around the end of the buffer, `view` is the part before the wrap and
+    // `wrapped_view` is the rest; otherwise `wrapped_view` is empty. It panics
+    // if the buffer is in record mode.
+    pub fn read_until(&mut self, delim: u8) -> Option<Lease<'_>> {
+        self.0.read_until(delim)
+    }
+
+    // read_wrapped is like read, but if the unread data wraps around the end
+    // of the buffer, it leases all of it: `view` is the part before the wrap
+    // and `wrapped_view` is the rest. In record mode, it's just read.
+    pub fn read_wrapped(&mut self) -> Option<Lease<'_>> {
+        self.0.read_wrapped()
+    }
+
+    // drain_iter leases everything that's currently readable, one Lease at a
+    // time, oldest first (see Drain).
+    pub fn drain_iter(&mut self) -> Drain<'_> {
+        let left = self.lag_bytes();
+        Drain { reader: self, left }
+    }
+
+    // replay copies up to `max` of the most recently read bytes, oldest
+    // first, out of the buffer's history (see BufferOptions::history). It's
+    // empty if the buffer doesn't keep one. Data that's still leased isn't
+    // part of the history yet.
+    pub fn replay(&mut self, max: usize) -> Vec<u8> {
+        let Some(history) = &self.0.history else {
+            return Vec::new();
+        };
+        let history = lock(history);
+        let skip = history.bytes.len().saturating_sub(max);
+        history.bytes.iter().skip(skip).copied().collect()
+    }
+
+    // read_array consumes exactly N bytes, or returns None if fewer than
+    // that are readable (see codec).
+    pub(crate) fn read_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.0.read_array()
+    }
+
+    // read_framed leases exactly one frame written by
+    // `Writer::write_framed`, without its length prefix. It returns None if
+    // the oldest unread data isn't a complete frame yet, e.g. if the writer
+    // is assembling one out of several try_writes, and leaves it unread.
+    pub fn read_framed(&mut self) -> Option<Lease<'_>> {
+        self.0.read_framed()
+    }
+
+    // debug_snapshot is a hexdump of up to `max_bytes` of the unread data,
+    // in the order it'll be read, marking where it wraps around the end of
+    // the buffer. It doesn't read anything, so it's safe to call at any time,
+    // e.g. to log what's in the buffer when the data doesn't parse.
+    #[cfg(feature = "debug-utils")]
+    pub fn debug_snapshot(&self, max_bytes: usize) -> String {
+        self.0.debug_snapshot(max_bytes)
+    }
+
+    // stats reads the buffer's counters without taking the lock, so it never
+    // slows down the writer. See Stats for what that means for consistency.
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    // stats_exact is like stats, but takes the lock to get a consistent
+    // snapshot.
+    pub fn stats_exact(&self) -> Stats {
+        self.0.stats_exact()
+    }
+}
+
+// Control is an administrative handle on a buffer, for code that's neither
+// reading nor writing it: e.g. to stop new writes and let the reader drain
+// the buffer before a reconfiguration. Nothing it does affects data that's
+// already been written, or a Lease that's already been handed out.
+pub struct Control {
+    buffer: Arc<Buffer>,
+    shrink_policy: Option<ShrinkPolicy>,
+    // idle_since is when `maybe_shrink` first saw the buffer idle, if it
+    // has been ever since.
+    idle_since: Option<Instant>,
+}
+
+// ShrinkPolicy is when `Control::maybe_shrink` shrinks the buffer: once its
+// occupancy has stayed below `below_percent` of its capacity for `after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShrinkPolicy {
+    pub below_percent: usize,
+    pub after: Duration,
+    // capacity is what the buffer is shrunk to.
+    pub capacity: usize,
+}
+
+impl Control {
+    fn new(buffer: Arc<Buffer>) -> Self {
+        buffer.controls.fetch_add(1, Ordering::Relaxed);
+        Self {
+            buffer,
+            shrink_policy: None,
+            idle_since: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.lock().capacity()
+    }
+
+    // pause makes every write fail until `resume`, with WriteError::Paused
+    // where the write reports why (e.g. `Writer::write_retry`, which also
+    // stops waiting for room). Writes already underway when it's called
+    // finish normally, but none start once it returns.
+    pub fn pause(&self) {
+        self.buffer.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.buffer.set_paused(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.buffer.paused.load(Ordering::Relaxed)
+    }
+
+    // compact moves all of the unread data to the start of the buffer, so
+    // that it can be read in one Lease and the writer has all of the
+    // remaining space in one piece, and returns how many bytes it moved. If
+    // the reader holds a Lease, or a Transaction is open, that happens once
+    // they're gone instead, and this returns 0.
+    pub fn compact(&self) -> usize {
+        self.buffer.compact.store(true, Ordering::Relaxed);
+        self.buffer.maybe_compact(&mut self.buffer.lock())
+    }
+
+    // grow moves the buffer into a new allocation of `capacity` bytes (which
+    // despite the name may also be smaller), with all of the unread data at
+    // the start in the order it'll be read. The capacity is adjusted the same
+    // way as when the buffer was created, e.g. rounded up to a power of two
+    // for create_pow2. It fails if the reader holds a Lease or a Transaction
+    // is open, if the unread data wouldn't fit, or if the buffer isn't on the
+    // heap (e.g. create_mmap), since there's no way to get more of that.
+    pub fn grow(&self, capacity: usize) -> Result<(), ResizeError> {
+        self.buffer.resize(capacity)
+    }
+
+    // shrink_to is like grow, but does nothing unless `capacity` is smaller
+    // than the current capacity.
+    pub fn shrink_to(&self, capacity: usize) -> Result<(), ResizeError> {
+        if capacity >= self.capacity() {
+            return Ok(());
+        }
+        self.buffer.resize(capacity)
+    }
+
+    // shrink_to_fit shrinks the buffer as far as the unread data allows.
+    pub fn shrink_to_fit(&self) -> Result<(), ResizeError> {
+        self.shrink_to(self.buffer.stats_exact().occupancy().max(1))
+    }
+
+    // set_shrink_policy sets (or with None, clears) the policy that
+    // `maybe_shrink` follows.
+    pub fn set_shrink_policy(&mut self, policy: Option<ShrinkPolicy>) {
+        self.shrink_policy = policy;
+        self.idle_since = None;
+    }
+
+    // maybe_shrink shrinks the buffer if the shrink policy says it's been
+    // idle for long enough, and reports whether it did. It's meant to be
+    // called periodically, e.g. from a timer: the buffer only counts as
+    // having been idle since the first call that found it idle, and stops
+    // counting as soon as a call finds it busy. Time comes from the buffer's
+    // clock, if it has one (see BufferOptions::clock).
+    pub fn maybe_shrink(&mut self) -> Result<bool, ResizeError> {
+        let Some(policy) = self.shrink_policy else {
+            return Ok(false);
+        };
+        let capacity = self.capacity();
+        let occupancy = self.buffer.stats_exact().occupancy();
+        if capacity <= policy.capacity || occupancy * 100 >= policy.below_percent * capacity {
+            self.idle_since = None;
+            return Ok(false);
+        }
+        let now = match &self.buffer.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        };
+        let since = *self.idle_since.get_or_insert(now);
+        if now.saturating_duration_since(since) < policy.after {
+            return Ok(false);
+        }
+        self.shrink_to(policy.capacity)?;
+        self.idle_since = None;
+        Ok(true)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.buffer.stats()
+    }
+
+    pub fn stats_exact(&self) -> Stats {
+        self.buffer.stats_exact()
+    }
+}
+impl Drop for Control {
+    fn drop(&mut self) {
+        self.buffer.controls.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Transaction is a write assembled from several appends, which the reader
+// sees all at once when it's committed, or not at all if the Transaction is
+// dropped first. From the first append until then, it holds space in the
+// buffer, and every other write to the buffer fails.
+pub struct Transaction<'a> {
+    buffer: &'a Buffer,
+    len: usize,
+    reserved: bool,
+}
+impl Transaction<'_> {
+    // append adds `p` to the end of the write. If there's no room for it,
+    // it fails, but the Transaction is still usable. Appending nothing always
+    // succeeds.
+    pub fn append(&mut self, p: &[u8]) -> bool {
+        if !self.buffer.txn_append(self.reserved, self.len, p) {
+            return false;
+        }
+        self.len += p.len();
+        self.reserved |= self.len > 0 || self.buffer.checksums;
+        true
+    }
+
+    // commit publishes everything that's been appended, as a single write
+    // (and in record mode, a single record). It fails if the write isn't
+    // allowed, e.g. if it isn't a multiple of the frame size.
+    pub fn commit(mut self) -> bool {
+        let reserved = std::mem::take(&mut self.reserved);
+        self.buffer.txn_commit(reserved, self.len)
+    }
+}
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.buffer.txn_abort(self.reserved);
+    }
+}
+
+pub struct Lease<'a> {
+    buffer: &'a Buffer,
+    // at is the offset of the Lease's first byte in the stream of everything
+    // ever read, i.e. bytes_read when it was leased.
+    at: usize,
+    lease: Option<ReadLease>,
+    // consume is how much of the lease to release on Drop: all of it, unless
+    // it came from read_with.
+    consume: usize,
+    // wrapped is where the data continues at the start of the buffer, if it
+    // does (see Reader::read_until).
+    wrapped: Option<ReadLease>,
+    pub view: &'a [u8],
+    pub wrapped_view: &'a [u8],
+}
+impl<'a> Lease<'a> {
+    // len is the total length of the Lease, including any wrapped part.
+    pub fn len(&self) -> usize {
+        self.view.len() + self.wrapped_view.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // chunks splits the Lease into pieces of at most `size` bytes, in order.
+    // A piece never spans the wrap: the last one before it may be short, and
+    // `wrapped_view` starts a new one. It panics if `size` is zero.
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = &'a [u8]> + use<'a> {
+        assert!(size > 0, "chunk size must be non-zero");
+        self.view.chunks(size).chain(self.wrapped_view.chunks(size))
+    }
+
+    // split_at divides the Lease into the first `mid` bytes and the rest,
+    // which can then be dropped in either order: each part's data is given
+    // back to the writer only once everything in front of it has been. It
+    // panics if `mid` is greater than the length, like slice::split_at.
+    pub fn split_at(mut self, mid: usize) -> (Lease<'a>, Lease<'a>) {
+        assert!(
+            mid <= self.len(),
+            "mid > len: {mid} > {len}",
+            len = self.len()
+        );
+        let buffer = self.buffer;
+        let at = self.at;
+        let mut lease = self.lease.take().expect("lease must persist until Drop");
+        lease.len = self.consume;
+        let wrapped = self.wrapped.take();
+        let (view, wrapped_view) = (self.view, self.wrapped_view);
+        std::mem::forget(self);
+        let part = |at, lease: ReadLease, wrapped, view, wrapped_view| Lease {
+            buffer,
+            at,
+            consume: lease.len,
+            lease: Some(lease),
+            wrapped,
+            view,
+            wrapped_view,
+        };
+        let (first, second) = if mid <= view.len() {
+            // The view may not start at the start of the lease (e.g. after a
+            // frame header), so cut it where the view does.
+            let base = unsafe { buffer.data().slice(lease.start, 0) }.as_ptr();
+            let cut = view.as_ptr().addr() - base.addr() + mid;
+            let (head, tail) = view.split_at(mid);
+            (
+                part(at, ReadLease { len: cut, ..lease }, None, head, &[]),
+                part(
+                    at + cut,
+                    ReadLease {
+                        start: lease.start + cut,
+                        len: lease.len - cut,
+                    },
+                    wrapped,
+                    tail,
+                    wrapped_view,
+                ),
+            )
+        } else {
+            let wrapped = wrapped.expect("a lease longer than its view must wrap");
+            let cut = mid - view.len();
+            let len = lease.len;
+            let (head, tail) = wrapped_view.split_at(cut);
+            (
+                part(
+                    at,
+                    lease,
+                    Some(ReadLease {
+                        len: cut,
+                        ..wrapped
+                    }),
+                    view,
+                    head,
+                ),
+                part(
+                    at + len + cut,
+                    ReadLease {
+                        start: wrapped.start + cut,
+                        len: wrapped.len - cut,
+                    },
+                    None,
+                    tail,
+                    &[],
+                ),
+            )
+        };
+        {
+            let _guard = buffer.lock();
+            lock(&buffer.parts).extra += 1;
+        }
+        (first, second)
+    }
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut lease = self.lease.take().expect("lease must persist until Drop");
+        // Only read_with leases nothing: an empty part of a split Lease
+        // still has to be released in its turn.
+        if self.consume == 0 && lease.len > 0 {
+            self.buffer.unlease();
+        } else {
+            lease.len = self.consume;
+            self.buffer
+                .release_part(self.at, lease, self.wrapped.take());
+        }
+    }
+}
+
+// Drain leases what was readable when it was created, one segment at a
+// time. Each Lease borrows the Drain, so it has to be dropped (releasing it)
+// before the next one can be leased, which is why this isn't an Iterator:
+//
+//     let mut drain = reader.drain_iter();
+//     while let Some(lease) = drain.next_lease() {
+//         socket.write_all(lease.view)?;
+//     }
+//
+// It stops as soon as the buffer is empty, and never goes on for much longer
+// than it takes to read what was there at the start, even if the writer
+// keeps writing.
+pub struct Drain<'a> {
+    reader: &'a mut Reader,
+    // left is how much of the data that was readable at the start hasn't
+    // been leased yet.
+    left: usize,
+}
+impl Drain<'_> {
+    pub fn next_lease(&mut self) -> Option<Lease<'_>> {
+        if self.left == 0 {
+            return None;
+        }
+        let lease = self.reader.read()?;
+        self.left = self.left.saturating_sub(lease.len());
+        Some(lease)
+    }
+}
+
+// BipBuffer is the unsplit owner of a buffer. It can be used directly from a
+// single thread, and later split into a Reader/Writer pair (and reassembled
+// with `unsplit`) without copying any data.
+pub struct BipBuffer(Arc<Buffer>);
+
+#[derive(Debug)]
+pub struct UnsplitError {
+    pub reader: Reader,
+    pub writer: Writer,
+}
+impl fmt::Display for UnsplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "reader and writer are not the sole halves of one buffer")
+    }
+}
+impl std::error::Error for UnsplitError {}
+
+impl BipBuffer {
+    pub fn try_new(capacity: usize) -> Result<Self, CreateError> {
+        Ok(Self::with_storage(Storage::alloc(capacity, 1)?))
+    }
+
+    pub fn try_from_storage(storage: Box<[u8]>) -> Result<Self, CreateError> {
+        validate_capacity(storage.len())?;
+        Ok(Self::with_storage(Storage::boxed(storage)))
+    }
+
+    fn with_storage(data: Storage) -> Self {
+        Self(Arc::new(Buffer::new(data)))
+    }
+
+    // new is like try_new, but panics if the capacity is invalid.
+    pub fn new(capacity: usize) -> Self {
+        match Self::try_new(capacity) {
+            Ok(buf) => buf,
+            Err(err) => panic!("invalid buffer capacity: {err}"),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.lock().capacity()
+    }
+
+    // clear discards all unread data.
+    pub fn clear(&mut self) {
+        {
+            let mut guard = self.0.lock();
+            for r in guard.unread() {
+                self.0.scrub(r.start, r.len());
+            }
+            guard.clear();
+        }
+        if let Some(records) = &self.0.records {
+            lock(records).unread.clear();
+        }
+        let c = &self.0.counters;
+        let written = c.bytes_written.load(Ordering::Relaxed);
+        let overwritten = c.bytes_overwritten.load(Ordering::Relaxed);
+        c.bytes_read
+            .store(written.wrapping_sub(overwritten), Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    pub fn write(&mut self, p: &[u8]) -> bool {
+        self.0.try_write(p)
+    }
+
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        self.0.read()
+    }
+
+    // into_inner gives back the underlying storage. Any unread data is still
+    // in there somewhere, but its position is unspecified. Storage that wasn't
+    // provided as a Box (e.g. from create_aligned) is copied into one.
+    pub fn into_inner(self) -> Box<[u8]> {
+        let buffer = Arc::into_inner(self.0).expect("BipBuffer is the sole owner");
+        buffer.data.into_inner().into_boxed()
+    }
+
+    pub fn split(self) -> (Reader, Writer) {
+        let writer = Writer {
+            buffer: self.0.clone(),
+            backoff: Backoff::None,
+            scratch: Vec::new(),
+        };
+        (Reader(self.0), writer)
+    }
+
+    // unsplit reassembles a BipBuffer from its halves. It fails (handing the
+    // halves back) if they came from different buffers, or if any other
+    // clones of the writer are still alive.
+    pub fn unsplit(reader: Reader, writer: Writer) -> Result<Self, UnsplitError> {
+        if !Arc::ptr_eq(&reader.0, &writer.buffer)
+            || Arc::strong_count(&reader.0) != 2 + writer.buffer.controls.load(Ordering::Relaxed)
+        {
+            return Err(UnsplitError { reader, writer });
+        }
+        drop(reader);
+        // There are no other writers to be blocked, and the next split makes
+        // a new Reader.
+        writer.buffer.disconnected.store(false, Ordering::Relaxed);
+        Ok(Self(writer.buffer))
+    }
+}
+
+// The halves' Debug output describes the state of the buffer, but never its
+// contents. It never waits for the tracker lock, so that it can't deadlock
+// whatever state the buffer is in.
+impl Buffer {
+    fn describe(&self, d: &mut fmt::DebugStruct<'_, '_>) {
+        let unread = self.stats().occupancy();
+        match try_lock(&self.tracker) {
+            Some(tracker) => {
+                let capacity = tracker.capacity();
+                d.field("capacity", &capacity)
+                    .field("unread", &unread)
+                    .field("free", &capacity.saturating_sub(unread))
+                    .field("inverted", &tracker.is_inverted())
+            }
+            None => d
+                .field("capacity", &format_args!("<locked>"))
+                .field("unread", &unread)
+                .field("inverted", &format_args!("<locked>")),
+        };
+    }
+}
+impl fmt::Debug for Reader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Reader");
+        self.0.describe(&mut d);
+        // Every other reference to the buffer belongs to a Writer.
+        let controls = self.0.controls.load(Ordering::Relaxed);
+        d.field("writers", &(Arc::strong_count(&self.0) - 1 - controls))
+            .finish()
+    }
+}
+impl fmt::Debug for Writer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Writer");
+        self.buffer.describe(&mut d);
+        d.field(
+            "reader_alive",
+            &!self.buffer.disconnected.load(Ordering::Relaxed),
+        )
+        .finish()
+    }
+}
+impl fmt::Debug for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Control");
+        self.buffer.describe(&mut d);
+        d.field("paused", &self.is_paused()).finish()
+    }
+}
+impl fmt::Debug for BipBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BipBuffer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(reader.read().is_none());
+
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+
+        {
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, b"asdfpqrs")
+        }
+
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn write_during_read_lease() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(writer.try_write(b"asdf"));
+
+        // The reader can see the first write, and is allowed
+        // to hold the lease even while writers continue to append.
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"asdf");
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(l.view, b"asdf");
+
+        // Subsequent reads are needed to pick up the concurrent writes.
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"pqrs");
+    }
+
+    #[test]
+    fn write_wraparound() {
+        let (mut reader, mut writer) = create(10);
+
+        // We're to: write 5, read 5, write 4, write 4.
+        // The last write should wrap around.
+        // We should be able to confirm because `read()` will need to return twice.
+
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"aaaaa");
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"bbbb");
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"cccc");
+        drop(l);
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn writes_that_exactly_fit() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(&[b'a'; 10]));
+        assert!(!writer.try_write(b"a"));
+        assert_eq!(reader.read_to_end(), [b'a'; 10]);
+
+        // Invert into a head region exactly the size of the write.
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbbb"));
+        drop(l);
+        assert!(writer.try_write(b"ccccc"));
+        assert!(!writer.try_write(b"d"));
+        assert_eq!(reader.read().unwrap().view, b"bbbbb");
+        assert_eq!(reader.read().unwrap().view, b"ccccc");
+    }
+
+    #[test]
+    fn push_single_bytes() {
+        let (mut reader, mut writer) = create(2);
+        assert!(writer.push(1));
+        assert!(writer.push(2));
+        assert!(!writer.push(3));
+        assert_eq!(reader.read_to_end(), [1, 2]);
+
+        // Appending nothing works even when the buffer is full.
+        assert!(writer.try_write(&[1, 2]));
+        let mut txn = writer.begin();
+        assert!(txn.append(&[]));
+        assert!(!txn.append(&[3]));
+        assert!(txn.commit());
+        assert_eq!(reader.read_to_end(), [1, 2]);
+    }
+
+    #[test]
+    fn try_read_exact_never_spans_the_wrap() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbb"));
+        drop(l);
+        assert!(writer.try_write(b"ccccc"));
+        // 8 bytes are unread, but split 3/5 by the wrap.
+        assert!(reader.try_read_exact(8).is_none());
+        assert!(reader.try_read_exact(4).is_none());
+        assert_eq!(reader.try_read_exact(2).unwrap().view, b"bb");
+        assert_eq!(reader.try_read_exact(1).unwrap().view, b"b");
+        assert!(reader.try_read_exact(6).is_none());
+        assert_eq!(reader.try_read_exact(5).unwrap().view, b"ccccc");
+        assert!(reader.try_read_exact(1).is_none());
+    }
+
+    #[test]
+    fn occupancy_accessors() {
+        let (mut reader, mut writer) = create(10);
+        assert_eq!((writer.capacity(), writer.available()), (10, 10));
+        assert!(reader.is_empty());
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        // Only the gap in front of the unread data is available now.
+        for _ in 0..2 {
+            assert_eq!((reader.len(), writer.available()), (8, 1));
+            assert!(!reader.is_empty());
+            assert!(!writer.try_write(b"dd"));
+        }
+        assert_eq!(reader.read_to_end(), b"bbbbcccc");
+        assert_eq!((reader.len(), writer.available()), (0, 10));
+    }
+
+    #[test]
+    fn write_blocking_loses_nothing() {
+        let (mut reader, mut writer) = create(8);
+        let want: Vec<u8> = (0..1024).map(|i| (i * 7) as u8).collect();
+        let got = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let (mut rest, mut n) = (&want[..], 1);
+                while !rest.is_empty() {
+                    let (p, tail) = rest.split_at(n.min(rest.len()));
+                    writer.write_blocking(p).unwrap();
+                    (rest, n) = (tail, n % 8 + 1);
+                }
+            });
+            let mut got = Vec::new();
+            while got.len() < want.len() {
+                reader.read_to_end_into(&mut got);
+            }
+            got
+        });
+        assert_eq!(got, want);
+        assert_eq!(writer.write_blocking(&[0; 9]), Err(WriteError::Full));
+        drop(reader);
+        // Only waiting fails once the reader is gone.
+        assert!(writer.write_blocking(&[0; 8]).is_ok());
+        assert_eq!(writer.write_blocking(b"x"), Err(WriteError::Disconnected));
+    }
+
+    #[test]
+    fn write_partial_fills_the_end_then_the_start() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbb"));
+        drop(l);
+        // 2 bytes fit before the end and 5 at the start.
+        assert_eq!(writer.write_partial(b"0123456789"), 7);
+        assert_eq!(writer.write_partial(b"x"), 0);
+        assert_eq!(reader.read().unwrap().view, b"bbb01");
+        // Reading what was before the wrap makes room after the rest.
+        assert_eq!(writer.write_partial(b"789abcdef"), 5);
+        assert_eq!(reader.read_to_end(), b"23456789ab");
+    }
+
+    #[test]
+    fn try_create_rejects_invalid_capacity() {
+        assert_eq!(try_create(0).err(), Some(CreateError::ZeroCapacity));
+        let too_large = isize::MAX as usize + 1;
+        assert_eq!(
+            try_create(too_large).err(),
+            Some(CreateError::CapacityTooLarge(too_large))
+        );
+        assert!(try_create(1).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid buffer capacity: capacity must be non-zero")]
+    fn create_panics_on_zero_capacity() {
+        create(0);
+    }
+
+    #[test]
+    fn bip_buffer_single_threaded() {
+        let mut buf = BipBuffer::new(10);
+        assert_eq!(buf.capacity(), 10);
+
+        assert!(buf.write(b"asdf"));
+        assert_eq!(buf.read().unwrap().view, b"asdf");
+        assert!(buf.read().is_none());
+
+        assert!(buf.write(b"pqrs"));
+        buf.clear();
+        assert!(buf.read().is_none());
+        assert!(buf.write(b"0123456789"));
+    }
+
+    #[test]
+    fn split_and_unsplit_keep_data() {
+        let mut buf = BipBuffer::new(10);
+        assert!(buf.write(b"asdf"));
+
+        let (mut reader, mut writer) = buf.split();
+        assert!(writer.try_write(b"pqrs"));
+
+        let mut buf = BipBuffer::unsplit(reader, writer).unwrap();
+        assert_eq!(buf.read().unwrap().view, b"asdfpqrs");
+
+        (reader, writer) = buf.split();
+        assert!(reader.read().is_none());
+        drop(writer);
+    }
+
+    #[test]
+    fn unsplit_rejects_mismatched_halves() {
+        let (reader1, writer1) = create(10);
+        let (reader2, writer2) = create(10);
+
+        let err = BipBuffer::unsplit(reader1, writer2).unwrap_err();
+        let (reader1, writer2) = (err.reader, err.writer);
+
+        // Outstanding writer clones also prevent reassembly.
+        let extra = writer1.clone();
+        let err = BipBuffer::unsplit(reader1, writer1).unwrap_err();
+        drop(extra);
+        assert!(BipBuffer::unsplit(err.reader, err.writer).is_ok());
+        assert!(BipBuffer::unsplit(reader2, writer2).is_ok());
+    }
+
+    #[test]
+    fn create_from_caller_storage() {
+        let storage = vec![0xff; 10].into_boxed_slice();
+        let ptr = storage.as_ptr();
+        let (mut reader, mut writer) = create_from(storage);
+
+        assert!(writer.try_write(b"asdfpqrs"));
+        assert!(!writer.try_write(b"xyz"));
+        assert_eq!(reader.read().unwrap().view, b"asdfpqrs");
+
+        let storage = BipBuffer::unsplit(reader, writer).unwrap().into_inner();
+        assert_eq!(storage.as_ptr(), ptr);
+        assert_eq!(&storage[..8], b"asdfpqrs");
+    }
+
+    #[test]
+    fn create_in_static_region() {
+        let region: &'static mut [u8] = Box::leak(Box::new([0; 10]));
+        let ptr = region.as_ptr();
+        let (mut reader, mut writer) = create_in(region);
+
+        assert!(writer.try_write(b"asdfpqrs"));
+        assert_eq!(reader.read().unwrap().view, b"asdfpqrs");
+        drop((reader, writer));
+
+        // Dropping the halves left the region alone.
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 8) }, b"asdfpqrs");
+        assert_eq!(
+            try_create_in(Box::leak(Box::new([]))).err(),
+            Some(CreateError::ZeroCapacity)
+        );
+    }
+
+    #[test]
+    fn create_from_vec_uses_len() {
+        let mut storage = Vec::with_capacity(100);
+        storage.resize(4, 0);
+        let (mut reader, mut writer) = create_from_vec(storage);
+        assert!(!writer.try_write(b"asdfp"));
+        assert!(writer.try_write(b"asdf"));
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+    }
+
+    #[test]
+    fn create_from_rejects_empty_storage() {
+        assert_eq!(
+            try_create_from(Box::new([])).err(),
+            Some(CreateError::ZeroCapacity)
+        );
+    }
+
+    #[test]
+    fn create_aligned_data_region() {
+        for align in [64, 4096] {
+            let (mut reader, mut writer) = create_aligned(100, align);
+            assert!(writer.try_write(b"asdf"));
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, b"asdf");
+            assert_eq!(l.view.as_ptr() as usize % align, 0);
+        }
+    }
+
+    #[test]
+    fn create_aligned_rejects_bad_alignment() {
+        assert_eq!(
+            try_create_aligned(100, 48).err(),
+            Some(CreateError::InvalidAlignment(48))
+        );
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn create_mmap_larger_than_ram_budget() {
+        const CAPACITY: usize = 8 << 30;
+        let path = std::env::temp_dir().join(format!("bbuf-buffer-{}", std::process::id()));
+        let (mut reader, mut writer) = super::create_mmap(&path, CAPACITY).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let chunk = vec![7; 1 << 20];
+        for _ in 0..4 {
+            assert!(writer.try_write(&chunk));
+        }
+        let l = reader.read().unwrap();
+        assert_eq!(l.view.len(), 4 << 20);
+        assert!(l.view.iter().all(|&b| b == 7));
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn mirrored_reads_never_split() {
+        let (mut reader, mut writer) = super::create_mirrored(4096).unwrap();
+
+        let a = vec![b'a'; 3000];
+        let b = vec![b'b'; 2000];
+        assert!(writer.try_write(&a));
+        {
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, a);
+            // Keep the reader away from the writer so nothing resets.
+            assert!(writer.try_write(b"x"));
+        }
+        // This write runs off the end of the buffer, and comes back in one
+        // piece.
+        assert!(writer.try_write(&b));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view.len(), 2001);
+        assert_eq!(l.view[0], b'x');
+        assert_eq!(&l.view[1..], b);
+    }
+
+    #[test]
+    fn create_with_options_prefault() {
+        let options = BufferOptions {
+            prefault: true,
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(10, options).unwrap();
+        assert!(writer.try_write(b"asdf"));
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+
+        let err = create_with_options(0, BufferOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn create_with_options_mlock() {
+        let options = BufferOptions {
+            mlock: true,
+            ..Default::default()
+        };
+        match create_with_options(4096, options) {
+            Ok((mut reader, mut writer)) => {
+                assert!(writer.try_write(b"asdf"));
+                assert_eq!(reader.read().unwrap().view, b"asdf");
+            }
+            // An unprivileged process may not be allowed to lock memory, but
+            // the failure must be surfaced rather than ignored.
+            Err(err) => assert_ne!(err.kind(), std::io::ErrorKind::InvalidInput),
+        }
+    }
+
+    #[test]
+    fn absurd_capacity_is_an_error() {
+        let options = BufferOptions {
+            mlock: true,
+            ..Default::default()
+        };
+        assert!(create_with_options(isize::MAX as usize, options).is_err());
+        assert!(matches!(
+            try_create(isize::MAX as usize),
+            Err(CreateError::OutOfMemory(_))
+        ));
+    }
+
+    #[test]
+    fn write_retry_without_backoff_fails_fast() {
+        let (_reader, mut writer) = create(4);
+        assert_eq!(writer.write_retry(b"asdf"), Ok(()));
+        assert_eq!(writer.write_retry(b"x"), Err(WriteError::Full));
+    }
+
+    #[test]
+    fn write_retry_waits_for_release() {
+        for backoff in [
+            Backoff::Spin {
+                max_iters: u32::MAX,
+            },
+            Backoff::SpinThenYield {
+                spins: 100,
+                yields: u32::MAX,
+            },
+            Backoff::SpinThenPark {
+                spins: 100,
+                timeout: Duration::from_secs(60),
+            },
+        ] {
+            let options = BufferOptions {
+                backoff,
+                ..Default::default()
+            };
+            let (mut reader, mut writer) = create_with_options(4, options).unwrap();
+            assert!(writer.try_write(b"asdf"));
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    std::thread::sleep(Duration::from_millis(10));
+                    assert_eq!(reader.read().unwrap().view, b"asdf");
+                });
+                assert_eq!(writer.write_retry(b"pqrs"), Ok(()), "{backoff:?}");
+            });
+            assert_eq!(reader.read().unwrap().view, b"pqrs");
+        }
+    }
+
+    fn create_blocking(capacity: usize) -> (Reader, Writer) {
+        let options = BufferOptions {
+            overflow: OverflowPolicy::Block,
+            ..Default::default()
+        };
+        create_with_options(capacity, options).unwrap()
+    }
+
+    #[test]
+    fn block_waits_for_release() {
+        let (mut reader, mut writer) = create_blocking(4);
+        assert!(writer.try_write(b"asdf"));
+        // try_write still fails straight away.
+        assert!(!writer.try_write(b"pqrs"));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                assert_eq!(reader.read().unwrap().view, b"asdf");
+            });
+            assert_eq!(writer.write_retry(b"pqrs"), Ok(()));
+        });
+        assert_eq!(reader.read().unwrap().view, b"pqrs");
+    }
+
+    #[test]
+    fn block_fails_once_reader_is_dropped() {
+        let (reader, mut writer) = create_blocking(4);
+        assert!(writer.try_write(b"asdf"));
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                drop(reader);
+            });
+            assert_eq!(writer.write_retry(b"pqrs"), Err(WriteError::Disconnected));
+        });
+        // And from then on, without blocking at all.
+        assert_eq!(writer.write_retry(b"pqrs"), Err(WriteError::Disconnected));
+    }
+
+    #[test]
+    fn block_rejects_oversized_writes() {
+        let (_reader, mut writer) = create_blocking(4);
+        assert_eq!(writer.write_retry(b"asdfg"), Err(WriteError::Full));
+    }
+
+    #[test]
+    fn unsplit_reconnects() {
+        let (reader, writer) = create_blocking(4);
+        let (mut reader, mut writer) = BipBuffer::unsplit(reader, writer).unwrap().split();
+        assert!(writer.try_write(b"asdf"));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                assert_eq!(reader.read().unwrap().view, b"asdf");
+            });
+            assert_eq!(writer.write_retry(b"pqrs"), Ok(()));
+        });
+    }
+
+    #[test]
+    fn write_retry_park_times_out() {
+        let (_reader, mut writer) = create(4);
+        writer.set_backoff(Backoff::SpinThenPark {
+            spins: 10,
+            timeout: Duration::from_millis(20),
+        });
+        assert!(writer.try_write(b"asdf"));
+        let start = Instant::now();
+        assert_eq!(writer.write_retry(b"x"), Err(WriteError::Full));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(lock(&writer.buffer.waiters).is_empty());
+    }
+
+    #[test]
+    fn stats_track_every_operation() {
+        let (mut reader, mut writer) = create(10);
+        assert_eq!(reader.stats(), Stats::default());
+
+        assert!(writer.try_write(b"aaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        assert_eq!(writer.stats().occupancy(), 8);
+        drop(l);
+        assert!(!writer.try_write(b"ccccc"));
+        // Inverts, since "bbbb" is still unread.
+        assert!(writer.try_write(b"ccc"));
+        assert_eq!(reader.read().unwrap().view, b"bbbb");
+        assert_eq!(reader.read().unwrap().view, b"ccc");
+        // Back to idle, so this takes the lock-free path.
+        assert!(writer.try_write(b"d"));
+
+        let expected = Stats {
+            bytes_written: 12,
+            bytes_read: 11,
+            writes_rejected: 1,
+            bytes_rejected: 5,
+            bytes_overwritten: 0,
+            inversions: 1,
+            max_occupancy: 8,
+            lease_count: 3,
+        };
+        assert_eq!(reader.stats(), expected);
+        assert_eq!(reader.stats_exact(), expected);
+        assert_eq!(writer.stats_exact().occupancy(), 1);
+    }
+
+    #[test]
+    fn pow2_stats_count_wraps_as_inversions() {
+        let (mut reader, mut writer) = create_pow2(8);
+        assert!(writer.try_write(b"aaaaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"b"));
+        // Doesn't fit in the one byte left at the end.
+        assert!(writer.try_write(b"cc"));
+        assert_eq!(writer.stats().inversions, 1);
+        assert_eq!(reader.read().unwrap().view, b"b");
+        assert_eq!(reader.read().unwrap().view, b"cc");
+        assert!(writer.try_write(b"dd"));
+        assert_eq!(writer.stats().inversions, 1);
+    }
+
+    #[test]
+    fn clear_counts_as_read() {
+        let mut buf = BipBuffer::new(10);
+        assert!(buf.write(b"asdf"));
+        buf.clear();
+        assert_eq!(buf.stats().occupancy(), 0);
+        assert_eq!(buf.stats().bytes_read, 4);
+    }
+
+    fn create_records(capacity: usize, records: usize) -> (Reader, Writer) {
+        let options = BufferOptions {
+            records: Some(records),
+            ..Default::default()
+        };
+        create_with_options(capacity, options).unwrap()
+    }
+
+    #[test]
+    fn records_keep_their_boundaries() {
+        let (mut reader, mut writer) = create_records(10, 10);
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"asdf");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"pqrs");
+        assert!(reader.read_record().is_none());
+        // Empty records aren't allowed.
+        assert!(!writer.try_write(b""));
+    }
+
+    #[test]
+    fn records_survive_inversion() {
+        let (mut reader, mut writer) = create_records(10, 10);
+        assert!(writer.try_write(b"aaaa"));
+        assert!(writer.try_write(b"bbbb"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"aaaa");
+        // This wraps around to the start of the buffer.
+        assert!(writer.try_write(b"ccc"));
+        assert!(writer.try_write(b"d"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"bbbb");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"ccc");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"d");
+        assert!(reader.read_record().is_none());
+    }
+
+    #[test]
+    fn records_fill_before_bytes_do() {
+        let (mut reader, mut writer) = create_records(100, 2);
+        assert!(writer.try_write(b"a"));
+        assert!(writer.try_write(b"b"));
+        assert!(!writer.try_write(b"c"));
+        assert_eq!(writer.stats().writes_rejected, 1);
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"a");
+        assert!(writer.try_write(b"c"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"b");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"c");
+    }
+
+    #[test]
+    fn records_mix_with_byte_reads() {
+        let (mut reader, mut writer) = create_records(10, 10);
+        assert!(writer.try_write(b"ab"));
+        assert!(writer.try_write(b"cd"));
+        assert_eq!(reader.read().unwrap().view, b"abcd");
+        assert!(writer.try_write(b"ef"));
+        assert!(writer.try_write(b"gh"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"ef");
+        assert_eq!(reader.read().unwrap().view, b"gh");
+    }
+
+    #[test]
+    fn records_are_overwritten_whole() {
+        let options = BufferOptions {
+            overflow: OverflowPolicy::Overwrite,
+            records: Some(3),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(10, options).unwrap();
+        assert!(writer.try_write(b"aaaa"));
+        assert!(writer.try_write(b"bbbb"));
+        // Only one byte is needed, but all of "aaaa" goes.
+        assert!(writer.try_write(b"ccc"));
+        assert!(writer.try_write(b"d"));
+        // Out of records rather than bytes, so "bbbb" goes.
+        assert!(writer.try_write(b"e"));
+        assert_eq!(writer.stats().bytes_overwritten, 8);
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"ccc");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"d");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"e");
+        assert!(reader.read_record().is_none());
+    }
+
+    #[test]
+    fn records_are_numbered() {
+        let (mut reader, mut writer) = create_records(10, 10);
+        assert_eq!(writer.next_seq(), 0);
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.clone().try_write(b"pqrs"));
+        assert_eq!(writer.next_seq(), 2);
+        let (seq, l) = reader.read_record().unwrap().unwrap();
+        assert_eq!((seq, l.view), (0, &b"asdf"[..]));
+        drop(l);
+        let (seq, l) = reader.read_record().unwrap().unwrap();
+        assert_eq!((seq, l.view), (1, &b"pqrs"[..]));
+    }
+
+    #[test]
+    fn sequence_gaps_match_rejected_writes() {
+        let (mut reader, mut writer) = create_records(8, 4);
+        let mut expected = 0;
+        let mut gaps = 0;
+        for i in 0..1000u32 {
+            writer.try_write(&[(i % 3) as u8 + 1; 3][..1 + (i % 3) as usize]);
+            if i % 5 == 0 {
+                while let Some(Ok((seq, _))) = reader.read_record() {
+                    gaps += seq - expected;
+                    expected = seq + 1;
+                }
+            }
+        }
+        while let Some(Ok((seq, _))) = reader.read_record() {
+            gaps += seq - expected;
+            expected = seq + 1;
+        }
+        gaps += writer.next_seq() - expected;
+        let stats = writer.stats();
+        assert!(stats.writes_rejected > 0);
+        assert_eq!(gaps, stats.writes_rejected as u64);
+    }
+
+    #[cfg(feature = "debug-utils")]
+    #[test]
+    fn debug_snapshot_marks_the_wrap() {
+        let (mut reader, mut writer) = create(10);
+        assert_eq!(reader.debug_snapshot(100), "0 unread bytes\n");
+        assert!(writer.try_write(b"aaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"ccc"));
+        let mut txn = writer.begin();
+        // Not committed, so not shown.
+        assert!(txn.append(b"z"));
+        assert_eq!(
+            reader.debug_snapshot(100),
+            "7 unread bytes\n\
+             00000000  62 62 62 62                                       |bbbb|\n\
+             -------- wraps to the start of the buffer --------\n\
+             00000004  63 63 63                                          |ccc|\n"
+        );
+        assert_eq!(
+            reader.debug_snapshot(5),
+            "7 unread bytes\n\
+             00000000  62 62 62 62                                       |bbbb|\n\
+             -------- wraps to the start of the buffer --------\n\
+             00000004  63                                                |c|\n\
+             (2 more bytes)\n"
+        );
+        drop(txn);
+        assert_eq!(reader.read().unwrap().view, b"bbbb");
+    }
+
+    #[test]
+    fn zeroize_scrubs_released_data() {
+        let options = BufferOptions {
+            zeroize_on_release: true,
+            overflow: OverflowPolicy::Overwrite,
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(8, options).unwrap();
+        let raw = |reader: &Reader| unsafe { reader.0.data().slice(0, 8) }.to_vec();
+        assert!(writer.try_write(b"secret"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"xy"));
+        assert_eq!(l.view, b"secret");
+        drop(l);
+        assert_eq!(raw(&reader), b"\0\0\0\0\0\0xy");
+
+        // Discards "x" to make room.
+        assert!(writer.try_write(b"1234567"));
+        assert_eq!(raw(&reader), b"1234567y");
+        assert_eq!(reader.read().unwrap().view, b"y");
+        assert_eq!(raw(&reader), b"1234567\0");
+
+        assert!(writer.try_write(b"z"));
+        let mut buf = BipBuffer::unsplit(reader, writer).unwrap();
+        buf.clear();
+        assert_eq!(unsafe { buf.0.data().slice(0, 8) }, [0; 8]);
+    }
+
+    #[cfg(feature = "crc")]
+    #[test]
+    fn checksums_catch_corruption() {
+        let options = BufferOptions {
+            records: Some(10),
+            checksums: true,
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(32, options).unwrap();
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+        // Flip a byte in the middle of the first record.
+        unsafe { reader.0.data().write(1, b"X") };
+        assert_eq!(
+            reader.read_record().unwrap().err(),
+            Some(RecordError::Corrupt { seq: 0 })
+        );
+        let (seq, l) = reader.read_record().unwrap().unwrap();
+        assert_eq!((seq, l.view), (1, &b"pqrs"[..]));
+        drop(l);
+        assert!(reader.read_record().is_none());
+        assert_eq!(reader.stats().occupancy(), 0);
+
+        // The checksum itself can be corrupted too.
+        assert!(writer.try_write(b"zzzz"));
+        unsafe { reader.0.data().write(4, b"X") };
+        assert_eq!(
+            reader.read_record().unwrap().err(),
+            Some(RecordError::Corrupt { seq: 2 })
+        );
+    }
+
+    // FakeClock only moves when told to.
+    #[derive(Debug)]
+    struct FakeClock {
+        start: Instant,
+        elapsed_ms: std::sync::atomic::AtomicU64,
+    }
+    impl FakeClock {
+        fn advance(&self, ms: u64) {
+            self.elapsed_ms.fetch_add(ms, Ordering::Relaxed);
+        }
+    }
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + Duration::from_millis(self.elapsed_ms.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn read_older_than_leaves_recent_records() {
+        let clock = std::sync::Arc::new(FakeClock {
+            start: Instant::now(),
+            elapsed_ms: 0.into(),
+        });
+        let options = BufferOptions {
+            records: Some(10),
+            clock: Some(clock.clone()),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(32, options).unwrap();
+        let age = Duration::from_millis(100);
+        assert!(writer.try_write(b"a"));
+        clock.advance(50);
+        assert!(writer.try_write(b"b"));
+        clock.advance(50);
+        assert!(writer.try_write(b"c"));
+        // "a" is exactly 100ms old, which isn't older than 100ms.
+        assert!(reader.read_older_than(age).is_none());
+        clock.advance(1);
+        assert_eq!(reader.read_older_than(age).unwrap().unwrap().1.view, b"a");
+        assert!(reader.read_older_than(age).is_none());
+        clock.advance(50);
+        assert_eq!(reader.read_older_than(age).unwrap().unwrap().1.view, b"b");
+        assert!(reader.read_older_than(age).is_none());
+        // Everything is still there for a regular read.
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"c");
+    }
+
+    #[test]
+    fn lag_follows_the_oldest_unread_write() {
+        let clock = std::sync::Arc::new(FakeClock {
+            start: Instant::now(),
+            elapsed_ms: 0.into(),
+        });
+        let options = BufferOptions {
+            clock: Some(clock.clone()),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(10, options).unwrap();
+        assert_eq!(reader.lag_duration(), Duration::ZERO);
+        assert!(writer.try_write(b"aaaa"));
+        let l = reader.read().unwrap();
+        clock.advance(10);
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        clock.advance(10);
+        // Inverts, so "bbbb" is the oldest data, in the tail segment.
+        assert!(writer.try_write(b"ccc"));
+        clock.advance(10);
+        assert_eq!(reader.lag_bytes(), 7);
+        assert_eq!(reader.lag_duration(), Duration::from_millis(20));
+        assert_eq!(reader.read().unwrap().view, b"bbbb");
+        assert_eq!(reader.lag_bytes(), 3);
+        assert_eq!(reader.lag_duration(), Duration::from_millis(10));
+        assert_eq!(reader.read().unwrap().view, b"ccc");
+        assert_eq!(reader.lag_bytes(), 0);
+        assert_eq!(reader.lag_duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn record_lag_follows_the_oldest_record() {
+        let clock = std::sync::Arc::new(FakeClock {
+            start: Instant::now(),
+            elapsed_ms: 0.into(),
+        });
+        let options = BufferOptions {
+            records: Some(10),
+            clock: Some(clock.clone()),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(32, options).unwrap();
+        assert!(writer.try_write(b"a"));
+        clock.advance(50);
+        assert!(writer.try_write(b"b"));
+        clock.advance(50);
+        assert_eq!(reader.lag_duration(), Duration::from_millis(100));
+        drop(reader.read_record());
+        assert_eq!(reader.lag_duration(), Duration::from_millis(50));
+        drop(reader.read_record());
+        assert_eq!(reader.lag_duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn zero_records_is_an_error() {
+        let options = BufferOptions {
+            records: Some(0),
+            ..Default::default()
+        };
+        assert!(create_with_options(10, options).is_err());
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let (mut reader, mut writer) = create(64);
+        assert!(writer.write_framed(b"asdf"));
+        assert!(writer.write_framed(b""));
+        assert!(writer.write_framed(b"pqrs"));
+        assert_eq!(reader.read_framed().unwrap().view, b"asdf");
+        assert_eq!(reader.read_framed().unwrap().view, b"");
+        assert_eq!(reader.read_framed().unwrap().view, b"pqrs");
+        assert!(reader.read_framed().is_none());
+    }
+
+    #[test]
+    fn frames_survive_inversion() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.write_framed(b"aaaa"));
+        assert!(writer.write_framed(b"bb"));
+        assert_eq!(reader.read_framed().unwrap().view, b"aaaa");
+        // Only two bytes are free at the end of the buffer, so the whole
+        // frame, prefix and all, wraps around to the start.
+        assert!(writer.write_framed(b"ccc"));
+        assert_eq!(writer.stats().inversions, 1);
+        assert_eq!(reader.read_framed().unwrap().view, b"bb");
+        assert_eq!(reader.read_framed().unwrap().view, b"ccc");
+        assert!(reader.read_framed().is_none());
+    }
+
+    #[test]
+    fn partial_frames_stay_unread() {
+        let (mut reader, mut writer) = create(64);
+        assert!(writer.try_write(&[10, 0]));
+        assert!(reader.read_framed().is_none());
+        assert!(writer.try_write(&[0, 0, b'a', b'b']));
+        assert!(reader.read_framed().is_none());
+        assert!(writer.try_write(b"cdefghij"));
+        assert_eq!(reader.read_framed().unwrap().view, b"abcdefghij");
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn frame_size_rounds_capacity_down() {
+        let options = BufferOptions::default().frame_size(4);
+        let (_reader, mut writer) = create_with_options(10, options).unwrap();
+        assert!(writer.try_write(b"aaaabbbb"));
+        assert!(!writer.try_write(b"cccc"));
+
+        let options = BufferOptions::default().frame_size(4);
+        assert!(create_with_options(3, options).is_err());
+        let options = BufferOptions::default().frame_size(0);
+        assert!(create_with_options(3, options).is_err());
+    }
+
+    #[test]
+    fn frame_size_rejects_partial_frames() {
+        let options = BufferOptions::default().frame_size(4);
+        let (mut reader, mut writer) = create_with_options(16, options).unwrap();
+        assert!(!writer.try_write(b"aaa"));
+        assert!(!writer.try_write(b"aaaab"));
+        assert!(writer.try_write(b""));
+        assert!(writer.try_write(b"aaaa"));
+        assert_eq!(writer.stats().writes_rejected, 2);
+        assert_eq!(reader.read().unwrap().view, b"aaaa");
+    }
+
+    #[test]
+    fn frames_are_never_split() {
+        // Each frame is filled with a single distinct byte, so a lease that
+        // split one would show up as a frame with mixed bytes.
+        let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = |n: u64| {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            (rng % n) as usize
+        };
+        for overflow in [OverflowPolicy::Fail, OverflowPolicy::Overwrite] {
+            let options = BufferOptions {
+                overflow,
+                ..BufferOptions::default().frame_size(4)
+            };
+            let (mut reader, mut writer) = create_with_options(30, options).unwrap();
+            let mut frame = 0u8;
+            for _ in 0..10_000 {
+                if next(3) > 0 {
+                    let n = 1 + next(4);
+                    let msg: Vec<u8> = (0..n)
+                        .flat_map(|i| [frame.wrapping_add(i as u8); 4])
+                        .collect();
+                    if writer.try_write(&msg) {
+                        frame = frame.wrapping_add(n as u8);
+                    }
+                } else if let Some(l) = reader.read() {
+                    assert_eq!(l.view.len() % 4, 0);
+                    for f in l.view.chunks(4) {
+                        assert!(f.iter().all(|&b| b == f[0]), "{f:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_are_overwritten_whole() {
+        let options = BufferOptions {
+            overflow: OverflowPolicy::Overwrite,
+            ..BufferOptions::default().frame_size(4)
+        };
+        let (mut reader, mut writer) = create_with_options(12, options).unwrap();
+        assert!(writer.try_write(b"aaaabbbbcccc"));
+        // Only one frame is needed, so only one frame is discarded.
+        assert!(writer.try_write(b"dddd"));
+        assert_eq!(writer.stats().bytes_overwritten, 4);
+        assert_eq!(reader.read().unwrap().view, b"bbbbcccc");
+        assert_eq!(reader.read().unwrap().view, b"dddd");
+    }
+
+    #[test]
+    fn debug_describes_the_buffer() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaaa"));
+        let l = reader.read();
+        assert!(writer.try_write(b"bb"));
+        drop(l);
+        assert!(writer.try_write(b"bbbb"));
+        let other = writer.clone();
+        let r = format!("{reader:?}");
+        for want in [
+            "capacity: 10",
+            "unread: 6",
+            "free: 4",
+            "inverted: true",
+            "writers: 2",
+        ] {
+            assert!(r.contains(want), "{r} should contain {want}");
+        }
+        assert!(!r.contains("bbbb"));
+        assert!(format!("{other:?}").contains("reader_alive: true"));
+
+        {
+            let _guard = lock(&reader.0.tracker);
+            assert!(format!("{writer:?}").contains("inverted: <locked>"));
+        }
+        drop(reader);
+        assert!(format!("{writer:?}").contains("reader_alive: false"));
+    }
+
+    #[test]
+    fn write_many_is_one_write() {
+        let (mut reader, mut writer) = create(10);
+        assert_eq!(writer.try_write_many(&[b"h", b"", b"key", b"val"]), Ok(()));
+        assert_eq!(
+            writer.try_write_many(&[b"h", b"key", b"value"]),
+            Err(WriteError::Full)
+        );
+        assert_eq!(writer.try_write_many(&[]), Ok(()));
+        assert_eq!(writer.try_write_many(&[b"", b""]), Ok(()));
+        assert_eq!(reader.read().unwrap().view, b"hkeyval");
+        assert!(reader.read().is_none());
+        assert_eq!(reader.stats().writes_rejected, 1);
+    }
+
+    #[test]
+    fn write_many_is_one_record() {
+        let (mut reader, mut writer) = create_records(16, 4);
+        assert_eq!(writer.try_write_many(&[b"h", b"key", b"val"]), Ok(()));
+        assert_eq!(writer.try_write_many(&[b"h", b"k", b"v"]), Ok(()));
+        assert_eq!(writer.try_write_many(&[b""]), Ok(()));
+        assert_eq!(writer.next_seq(), 2);
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"hkeyval");
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"hkv");
+        assert!(reader.read_record().is_none());
+    }
+
+    #[test]
+    fn transactions_are_all_or_nothing() {
+        let (mut reader, mut writer) = create(16);
+        let mut other = writer.clone();
+        let mut txn = writer.begin();
+        assert!(txn.append(b"ab"));
+        assert!(reader.read().is_none());
+        // Nobody else can write until the transaction is done.
+        assert!(!other.try_write(b"x"));
+        assert!(txn.append(b"cd"));
+        assert!(reader.read().is_none());
+        assert!(txn.commit());
+        assert!(other.try_write(b"x"));
+        assert_eq!(reader.read().unwrap().view, b"abcdx");
+        let stats = reader.stats();
+        assert_eq!(stats.bytes_written, 5);
+        assert_eq!(stats.writes_rejected, 1);
+    }
+
+    #[test]
+    fn aborted_transactions_give_back_their_space() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        let mut txn = writer.begin();
+        // This only fits at the start of the buffer, so it inverts it.
+        assert!(txn.append(b"ccc"));
+        assert!(reader.0.lock().is_inverted());
+        drop(txn);
+        assert_eq!(reader.read().unwrap().view, b"bbbb");
+        assert!(reader.read().is_none());
+        assert!(writer.try_write(b"dddddddddd"));
+        let stats = reader.stats();
+        assert_eq!(stats.bytes_written, 18);
+        assert_eq!(stats.inversions, 0);
+    }
+
+    #[test]
+    fn transactions_grow_their_reservation() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"aaaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bb"));
+        drop(l);
+        let mut txn = writer.begin();
+        assert!(txn.append(b"cc"));
+        // Grows in place.
+        assert!(txn.append(b"dddd"));
+        // There's no room for this anywhere...
+        assert!(!txn.append(b"eeee"));
+        assert_eq!(reader.read().unwrap().view, b"bb");
+        // ...until the reader catches up, and it can move to the start.
+        assert!(txn.append(b"ee"));
+        assert!(txn.commit());
+        assert_eq!(reader.read().unwrap().view, b"ccddddee");
+    }
+
+    #[test]
+    fn transactions_are_one_record() {
+        let (mut reader, mut writer) = create_records(16, 4);
+        let mut txn = writer.begin();
+        assert!(txn.append(b"ab"));
+        assert!(txn.append(b"cd"));
+        assert!(txn.commit());
+        assert!(writer.try_write(b"ef"));
+        // Empty records still aren't allowed.
+        assert!(!writer.begin().commit());
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"abcd");
+        let (seq, l) = reader.read_record().unwrap().unwrap();
+        assert_eq!((seq, l.view), (1, &b"ef"[..]));
+    }
+
+    #[test]
+    fn transactions_match_a_model() {
+        // Interleave plain writes, committed and aborted transactions and
+        // reads, and check that the reader sees exactly the committed data.
+        let mut rng = 0x0123_4567_89ab_cdefu64;
+        let mut next = |n: u64| {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            (rng % n) as usize
+        };
+        #[cfg_attr(not(all(feature = "mmap", target_os = "linux")), allow(unused_mut))]
+        let mut buffers = vec![
+            Buffer::new(Storage::alloc(29, 1).unwrap()),
+            Buffer::with_tracker(
+                Storage::alloc(32, 1).unwrap(),
+                AnyTracker::Pow2(Pow2Tracker::new(32)),
+            ),
+        ];
+        #[cfg(all(feature = "mmap", target_os = "linux"))]
+        buffers.push(Buffer::new(Storage::mirrored(1).unwrap()));
+        for buffer in buffers {
+            let capacity = buffer.data().len();
+            let (mut reader, mut writer) = BipBuffer(Arc::new(buffer)).split();
+            let mut expected = VecDeque::new();
+            let mut byte = 0u8;
+            let mut msg = |n: usize| {
+                (0..n)
+                    .map(|_| {
+                        byte = byte.wrapping_add(1);
+                        byte
+                    })
+                    .collect::<Vec<u8>>()
+            };
+            for _ in 0..10_000 {
+                match next(4) {
+                    0 => {
+                        let p = msg(1 + next(capacity as u64 / 2));
+                        if writer.try_write(&p) {
+                            expected.extend(p);
+                        }
+                    }
+                    1 => {
+                        let mut txn = writer.begin();
+                        let mut appended = Vec::new();
+                        for _ in 0..next(4) {
+                            let p = msg(next(capacity as u64 / 4));
+                            if txn.append(&p) {
+                                appended.extend(p);
+                            }
+                        }
+                        if next(2) == 0 {
+                            assert!(txn.commit());
+                            expected.extend(appended);
+                        }
+                    }
+                    _ => {
+                        if let Some(l) = reader.read() {
+                            let want: Vec<u8> = expected.drain(..l.view.len()).collect();
+                            assert_eq!(l.view, want);
+                        }
+                    }
+                }
+            }
+            while let Some(l) = reader.read() {
+                let want: Vec<u8> = expected.drain(..l.view.len()).collect();
+                assert_eq!(l.view, want);
+            }
+            assert!(expected.is_empty());
+        }
+    }
+
+    fn create_overwriting(capacity: usize) -> (Reader, Writer) {
+        let options = BufferOptions {
+            overflow: OverflowPolicy::Overwrite,
+            ..Default::default()
+        };
+        create_with_options(capacity, options).unwrap()
+    }
 
-struct Buffer {
-    tracker: Mutex<Tracker>,
-    data: UnsafeCell<Box<[u8]>>,
-}
+    #[test]
+    fn overwrite_discards_oldest() {
+        let (mut reader, mut writer) = create_overwriting(10);
+        assert!(writer.try_write(b"aaaa"));
+        assert!(writer.try_write(b"bbbb"));
+        // Discards "aaa" so that "ccc" fits at the start of the buffer.
+        assert!(writer.try_write(b"ccc"));
+        assert_eq!(writer.stats().bytes_overwritten, 3);
+        // Discards the next four unread bytes, "abbb", so that "dddd" fits
+        // right after "ccc".
+        assert!(writer.try_write(b"dddd"));
+        assert_eq!(reader.read().unwrap().view, b"b");
+        assert_eq!(reader.read().unwrap().view, b"cccdddd");
+        assert!(reader.read().is_none());
 
-// We solemnly swear that the users of Buffer will avoid data races on the
-// `data` field by always following access patterns vetted by the `tracker`
-unsafe impl Sync for Buffer {}
+        let stats = reader.stats();
+        assert_eq!(stats.bytes_overwritten, 7);
+        assert_eq!(stats.occupancy(), 0);
+        assert_eq!(stats.writes_rejected, 0);
+    }
 
-pub struct Reader(Arc<Buffer>);
-#[derive(Clone)]
-pub struct Writer(Arc<Buffer>);
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn mirrored_overwrite_discards_oldest() {
+        let mut buffer = Buffer::new(Storage::mirrored(1).unwrap());
+        buffer.overflow = OverflowPolicy::Overwrite;
+        let capacity = buffer.data().len();
+        let (mut reader, mut writer) = BipBuffer(Arc::new(buffer)).split();
+        let half = vec![b'a'; capacity / 2];
+        assert!(writer.try_write(&half));
+        assert!(writer.try_write(&vec![b'b'; capacity / 2]));
+        // Only as much as the new write needs is discarded, and the rest
+        // reads back contiguously across the wrap point.
+        assert!(writer.try_write(b"cc"));
+        assert_eq!(writer.stats().bytes_overwritten, 2);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view.len(), capacity);
+        assert_eq!(&l.view[..capacity / 2 - 2], &half[2..]);
+        assert_eq!(&l.view[capacity - 2..], b"cc");
+    }
 
-pub fn create(capacity: usize) -> (Reader, Writer) {
-    let b = Arc::new(Buffer {
-        tracker: Mutex::new(Tracker::new(capacity)),
-        data: UnsafeCell::new(vec![0; capacity].into_boxed_slice()),
-    });
-    (Reader(b.clone()), Writer(b))
-}
+    #[test]
+    fn overwrite_never_discards_leased_data() {
+        let (mut reader, mut writer) = create_overwriting(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbbb"));
+        assert!(!writer.try_write(b"c"));
+        assert_eq!(l.view, b"aaaaa");
+        drop(l);
+        // Once the lease is gone, there's room again without discarding.
+        assert!(writer.try_write(b"ccccc"));
+        // Now one "b" gets discarded.
+        assert!(writer.try_write(b"d"));
+        assert_eq!(reader.read().unwrap().view, b"bbbb");
+        assert_eq!(reader.read().unwrap().view, b"cccccd");
+        assert_eq!(reader.stats().bytes_overwritten, 1);
+        assert_eq!(reader.stats().writes_rejected, 1);
+    }
 
-impl Writer {
-    pub fn try_write(&mut self, p: &[u8]) -> bool {
-        let mut guard = self.0.tracker.lock().unwrap();
-        let Some(w) = guard.write(p.len()) else {
-            return false;
+    #[test]
+    fn overwrite_rejects_oversized_writes_without_discarding() {
+        let (mut reader, mut writer) = create_overwriting(10);
+        assert!(writer.try_write(b"asdf"));
+        assert!(!writer.try_write(&[0; 11]));
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+        assert_eq!(reader.stats().bytes_overwritten, 0);
+    }
+
+    #[test]
+    fn overwrite_keeps_the_newest_data() {
+        // Write a stream where each byte encodes its own position, with
+        // random write sizes and reads in between. Whatever gets discarded,
+        // the reader must see the stream in order, and everything must add
+        // up.
+        let mut rng = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |n: u64| {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            (rng % n) as usize
         };
-        unsafe {
-            let data = &mut *self.0.data.get();
-            data[w.start..][..w.len].copy_from_slice(p);
+        //
+        // Positions are only recoverable mod 251, so this needs capacities
+        // well below that, which rules out the page-sized mirrored buffer.
+        let buffers = [
+            Buffer::new(Storage::alloc(29, 1).unwrap()),
+            Buffer::with_tracker(
+                Storage::alloc(32, 1).unwrap(),
+                AnyTracker::Pow2(Pow2Tracker::new(32)),
+            ),
+        ];
+        for mut buffer in buffers {
+            buffer.overflow = OverflowPolicy::Overwrite;
+            let capacity = buffer.data().len();
+            let (mut reader, mut writer) = BipBuffer(Arc::new(buffer)).split();
+
+            let mut pos = 0;
+            let mut seen: Option<usize> = None;
+            for _ in 0..10_000 {
+                if next(3) > 0 {
+                    let len = 1 + next(12);
+                    let msg: Vec<u8> = (pos..pos + len).map(|i| (i % 251) as u8).collect();
+                    assert!(writer.try_write(&msg));
+                    pos += len;
+                } else if let Some(l) = reader.read() {
+                    // Only the last `capacity` bytes can still be in the
+                    // buffer, which pins down which position each byte
+                    // came from.
+                    let oldest = pos.saturating_sub(capacity);
+                    for &b in l.view {
+                        let p = oldest + (b as usize + 251 - oldest % 251) % 251;
+                        assert!(p < pos);
+                        if let Some(seen) = seen {
+                            assert!(p > seen, "{p} after {seen}");
+                        }
+                        seen = Some(p);
+                    }
+                }
+            }
+            while reader.read().is_some() {}
+            let stats = reader.stats();
+            assert_eq!(stats.bytes_written, pos);
+            assert_eq!(stats.bytes_read + stats.bytes_overwritten, pos);
+            assert!(stats.bytes_overwritten > 0);
+            assert_eq!(stats.writes_rejected, 0);
         }
-        guard.commit(w);
-        true
     }
-}
-impl Reader {
-    pub fn read(&mut self) -> Option<Lease> {
-        let r = self.0.tracker.lock().unwrap().read()?;
-        let view = unsafe {
-            let data = &mut *self.0.data.get();
-            &data[r.start..][..r.len]
+
+    #[test]
+    fn pow2_buffer() {
+        // Rounded up to 16.
+        let (mut reader, mut writer) = create_pow2(10);
+        assert!(writer.try_write(b"0123456789"));
+        assert!(writer.try_write(b"abcdef"));
+        assert!(!writer.try_write(b"x"));
+        assert_eq!(reader.read().unwrap().view, b"0123456789abcdef");
+
+        assert!(writer.try_write(b"asdf"));
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+        assert_eq!(try_create_pow2(0).err(), Some(CreateError::ZeroCapacity));
+        assert!(try_create_pow2(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn pause_spares_outstanding_leases() {
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert!(writer.try_write(b"asdf"));
+        let l = reader.read().unwrap();
+        control.pause();
+        assert!(control.is_paused());
+        assert!(!writer.try_write(b"pq"));
+        assert_eq!(writer.try_write_many(&[b"pq"]), Err(WriteError::Paused));
+        assert_eq!(writer.write_retry(b"pq"), Err(WriteError::Paused));
+        assert!(!writer.begin().append(b"pq"));
+        assert_eq!(l.view, b"asdf");
+        drop(l);
+        // Still paused once the buffer is idle, so the lock-free path is off
+        // too.
+        assert!(!writer.try_write(b"pq"));
+        assert!(reader.read().is_none());
+        assert_eq!(control.stats().writes_rejected, 0);
+
+        control.resume();
+        assert!(writer.try_write(b"pq"));
+        assert_eq!(reader.read().unwrap().view, b"pq");
+        assert!(format!("{control:?}").contains("paused: false"));
+        assert!(format!("{reader:?}").contains("writers: 1"));
+        assert!(BipBuffer::unsplit(reader, writer).is_ok());
+    }
+
+    #[test]
+    fn pause_wakes_blocked_writers() {
+        let options = BufferOptions {
+            overflow: OverflowPolicy::Block,
+            ..Default::default()
         };
-        Some(Lease {
-            reader: self,
-            lease: Some(r),
-            view,
-        })
+        let (_reader, mut writer) = create_with_options(4, options).unwrap();
+        let control = Control::new(writer.buffer.clone());
+        assert!(writer.try_write(b"asdf"));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                control.pause();
+            });
+            assert_eq!(writer.write_retry(b"pq"), Err(WriteError::Paused));
+        });
     }
-}
 
-pub struct Lease<'a> {
-    reader: &'a mut Reader,
-    lease: Option<ReadLease>,
-    pub view: &'a [u8],
-}
-impl Drop for Lease<'_> {
-    fn drop(&mut self) {
-        let lease = self.lease.take().expect("lease must persist until Drop");
-        self.reader.0.tracker.lock().unwrap().release(lease);
+    #[test]
+    fn compact_linearizes_inverted_data() {
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert!(writer.try_write(b"aaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"bbbb"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"cccc"));
+        drop(l);
+        // Inverts.
+        assert!(writer.try_write(b"ddd"));
+        control.compact();
+        assert!(writer.try_write(b"eee"));
+        assert_eq!(reader.read().unwrap().view, b"ccccdddeee");
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::create;
+    #[test]
+    fn compact_restores_contiguous_space() {
+        // As in the tracker's wraparound test, the unread data ends up split
+        // between 5..9 and 0..4, leaving two separate free bytes.
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        assert!(!writer.try_write(b"xx"));
+        assert_eq!(control.compact(), 8);
+        assert!(writer.try_write(b"xx"));
+        assert_eq!(reader.read().unwrap().view, b"bbbbccccxx");
+        assert_eq!(control.compact(), 0);
+    }
 
     #[test]
-    fn smoke() {
+    fn read_contiguous_reports_fragmentation() {
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert_eq!(
+            reader.read_contiguous(1).err(),
+            Some(ContiguousError::Short { unread: 0 })
+        );
+        // As in compact_restores_contiguous_space, the unread data ends up
+        // split between 5..9 and 0..4.
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        assert_eq!(
+            reader.read_contiguous(9).err(),
+            Some(ContiguousError::Short { unread: 8 })
+        );
+        assert_eq!(
+            reader.read_contiguous(6).err(),
+            Some(ContiguousError::Fragmented {
+                contiguous: 4,
+                unread: 8
+            })
+        );
+        control.compact();
+        assert_eq!(reader.read_contiguous(6).unwrap().view, b"bbbbcccc");
+    }
+
+    #[test]
+    fn compact_waits_for_the_lease() {
+        let options = BufferOptions {
+            records: Some(10),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(10, options).unwrap();
+        let control = Control::new(writer.buffer.clone());
+        for p in [b"aa", b"bb", b"cc"] {
+            assert!(writer.try_write(p));
+        }
+        let (_, l) = reader.read_record().unwrap().unwrap();
+        control.compact();
+        // The lease is untouched, and nothing has moved yet.
+        assert_eq!(l.view, b"aa");
+        assert!(!writer.try_write(b"dddddd"));
+        drop(l);
+        // Now "bbcc" is at the start, followed by 6 bytes of room.
+        assert!(writer.try_write(b"dddddd"));
+        assert_eq!(reader.read().unwrap().view, b"bbccdddddd");
+    }
+
+    #[test]
+    fn compact_waits_for_the_transaction() {
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert!(writer.try_write(b"aaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        let mut txn = writer.begin();
+        assert!(txn.append(b"cc"));
+        control.compact();
+        assert!(txn.commit());
+        assert!(writer.try_write(b"dddd"));
+        assert_eq!(reader.read().unwrap().view, b"bbbbccdddd");
+    }
+
+    #[test]
+    fn grow_linearizes_inverted_data() {
+        let (mut reader, mut writer, control) = create_with_control(10);
+        assert!(writer.try_write(b"aaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"bbbb"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"cccc"));
+        assert!(matches!(control.grow(20), Err(ResizeError::Leased)));
+        drop(l);
+        // Inverts.
+        assert!(writer.try_write(b"ddd"));
+        assert!(matches!(
+            control.grow(6),
+            Err(ResizeError::TooSmall { occupancy: 7 })
+        ));
+
+        control.grow(20).unwrap();
+        assert!(format!("{control:?}").contains("capacity: 20"));
+        assert!(writer.try_write(b"eeeeeeeeeeeee"));
+        assert!(!writer.try_write(b"f"));
+        assert_eq!(reader.read().unwrap().view, b"ccccdddeeeeeeeeeeeee");
+        assert!(writer.try_write(&[b'g'; 20]));
+    }
+
+    #[test]
+    fn grow_pow2_rounds_up() {
+        let (mut reader, mut writer) = create_pow2(8);
+        let control = Control::new(writer.buffer.clone());
+        assert!(writer.try_write(b"aaaaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"b"));
+        // Pads the end of the buffer.
+        assert!(writer.try_write(b"cc"));
+        control.grow(9).unwrap();
+        assert!(writer.try_write(&[b'd'; 13]));
+        assert_eq!(reader.read().unwrap().view, b"bccddddddddddddd");
+    }
+
+    #[test]
+    fn shrink_needs_room_for_unread_data() {
+        let (mut reader, mut writer, control) = create_with_control(100);
+        assert!(writer.try_write(&[b'a'; 60]));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(&[b'b'; 30]));
+        assert!(writer.try_write(&[b'c'; 20]));
+        assert!(matches!(
+            control.shrink_to(49),
+            Err(ResizeError::TooSmall { occupancy: 50 })
+        ));
+        control.shrink_to(1000).unwrap();
+        assert_eq!(control.capacity(), 100);
+        control.shrink_to_fit().unwrap();
+        assert_eq!(control.capacity(), 50);
+        assert!(!writer.try_write(b"d"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view.len(), 50);
+        assert!(l.view.starts_with(&[b'b'; 30]));
+    }
+
+    #[test]
+    fn maybe_shrink_waits_for_idleness() {
+        let clock = std::sync::Arc::new(FakeClock {
+            start: Instant::now(),
+            elapsed_ms: 0.into(),
+        });
+        let options = BufferOptions {
+            clock: Some(clock.clone()),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(100, options).unwrap();
+        let mut control = Control::new(writer.buffer.clone());
+        assert!(!control.maybe_shrink().unwrap());
+        control.set_shrink_policy(Some(ShrinkPolicy {
+            below_percent: 10,
+            after: Duration::from_secs(60),
+            capacity: 20,
+        }));
+        assert!(writer.try_write(&[b'a'; 10]));
+        // 10% isn't below 10%.
+        assert!(!control.maybe_shrink().unwrap());
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(&[b'b'; 9]));
+        assert!(!control.maybe_shrink().unwrap());
+        clock.advance(59_000);
+        assert!(!control.maybe_shrink().unwrap());
+        // A busy moment starts the wait over.
+        assert!(writer.try_write(&[b'c'; 50]));
+        assert!(!control.maybe_shrink().unwrap());
+        drop(reader.read().unwrap());
+        assert!(!control.maybe_shrink().unwrap());
+        clock.advance(59_000);
+        assert!(!control.maybe_shrink().unwrap());
+        assert!(writer.try_write(b"d"));
+        clock.advance(1_000);
+        assert!(control.maybe_shrink().unwrap());
+        assert_eq!(control.capacity(), 20);
+        assert_eq!(reader.read().unwrap().view, b"d");
+        // Already small enough.
+        clock.advance(60_000);
+        assert!(!control.maybe_shrink().unwrap());
+    }
+
+    #[test]
+    fn grow_needs_heap_storage() {
+        let (_reader, writer) = create_in(Box::leak(Box::new([0; 8])));
+        let control = Control::new(writer.buffer.clone());
+        assert!(matches!(control.grow(16), Err(ResizeError::Unsupported)));
+    }
+
+    #[test]
+    fn panics_dont_poison_the_buffer() {
         let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"asdf"));
+        std::thread::scope(|scope| {
+            let res = scope.spawn(|| {
+                let _lease = reader.read().unwrap();
+                panic!("while holding a lease");
+            });
+            assert!(res.join().is_err());
+        });
+        std::thread::scope(|scope| {
+            let res = scope.spawn(|| {
+                let _guard = reader.0.lock();
+                panic!("while holding the lock");
+            });
+            assert!(res.join().is_err());
+        });
+        assert!(reader.0.tracker.is_poisoned());
+        assert!(writer.try_write(b"pqrs"));
+        // The lease was released as the first thread unwound.
+        assert_eq!(reader.read().unwrap().view, b"pqrs");
+        assert!(format!("{writer:?}").contains("inverted: false"));
+    }
 
+    #[test]
+    fn read_with_consumes_what_it_reports() {
+        let (mut reader, mut writer) = create(16);
+        assert_eq!(reader.read_with(|p| (0, p.len())), None);
+        assert!(writer.try_write(b"hello world"));
+        assert_eq!(reader.read_with(|p| (5, p.len())), Some(11));
+        assert_eq!(reader.read_with(|p| (0, p.to_vec())).unwrap(), b" world");
+        assert_eq!(reader.read_with(|p| (p.len(), ())), Some(()));
         assert!(reader.read().is_none());
+    }
 
+    #[test]
+    fn read_with_panics_consume_nothing() {
+        let (mut reader, mut writer) = create(10);
         assert!(writer.try_write(b"asdf"));
-        assert!(writer.try_write(b"pqrs"));
+        std::thread::scope(|scope| {
+            let res = scope.spawn(|| reader.read_with(|_| -> (usize, ()) { panic!("in f") }));
+            assert!(res.join().is_err());
+        });
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+    }
 
-        {
-            let l = reader.read().unwrap();
-            assert_eq!(l.view, b"asdfpqrs")
+    // Trickle accepts at most 3 bytes per write, and fails after `limit`.
+    struct Trickle {
+        data: Vec<u8>,
+        limit: usize,
+        interrupted: bool,
+    }
+    impl std::io::Write for Trickle {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            if self.data.len() == self.limit {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            let n = p.len().min(3).min(self.limit - self.data.len());
+            self.data.extend_from_slice(&p[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
+    }
 
+    #[test]
+    fn write_to_handles_partial_writes() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        let mut dst = Trickle {
+            data: Vec::new(),
+            limit: usize::MAX,
+            interrupted: false,
+        };
+        assert_eq!(reader.write_to(&mut dst).unwrap(), 8);
+        assert_eq!(dst.data, b"bbbbcccc");
         assert!(reader.read().is_none());
     }
 
     #[test]
-    fn write_during_read_lease() {
+    fn write_to_keeps_what_dst_rejected() {
         let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"asdfpqrs"));
+        let mut dst = Trickle {
+            data: Vec::new(),
+            limit: 5,
+            interrupted: true,
+        };
+        let err = reader.write_to(&mut dst).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(dst.data, b"asdfp");
+        assert_eq!(reader.read().unwrap().view, b"qrs");
+    }
 
-        assert!(writer.try_write(b"asdf"));
+    #[test]
+    fn read_from_commits_what_was_read() {
+        let (mut reader, mut writer) = create(10);
+        let mut src: &[u8] = b"hello world";
+        assert_eq!(writer.read_from(&mut src, 100).unwrap(), 10);
+        assert_eq!(src, b"d");
+        assert_eq!(
+            writer.read_from(&mut src, 100).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        assert_eq!(
+            reader.read_with(|p| (4, p.to_vec())).unwrap(),
+            b"hello worl"
+        );
+        // Only 4 bytes are free, at the start of the buffer.
+        let mut src: &[u8] = b"d!";
+        assert_eq!(writer.read_from(&mut src, 1).unwrap(), 1);
+        assert_eq!(reader.read().unwrap().view, b"o worl");
+        assert_eq!(reader.read().unwrap().view, b"d");
+    }
 
-        // The reader can see the first write, and is allowed
-        // to hold the lease even while writers continue to append.
-        let l = reader.read().unwrap();
-        assert_eq!(l.view, b"asdf");
-        assert!(writer.try_write(b"pqrs"));
-        assert_eq!(l.view, b"asdf");
+    #[test]
+    fn read_from_gives_back_unused_space() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaaaaa"));
+        assert_eq!(reader.read_with(|_| (4, ())), Some(()));
+        // Reading at most 4 bytes inverts the buffer, so EOF has to undo that.
+        let mut src: &[u8] = b"";
+        assert_eq!(writer.read_from(&mut src, 4).unwrap(), 0);
+        assert!(format!("{writer:?}").contains("inverted: false"));
+        assert!(writer.try_write(b"bb"));
+        assert_eq!(reader.read().unwrap().view, b"aaaabb");
+        assert_eq!(reader.stats().inversions, 0);
 
-        // Subsequent reads are needed to pick up the concurrent writes.
+        struct Broken;
+        impl std::io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::ConnectionReset.into())
+            }
+        }
+        assert!(writer.read_from(&mut Broken, 4).is_err());
+        assert!(reader.read().is_none());
+        assert!(writer.try_write(b"cc"));
+    }
+
+    #[test]
+    fn read_to_end_follows_the_inversion() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
         drop(l);
+        assert!(writer.try_write(b"cccc"));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+        let mut out = b"!".to_vec();
+        assert_eq!(reader.read_to_end_into(&mut out), 8);
+        assert_eq!(out, b"!bbbbcccc");
+        assert!(format!("{writer:?}").contains("inverted: false"));
+        assert_eq!(reader.read_to_end(), b"");
+        // The buffer is empty again, so a full-size write fits.
+        assert!(writer.try_write(b"dddddddddd"));
+        assert_eq!(reader.read_to_end(), b"dddddddddd");
+    }
+
+    #[test]
+    fn read_to_string_joins_characters_split_by_the_wrap() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
         let l = reader.read().unwrap();
-        assert_eq!(l.view, b"pqrs");
+        // 😀 is f0 9f 98 80.
+        assert!(writer.try_write(b"bb\xf0\x9f"));
+        drop(l);
+        let mut out = String::new();
+        assert_eq!(reader.read_to_string(&mut out), Ok(2));
+        assert!(writer.try_write(b"\x98"));
+        assert_eq!(reader.read_to_string(&mut out), Ok(0));
+        assert!(writer.try_write(b"\x80c"));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+        assert_eq!(reader.read_to_string(&mut out), Ok(5));
+        assert_eq!(out, "bb😀c");
+        assert!(reader.read().is_none());
     }
 
     #[test]
-    fn write_wraparound() {
+    fn read_to_string_stops_at_invalid_data() {
         let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"ok\xffok"));
+        let mut out = String::from(">");
+        assert_eq!(
+            reader.read_to_string(&mut out),
+            Err(Utf8Error { offset: 2 })
+        );
+        assert_eq!(out, ">ok");
+        assert_eq!(reader.read().unwrap().view, b"\xffok");
+    }
 
-        // We're to: write 5, read 5, write 4, write 4.
-        // The last write should wrap around.
-        // We should be able to confirm because `read()` will need to return twice.
+    #[test]
+    fn buf_read_lines_across_the_wrap() {
+        use std::io::BufRead;
+
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"one\ntwo\nthr"));
+        let mut line = String::new();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 4);
+        assert_eq!(reader.read_line(&mut line).unwrap(), 4);
+        assert_eq!(line, "one\ntwo\n");
+        // "thr" is pinned until the rest of its line shows up.
+        line.clear();
+        assert_eq!(reader.fill_buf().unwrap(), b"thr");
+        assert!(writer.try_write(b"ee\nfour\n"));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+        let lines: Vec<_> = reader.lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["three", "four"]);
+    }
+
+    #[test]
+    fn io_read_copies_whole_records() {
+        use std::io::Read;
+
+        let options = BufferOptions {
+            records: Some(4),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(16, options).unwrap();
+        assert!(writer.try_write(b"abc"));
+        assert!(writer.try_write(b"def"));
+        let mut buf = [0; 2];
+        let err = Read::read(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let mut buf = [0; 5];
+        assert_eq!(Read::read(&mut reader, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(Read::read(&mut reader, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"def");
+    }
 
+    #[test]
+    fn buf_read_lines_within_records() {
+        use std::io::BufRead;
+
+        let options = BufferOptions {
+            records: Some(4),
+            ..Default::default()
+        };
+        let (mut reader, mut writer) = create_with_options(16, options).unwrap();
+        assert!(writer.try_write(b"a\nb"));
+        assert!(writer.try_write(b"c\nd\n"));
+        let mut line = String::new();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 2);
+        assert_eq!(line, "a\n");
+        let lines: Vec<_> = (&mut reader).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["bc", "d"]);
+        // Nothing's left of either record.
+        assert!(writer.try_write(b"efgh"));
+        assert_eq!(reader.read_record().unwrap().unwrap().1.view, b"efgh");
+    }
+
+    #[test]
+    fn read_until_follows_the_wrap() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"aaaaaaaaaa"));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(b"b\ncc"));
+        drop(l);
+        assert!(writer.try_write(b"c\ndd"));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+
+        let l = reader.read_until(b'\n').unwrap();
+        assert_eq!((l.view, l.wrapped_view), (&b"b\n"[..], &b""[..]));
+        drop(l);
+        let l = reader.read_until(b'\n').unwrap();
+        assert_eq!((l.view, l.wrapped_view), (&b"cc"[..], &b"c\n"[..]));
+        drop(l);
+        assert!(reader.read_until(b'\n').is_none());
+        assert!(writer.try_write(b"\n"));
+        assert_eq!(reader.read_until(b'\n').unwrap().view, b"dd\n");
+        assert!(format!("{writer:?}").contains("unread: 0"));
+    }
+
+    #[test]
+    fn read_wrapped_takes_both_sides() {
+        let (mut reader, mut writer) = create(10);
         assert!(writer.try_write(b"aaaaa"));
         let l = reader.read().unwrap();
-        assert_eq!(l.view, b"aaaaa");
         assert!(writer.try_write(b"bbbb"));
         drop(l);
         assert!(writer.try_write(b"cccc"));
+        let l = reader.read_wrapped().unwrap();
+        assert_eq!((l.view, l.wrapped_view), (&b"bbbb"[..], &b"cccc"[..]));
+        drop(l);
+        assert!(reader.read_wrapped().is_none());
+        assert!(writer.try_write(&[1; 10]));
+    }
+
+    #[test]
+    fn chunks_cover_the_whole_lease() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"aaaaaaaaaaa"));
         let l = reader.read().unwrap();
-        assert_eq!(l.view, b"bbbb");
+        assert!(writer.try_write(b"bbbb"));
         drop(l);
+        assert!(writer.try_write(b"cccccc\n"));
+        let l = reader.read_until(b'\n').unwrap();
+        assert_eq!(l.len(), 11);
+        let chunks: Vec<_> = l.chunks(3).collect();
+        assert_eq!(chunks, [&b"bbb"[..], b"b", b"ccc", b"ccc", b"\n"]);
+        assert_eq!(chunks.concat().len(), l.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk size must be non-zero")]
+    fn chunks_must_be_non_empty() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"a"));
+        let _ = reader.read().unwrap().chunks(0);
+    }
+
+    #[test]
+    fn split_halves_release_in_order() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"hello world"));
+        let (first, second) = reader.read().unwrap().split_at(5);
+        assert_eq!((first.view, second.view), (&b"hello"[..], &b" world"[..]));
+        drop(second);
+        // Nothing is released while the first half is still leased.
+        assert_eq!(writer.stats().bytes_read, 0);
+        assert!(!writer.try_write(&[b'x'; 6]));
+        drop(first);
+        assert_eq!(writer.stats().bytes_read, 11);
+        assert!(writer.try_write(&[b'x'; 16]));
+        assert_eq!(reader.read_to_end(), [b'x'; 16]);
+    }
+
+    #[test]
+    fn split_across_the_wrap() {
+        for (mid, second_first) in [(2, false), (2, true), (6, true), (4, true), (11, true)] {
+            let (mut reader, mut writer) = create(16);
+            assert!(writer.try_write(b"aaaaaaaaaaa"));
+            let l = reader.read().unwrap();
+            assert!(writer.try_write(b"bbbb"));
+            drop(l);
+            assert!(writer.try_write(b"cccccc\n"));
+            let l = reader.read_until(b'\n').unwrap();
+            let (first, second) = l.split_at(mid);
+            let joined = |l: &Lease| [l.view, l.wrapped_view].concat();
+            assert_eq!([joined(&first), joined(&second)].concat(), b"bbbbcccccc\n");
+            assert_eq!(joined(&first).len(), mid);
+            if second_first {
+                drop(second);
+                assert_eq!(writer.stats().bytes_read, 11);
+                drop(first);
+            } else {
+                drop(first);
+                assert_eq!(writer.stats().bytes_read, 11 + mid);
+                drop(second);
+            }
+            assert_eq!(writer.stats().bytes_read, 22);
+            assert!(writer.try_write(&[b'x'; 16]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "mid > len")]
+    fn split_past_the_end() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(b"abc"));
+        let _ = reader.read().unwrap().split_at(4);
+    }
+
+    #[test]
+    fn drain_follows_the_wrap() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
         let l = reader.read().unwrap();
-        assert_eq!(l.view, b"cccc");
+        assert!(writer.try_write(b"bbbb"));
         drop(l);
-        assert!(reader.read().is_none());
+        assert!(writer.try_write(b"cccc"));
+        let mut out = Vec::new();
+        let mut drain = reader.drain_iter();
+        while let Some(lease) = drain.next_lease() {
+            out.push(lease.view.to_vec());
+            // The writer can't keep the drain going forever.
+            writer.try_write(b"d");
+        }
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0], b"bbbb");
+        assert!(out[1].starts_with(b"cccc"));
+        assert!(drain.next_lease().is_none());
+    }
+
+    #[test]
+    fn replay_returns_the_latest_reads() {
+        let options = BufferOptions {
+            history: 12,
+            ..BufferOptions::default()
+        };
+        let (mut reader, mut writer) = create_with_options(10, options).unwrap();
+        let mut stream = Vec::new();
+        for i in 0..20u8 {
+            let p = [i; 3];
+            assert!(writer.try_write(&p));
+            stream.extend(p);
+            if i % 2 == 1 {
+                reader.read_to_end();
+            }
+        }
+        assert_eq!(reader.replay(100), stream[stream.len() - 12..]);
+        assert_eq!(reader.replay(4), stream[stream.len() - 4..]);
+        // Unread data isn't history yet.
+        assert!(writer.try_write(b"x"));
+        assert_eq!(reader.replay(1), [19]);
+        assert_eq!(reader.read_to_end(), b"x");
+        assert_eq!(reader.replay(2), [19, b'x']);
+
+        let (mut reader, _) = create(10);
+        assert!(reader.replay(100).is_empty());
+    }
+
+    #[test]
+    fn lock_free_writes_when_idle() {
+        let (mut reader, mut writer) = create(10);
+        let fast = |reader: &Reader| reader.0.fast.load(Ordering::Relaxed);
+
+        // The first write goes through the lock, and leaves the fast path
+        // enabled once the reader catches up.
+        assert!(writer.try_write(b"asdf"));
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+        assert_eq!(fast(&reader), FAST_IDLE);
+
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(fast(&reader), FAST_COMMITTED + 4);
+        // Subsequent writes go through the lock, and see the first one.
+        assert!(writer.try_write(b"xyz"));
+        assert_eq!(fast(&reader), FAST_OFF);
+        assert!(!writer.try_write(b"0123"));
+        assert_eq!(reader.read().unwrap().view, b"pqrsxyz");
+
+        // Too-large writes fail without claiming the buffer.
+        assert_eq!(fast(&reader), FAST_IDLE);
+        assert!(!writer.try_write(&[0; 11]));
+        assert_eq!(fast(&reader), FAST_IDLE);
+        assert!(writer.try_write(&[1; 10]));
+        assert_eq!(reader.read().unwrap().view, [1; 10]);
+    }
+
+    #[test]
+    fn lock_free_writes_race_with_locked_ones() {
+        let (mut reader, writer) = create(64);
+        let total = 20_000;
+        std::thread::scope(|scope| {
+            for id in 0..2u8 {
+                let mut writer = writer.clone();
+                scope.spawn(move || {
+                    let mut sent = 0;
+                    while sent < total {
+                        if writer.try_write(&[id; 4]) {
+                            sent += 1;
+                        } else {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let mut counts = [0; 2];
+            while counts[0] + counts[1] < 2 * total * 4 {
+                match reader.read() {
+                    Some(l) => {
+                        for chunk in l.view.chunks(4) {
+                            assert!(chunk == [0; 4] || chunk == [1; 4]);
+                            counts[chunk[0] as usize] += 4;
+                        }
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+            assert_eq!(counts, [total * 4; 2]);
+        });
     }
 }
@@ -0,0 +1,82 @@
+// codec adds methods to buffer::Writer and buffer::Reader for integers in a
+// fixed byte order. Each write is a single write of the integer's bytes, so
+// it either fits whole or fails. Each read consumes exactly the integer's
+// bytes, copying them out even if they wrap around the end of the buffer, or
+// returns None (consuming nothing) if fewer than that are readable.
+//
+// They compose with everything else in the byte stream: e.g. a u32_be length
+// followed by a try_write of that many bytes.
+
+use crate::buffer::{Reader, Writer};
+
+macro_rules! codec {
+    ($($t:ty: $write_le:ident, $write_be:ident, $read_le:ident, $read_be:ident;)*) => {
+        impl Writer {
+            $(
+                pub fn $write_le(&mut self, v: $t) -> bool {
+                    self.try_write(&v.to_le_bytes())
+                }
+                pub fn $write_be(&mut self, v: $t) -> bool {
+                    self.try_write(&v.to_be_bytes())
+                }
+            )*
+        }
+        impl Reader {
+            $(
+                pub fn $read_le(&mut self) -> Option<$t> {
+                    self.read_array().map(<$t>::from_le_bytes)
+                }
+                pub fn $read_be(&mut self) -> Option<$t> {
+                    self.read_array().map(<$t>::from_be_bytes)
+                }
+            )*
+        }
+    };
+}
+codec! {
+    u16: write_u16_le, write_u16_be, read_u16_le, read_u16_be;
+    u32: write_u32_le, write_u32_be, read_u32_le, read_u32_be;
+    u64: write_u64_le, write_u64_be, read_u64_le, read_u64_be;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffer::create;
+
+    #[test]
+    fn byte_orders() {
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.write_u16_le(0x0102));
+        assert!(writer.write_u16_be(0x0102));
+        assert!(writer.write_u32_be(0x01020304));
+        assert_eq!(
+            reader.read().unwrap().view,
+            [2, 1, 1, 2, 1, 2, 3, 4].as_slice()
+        );
+        assert!(writer.write_u32_le(7));
+        assert!(writer.write_u64_be(8));
+        assert_eq!(reader.read_u32_le(), Some(7));
+        assert_eq!(reader.read_u64_be(), Some(8));
+        assert_eq!(reader.read_u16_le(), None);
+    }
+
+    #[test]
+    fn u64_split_by_the_wrap() {
+        let v: u64 = 0x0102030405060708;
+        let bytes = v.to_le_bytes();
+        let (mut reader, mut writer) = create(16);
+        assert!(writer.try_write(&[0; 13]));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(&bytes[..3]));
+        drop(l);
+        assert!(writer.try_write(&bytes[3..7]));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+        // Not all there yet, so nothing is consumed.
+        assert_eq!(reader.read_u64_le(), None);
+        assert!(writer.try_write(&bytes[7..]));
+        assert!(writer.write_u16_be(9));
+        assert_eq!(reader.read_u64_le(), Some(v));
+        assert_eq!(reader.read_u16_be(), Some(9));
+        assert!(reader.read().is_none());
+    }
+}
@@ -0,0 +1,65 @@
+// CRC32C (Castagnoli), as used for record checksums. This is the plain
+// table-driven algorithm: a byte at a time, with the table built at compile
+// time.
+
+const POLY: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// Crc32c accumulates a checksum over several slices, as if they were one.
+pub(crate) struct Crc32c(u32);
+impl Crc32c {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, p: &[u8]) {
+        for &b in p {
+            self.0 = TABLE[((self.0 ^ b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        let crc = |p: &[u8]| {
+            let mut c = Crc32c::new();
+            c.update(p);
+            c.finish()
+        };
+        assert_eq!(crc(b""), 0);
+        assert_eq!(crc(b"123456789"), 0xe306_9283);
+        assert_eq!(crc(&[0; 32]), 0x8a91_36aa);
+
+        let mut c = Crc32c::new();
+        c.update(b"1234");
+        c.update(b"56789");
+        assert_eq!(c.finish(), 0xe306_9283);
+    }
+}
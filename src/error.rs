@@ -0,0 +1,173 @@
+use core::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CreateError {
+    // A zero-capacity buffer can never accept a write.
+    ZeroCapacity,
+    // Allocations larger than isize::MAX bytes are not possible.
+    CapacityTooLarge(usize),
+    // Alignments must be a power of two.
+    InvalidAlignment(usize),
+    // The allocator couldn't provide the requested capacity.
+    OutOfMemory(usize),
+}
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::ZeroCapacity => write!(f, "capacity must be non-zero"),
+            CreateError::CapacityTooLarge(capacity) => {
+                write!(f, "capacity {capacity} exceeds isize::MAX")
+            }
+            CreateError::InvalidAlignment(align) => {
+                write!(f, "alignment {align} is not a power of two")
+            }
+            CreateError::OutOfMemory(capacity) => {
+                write!(f, "failed to allocate {capacity} bytes")
+            }
+        }
+    }
+}
+impl core::error::Error for CreateError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WriteError {
+    // There wasn't enough free space for the write, even after backing off.
+    Full,
+    // The reader was dropped, so a blocking write would never finish.
+    Disconnected,
+    // Writes were paused by the buffer's Control.
+    Paused,
+}
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Full => write!(f, "buffer is full"),
+            WriteError::Disconnected => write!(f, "reader is gone"),
+            WriteError::Paused => write!(f, "writes are paused"),
+        }
+    }
+}
+impl core::error::Error for WriteError {}
+#[cfg(feature = "std")]
+impl From<WriteError> for std::io::Error {
+    fn from(err: WriteError) -> Self {
+        let kind = match err {
+            WriteError::Full | WriteError::Paused => std::io::ErrorKind::WouldBlock,
+            WriteError::Disconnected => std::io::ErrorKind::BrokenPipe,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+// ResizeError is why a buffer couldn't be resized.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ResizeError {
+    // The reader holds a Lease, which has to stay where it is.
+    Leased,
+    // A Transaction is open, and its reservation has to stay where it is.
+    InTransaction,
+    // The unread data needs more room than the new capacity.
+    TooSmall { occupancy: usize },
+    // The buffer's storage can't be reallocated (e.g. it's a mapped file).
+    Unsupported,
+    // The new storage couldn't be set up.
+    Io(std::io::Error),
+}
+#[cfg(feature = "std")]
+impl fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResizeError::Leased => write!(f, "the reader holds a lease"),
+            ResizeError::InTransaction => write!(f, "a transaction is open"),
+            ResizeError::TooSmall { occupancy } => {
+                write!(f, "{occupancy} unread bytes wouldn't fit")
+            }
+            ResizeError::Unsupported => write!(f, "the buffer's storage can't be reallocated"),
+            ResizeError::Io(err) => write!(f, "failed to set up new storage: {err}"),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for ResizeError {}
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ResizeError {
+    fn from(err: std::io::Error) -> Self {
+        ResizeError::Io(err)
+    }
+}
+
+// ContiguousError is why Reader::read_contiguous couldn't lease `min` bytes.
+#[cfg(feature = "std")]
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContiguousError {
+    // Fewer than `min` bytes are unread in total.
+    Short { unread: usize },
+    // Enough bytes are unread, but they wrap around the end of the buffer,
+    // and only `contiguous` of them come before the wrap.
+    Fragmented { contiguous: usize, unread: usize },
+}
+#[cfg(feature = "std")]
+impl fmt::Display for ContiguousError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContiguousError::Short { unread } => write!(f, "only {unread} bytes are unread"),
+            ContiguousError::Fragmented { contiguous, unread } => write!(
+                f,
+                "only {contiguous} of {unread} unread bytes are contiguous"
+            ),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for ContiguousError {}
+
+// Utf8Error is returned by Reader::read_to_string when the data isn't valid
+// UTF-8. The invalid bytes come `offset` bytes into what that call read, and
+// are left unread.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Utf8Error {
+    pub offset: usize,
+}
+impl fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid UTF-8 after {} bytes", self.offset)
+    }
+}
+impl core::error::Error for Utf8Error {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecordError {
+    // The record with this sequence number failed its checksum, and was
+    // skipped.
+    Corrupt { seq: u64 },
+}
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Corrupt { seq } => write!(f, "record {seq} is corrupt"),
+        }
+    }
+}
+impl core::error::Error for RecordError {}
+
+pub(crate) fn validate_capacity(capacity: usize) -> Result<(), CreateError> {
+    if capacity == 0 {
+        return Err(CreateError::ZeroCapacity);
+    }
+    if capacity > isize::MAX as usize {
+        return Err(CreateError::CapacityTooLarge(capacity));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+impl From<CreateError> for std::io::Error {
+    fn from(err: CreateError) -> Self {
+        let kind = match err {
+            CreateError::OutOfMemory(_) => std::io::ErrorKind::OutOfMemory,
+            _ => std::io::ErrorKind::InvalidInput,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
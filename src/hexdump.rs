@@ -0,0 +1,52 @@
+use std::fmt::Write;
+
+// BYTES_PER_LINE is how many bytes each line of a dump shows.
+const BYTES_PER_LINE: usize = 16;
+
+// hexdump appends `p` to `out` in the classic `hexdump -C` layout: the offset
+// of each line, then its bytes in hex, then as ASCII (with `.` for anything
+// unprintable). Offsets start at `offset`, so that a dump can continue where
+// another left off.
+pub(crate) fn hexdump(out: &mut String, offset: usize, p: &[u8]) {
+    for (i, line) in p.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(out, "{:08x} ", offset + i * BYTES_PER_LINE);
+        for j in 0..BYTES_PER_LINE {
+            if j % 8 == 0 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, "{b:02x} ");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str(" |");
+        for &b in line {
+            out.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        out.push_str("|\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classic_layout() {
+        let mut out = String::new();
+        hexdump(&mut out, 0, b"hello, world\n\0\xffabcdef");
+        hexdump(&mut out, 0x20, b"xy");
+        assert_eq!(
+            out,
+            "00000000  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 0a 00 ff 61  |hello, world...a|\n\
+             00000010  62 63 64 65 66                                    |bcdef|\n\
+             00000020  78 79                                             |xy|\n"
+        );
+    }
+}
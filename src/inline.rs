@@ -0,0 +1,235 @@
+use core::{cell::RefCell, marker::PhantomData, ptr::NonNull};
+
+use crate::{
+    error::{CreateError, validate_capacity},
+    tracker::{ReadLease, Tracker},
+};
+
+// StaticBipBuffer keeps its storage inline, so it needs no allocation at all.
+// Its halves are borrows rather than owned handles, and can't leave the
+// thread that split it.
+pub struct StaticBipBuffer<const N: usize> {
+    tracker: RefCell<Tracker>,
+    data: [u8; N],
+}
+
+impl<const N: usize> StaticBipBuffer<N> {
+    pub fn new() -> Self {
+        const { assert!(N > 0, "StaticBipBuffer capacity must be non-zero") };
+        Self {
+            tracker: RefCell::new(Tracker::new(N)),
+            data: [0; N],
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn split(&mut self) -> (ReaderRef<'_>, WriterRef<'_>) {
+        let data = NonNull::from(&mut self.data).cast::<u8>();
+        let reader = ReaderRef {
+            tracker: &self.tracker,
+            data,
+            _marker: PhantomData,
+        };
+        let writer = WriterRef {
+            tracker: &self.tracker,
+            data,
+            _marker: PhantomData,
+        };
+        (reader, writer)
+    }
+}
+
+impl<const N: usize> Default for StaticBipBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SliceBipBuffer is StaticBipBuffer over storage that lives somewhere else,
+// e.g. a linker-placed `&'static mut [u8]` region.
+pub struct SliceBipBuffer<'a> {
+    tracker: RefCell<Tracker>,
+    data: &'a mut [u8],
+}
+
+impl<'a> SliceBipBuffer<'a> {
+    pub fn try_new(data: &'a mut [u8]) -> Result<Self, CreateError> {
+        validate_capacity(data.len())?;
+        Ok(Self {
+            tracker: RefCell::new(Tracker::new(data.len())),
+            data,
+        })
+    }
+
+    // new is like try_new, but panics if the storage is empty.
+    pub fn new(data: &'a mut [u8]) -> Self {
+        match Self::try_new(data) {
+            Ok(buf) => buf,
+            Err(err) => panic!("invalid buffer capacity: {err}"),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn split(&mut self) -> (ReaderRef<'_>, WriterRef<'_>) {
+        let data = NonNull::from(&mut *self.data).cast::<u8>();
+        let reader = ReaderRef {
+            tracker: &self.tracker,
+            data,
+            _marker: PhantomData,
+        };
+        let writer = WriterRef {
+            tracker: &self.tracker,
+            data,
+            _marker: PhantomData,
+        };
+        (reader, writer)
+    }
+}
+
+// The halves share `data` through a raw pointer; as with buffer.rs, every
+// access is vetted by the tracker so they never touch the same bytes at once.
+pub struct ReaderRef<'a> {
+    tracker: &'a RefCell<Tracker>,
+    data: NonNull<u8>,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+pub struct WriterRef<'a> {
+    tracker: &'a RefCell<Tracker>,
+    data: NonNull<u8>,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+impl WriterRef<'_> {
+    pub fn try_write(&mut self, p: &[u8]) -> bool {
+        let mut tracker = self.tracker.borrow_mut();
+        let Some(w) = tracker.write(p.len()) else {
+            return false;
+        };
+        unsafe {
+            let dst = self.data.as_ptr().add(w.start);
+            core::ptr::copy_nonoverlapping(p.as_ptr(), dst, w.len);
+        }
+        tracker.commit(w);
+        true
+    }
+}
+impl ReaderRef<'_> {
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        let r = self.tracker.borrow_mut().read()?;
+        let view = unsafe { core::slice::from_raw_parts(self.data.as_ptr().add(r.start), r.len) };
+        Some(Lease {
+            tracker: self.tracker,
+            lease: Some(r),
+            view,
+        })
+    }
+}
+
+pub struct Lease<'a> {
+    tracker: &'a RefCell<Tracker>,
+    lease: Option<ReadLease>,
+    pub view: &'a [u8],
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let lease = self.lease.take().expect("lease must persist until Drop");
+        self.tracker.borrow_mut().release(lease);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SliceBipBuffer, StaticBipBuffer};
+    use crate::CreateError;
+
+    #[test]
+    fn smoke() {
+        let mut buf = StaticBipBuffer::<10>::new();
+        let (mut reader, mut writer) = buf.split();
+
+        assert!(reader.read().is_none());
+
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+
+        {
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, b"asdfpqrs")
+        }
+
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn write_during_read_lease() {
+        let mut buf = StaticBipBuffer::<10>::new();
+        let (mut reader, mut writer) = buf.split();
+
+        assert!(writer.try_write(b"asdf"));
+
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"asdf");
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(l.view, b"asdf");
+
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"pqrs");
+    }
+
+    #[test]
+    fn write_wraparound() {
+        let mut buf = StaticBipBuffer::<10>::new();
+        let (mut reader, mut writer) = buf.split();
+
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"aaaaa");
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"bbbb");
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"cccc");
+        drop(l);
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn resplit_keeps_data() {
+        let mut buf = StaticBipBuffer::<10>::new();
+        assert_eq!(buf.capacity(), 10);
+        {
+            let (_, mut writer) = buf.split();
+            assert!(writer.try_write(b"asdf"));
+        }
+        let (mut reader, _) = buf.split();
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+    }
+
+    #[test]
+    fn slice_storage() {
+        let mut storage = [0; 10];
+        {
+            let mut buf = SliceBipBuffer::new(&mut storage);
+            assert_eq!(buf.capacity(), 10);
+            let (mut reader, mut writer) = buf.split();
+            assert!(writer.try_write(b"asdfpqrs"));
+            assert!(!writer.try_write(b"xyz"));
+            assert_eq!(reader.read().unwrap().view, b"asdfpqrs");
+        }
+        assert_eq!(&storage[..8], b"asdfpqrs");
+        assert_eq!(
+            SliceBipBuffer::try_new(&mut []).err(),
+            Some(CreateError::ZeroCapacity)
+        );
+    }
+}
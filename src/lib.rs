@@ -1,13 +1,123 @@
+// Without the `std` feature, only the allocation-free parts of the crate (the
+// tracker and the inline buffers) are available, and none of them can be
+// shared between threads or interrupt handlers.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
 // tracker is the underlying bipartite-buffer offset tracking.
 // It has no data and no I/O.
 pub mod tracker;
 
+// error has the errors shared by every kind of buffer.
+mod error;
+#[cfg(feature = "std")]
+pub use error::{ContiguousError, ResizeError};
+pub use error::{CreateError, RecordError, Utf8Error, WriteError};
+
+// crc computes the checksums for buffer records.
+#[cfg(feature = "crc")]
+mod crc;
+
+// hexdump formats bytes for Reader::debug_snapshot.
+#[cfg(feature = "debug-utils")]
+mod hexdump;
+
+// sync re-exports the atomics and locks used by the thread-safe buffers.
+#[cfg(feature = "std")]
+mod sync;
+
+// storage is the raw memory backing a buffer: allocation, alignment and
+// deallocation, but no knowledge of what's been written where.
+#[cfg(feature = "std")]
+mod storage;
+
 // buffer is the data buffer itself. It relies on the tracker
 // for safety.
 // It has data but no I/O.
+#[cfg(feature = "std")]
 pub mod buffer;
 
+// The thread-safe buffer is the one to reach for first, so its entry points
+// are also at the crate root:
+//
+//     let (mut reader, mut writer) = bbuf::create(1024);
+//     std::thread::spawn(move || writer.try_write(b"hello"));
+//     while let Some(lease) = reader.read() { ... }
+#[cfg(feature = "std")]
+pub use buffer::{Lease, Reader, Writer, create, try_create};
+
+// spsc is a lock-free variant of buffer for exactly one producer and one
+// consumer.
+// It has data but no I/O.
+#[cfg(feature = "std")]
+pub mod spsc;
+
+// mpsc is a lock-free variant of buffer for any number of producers and one
+// consumer. Unlike the others, it hands out one written message at a time.
+// It relies on 64-bit atomics, so it's only available on targets that have
+// them.
+// It has data but no I/O.
+#[cfg(all(feature = "std", target_has_atomic = "64"))]
+pub mod mpsc;
+
+// spmc is a variant of buffer for one producer and any number of consumers,
+// which each claim distinct parts of what's been written. Like mpsc, it needs
+// 64-bit atomics.
+// It has data but no I/O.
+#[cfg(all(feature = "std", target_has_atomic = "64"))]
+pub mod spmc;
+
+// local is a single-threaded buffer on the heap, without any locking.
+// It has data but no I/O.
+#[cfg(feature = "std")]
+pub mod local;
+
+// inline is a buffer whose storage lives inside the struct itself, with
+// borrowed, single-threaded halves.
+// It has data but no I/O.
+pub mod inline;
+
+// codec reads and writes fixed-size integers, e.g. length and id fields,
+// in either byte order.
+#[cfg(feature = "std")]
+pub mod codec;
+
+// typed is a buffer of fixed-size elements, like f32 samples, rather than
+// bytes. It's a thin layer over buffer.
+// It has data but no I/O.
+#[cfg(feature = "std")]
+pub mod typed;
+
+// line has a Writer wrapper that only publishes whole lines of text.
+// It has data but no I/O.
+#[cfg(feature = "std")]
+pub mod line;
+
 // sink has logic to spawn a dedicated thread to continuously and eagerly
 // drain a buffer into an underlying provided std::io::Write sink.
 // It has both data and I/O.
+#[cfg(feature = "std")]
 pub mod sink;
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    #[test]
+    fn root_producer_consumer() {
+        let (mut reader, mut writer): (crate::Reader, crate::Writer) = crate::create(16);
+        let producer = std::thread::spawn(move || {
+            for i in 0..100u8 {
+                while !writer.try_write(&[i]) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut got = Vec::new();
+        while got.len() < 100 {
+            let lease: Option<crate::Lease> = reader.read();
+            if let Some(lease) = lease {
+                got.extend_from_slice(lease.view);
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(got, (0..100).collect::<Vec<u8>>());
+    }
+}
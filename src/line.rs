@@ -0,0 +1,160 @@
+// line has a Writer wrapper for text that's produced in fragments (e.g. by
+// `write!`) but should reach the reader a whole line at a time.
+
+use std::io;
+
+use crate::buffer::Writer;
+
+// DEFAULT_MAX_LINE is how long a line can get before LineWriter::new gives
+// up waiting for its end.
+const DEFAULT_MAX_LINE: usize = 4096;
+
+// LineWriter stages what's written to it, and only writes it to the buffer
+// once it's seen a newline: everything up to and including the last one, as
+// a single write. A line that grows to `max_line` bytes without ending is
+// written anyway, so staging never grows without bound.
+//
+// If the buffer doesn't have room, a `write` fails with the error from
+// `Writer::try_write_many` (e.g. WouldBlock when it's full), and whatever it
+// couldn't publish of its input is left for the caller to retry, as with
+// any io::Write. `flush` doesn't write a partial line, since that's the
+// point; use `flush_partial` for that. Dropping a LineWriter tries to
+// write whatever's left.
+#[derive(Debug)]
+pub struct LineWriter {
+    writer: Writer,
+    staged: Vec<u8>,
+    max_line: usize,
+}
+
+impl LineWriter {
+    pub fn new(writer: Writer) -> Self {
+        Self::with_max_line(writer, DEFAULT_MAX_LINE)
+    }
+
+    pub fn with_max_line(writer: Writer, max_line: usize) -> Self {
+        Self {
+            writer,
+            staged: Vec::new(),
+            max_line,
+        }
+    }
+
+    // flush_partial writes whatever's staged, even if it doesn't end in a
+    // newline, e.g. on shutdown.
+    pub fn flush_partial(&mut self) -> io::Result<()> {
+        self.put(self.staged.len())
+    }
+
+    // put writes the first `n` staged bytes as a single write.
+    fn put(&mut self, n: usize) -> io::Result<()> {
+        if n == 0 {
+            return Ok(());
+        }
+        self.writer.try_write_many(&[&self.staged[..n]])?;
+        self.staged.drain(..n);
+        Ok(())
+    }
+}
+
+impl io::Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let before = self.staged.len();
+        self.staged.extend_from_slice(buf);
+        let mut published = 0;
+        let mut res = Ok(());
+        if let Some(i) = self.staged.iter().rposition(|&b| b == b'\n') {
+            res = self.put(i + 1);
+            if res.is_ok() {
+                published = i + 1;
+            }
+        }
+        if res.is_ok() && self.staged.len() >= self.max_line {
+            let n = self.staged.len();
+            res = self.put(n);
+            if res.is_ok() {
+                published += n;
+            }
+        }
+        match res {
+            Ok(()) => Ok(buf.len()),
+            Err(err) => {
+                // Give back whatever of `buf` wasn't published, so that the
+                // caller can retry it.
+                let accepted = published.saturating_sub(before);
+                self.staged
+                    .truncate(self.staged.len() - (buf.len() - accepted));
+                if accepted > 0 { Ok(accepted) } else { Err(err) }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LineWriter {
+    fn drop(&mut self) {
+        let _ = self.flush_partial();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use crate::buffer::create;
+
+    #[test]
+    fn fragments_make_whole_lines() {
+        let (mut reader, writer) = create(64);
+        let mut w = LineWriter::new(writer);
+        write!(w, "hello, ").unwrap();
+        write!(w, "{}", 42).unwrap();
+        assert!(reader.read().is_none());
+        write!(w, "!\nnext").unwrap();
+        assert_eq!(reader.read_to_end(), b"hello, 42!\n");
+        write!(w, " line\nand a third\n").unwrap();
+        assert_eq!(reader.read_to_end(), b"next line\nand a third\n");
+    }
+
+    #[test]
+    fn long_lines_are_forced_out() {
+        let (mut reader, writer) = create(64);
+        let mut w = LineWriter::with_max_line(writer, 8);
+        w.write_all(b"abcde").unwrap();
+        assert!(reader.read().is_none());
+        w.write_all(b"fgh").unwrap();
+        assert_eq!(reader.read_to_end(), b"abcdefgh");
+        w.write_all(b"ij\nklmnopqrstu").unwrap();
+        assert_eq!(reader.read_to_end(), b"ij\nklmnopqrstu");
+    }
+
+    #[test]
+    fn partial_line_waits_for_flush() {
+        let (mut reader, writer) = create(64);
+        let mut w = LineWriter::new(writer);
+        w.write_all(b"done\nhalf").unwrap();
+        w.flush().unwrap();
+        assert_eq!(reader.read_to_end(), b"done\n");
+        w.flush_partial().unwrap();
+        assert_eq!(reader.read_to_end(), b"half");
+        w.write_all(b"tail").unwrap();
+        drop(w);
+        assert_eq!(reader.read_to_end(), b"tail");
+    }
+
+    #[test]
+    fn full_buffer_keeps_the_input() {
+        let (mut reader, writer) = create(4);
+        let mut w = LineWriter::new(writer);
+        w.write_all(b"ab").unwrap();
+        let err = w.write(b"cdef\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(reader.read().is_none());
+        w.write_all(b"c\n").unwrap();
+        assert_eq!(reader.read_to_end(), b"abc\n");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    error::{CreateError, WriteError, validate_capacity},
+    tracker::{ReadLease, Tracker},
+};
+
+// Buffer is a single-threaded buffer on the heap, with no locking or
+// reference counting at all. A Lease borrows the whole Buffer, so nothing
+// can be written until it's been dropped; for a reader and writer that take
+// turns with leases outstanding, see inline.
+pub struct Buffer {
+    tracker: Tracker,
+    data: Box<[u8]>,
+}
+
+impl Buffer {
+    pub fn try_new(capacity: usize) -> Result<Self, CreateError> {
+        validate_capacity(capacity)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(capacity)
+            .map_err(|_| CreateError::OutOfMemory(capacity))?;
+        data.resize(capacity, 0);
+        Ok(Self {
+            tracker: Tracker::new(capacity),
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    // new is like try_new, but panics if the capacity is invalid.
+    pub fn new(capacity: usize) -> Self {
+        match Self::try_new(capacity) {
+            Ok(buf) => buf,
+            Err(err) => panic!("invalid buffer capacity: {err}"),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    // clear discards all unread data and zeroes the storage, so that nothing
+    // written before can show up again. A Lease borrows the Buffer, so none
+    // can be outstanding.
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+        self.clear_fast();
+    }
+
+    // clear_fast is clear without the zeroing.
+    pub fn clear_fast(&mut self) {
+        self.tracker.clear();
+    }
+
+    pub fn write(&mut self, p: &[u8]) -> Result<(), WriteError> {
+        let w = self.tracker.write(p.len()).ok_or(WriteError::Full)?;
+        self.data[w.start..w.start + w.len].copy_from_slice(p);
+        self.tracker.commit(w);
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        let r = self.tracker.read()?;
+        Some(Lease {
+            view: &self.data[r.start..r.start + r.len],
+            tracker: &mut self.tracker,
+            lease: Some(r),
+        })
+    }
+}
+
+impl core::fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Buffer")
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+// Lease is the next readable segment of a Buffer. Dropping it consumes all
+// of it; `release` consumes only part of it.
+pub struct Lease<'a> {
+    tracker: &'a mut Tracker,
+    lease: Option<ReadLease>,
+    pub view: &'a [u8],
+}
+impl Lease<'_> {
+    // release consumes the first `n` bytes of the Lease, leaving the rest
+    // to be read again. It panics if `n` is greater than the length.
+    pub fn release(mut self, n: usize) {
+        let lease = self.lease.as_mut().expect("lease must persist until Drop");
+        assert!(n <= lease.len, "released {n} of {} bytes", lease.len);
+        lease.len = n;
+    }
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let lease = self.lease.take().expect("lease must persist until Drop");
+        self.tracker.release(lease);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let mut buf = Buffer::new(10);
+        assert!(buf.read().is_none());
+        buf.write(b"asdf").unwrap();
+        buf.write(b"pqrs").unwrap();
+        assert_eq!(buf.read().unwrap().view, b"asdfpqrs");
+        assert!(buf.read().is_none());
+    }
+
+    #[test]
+    fn write_wraparound() {
+        let mut buf = Buffer::new(10);
+        buf.write(b"aaaaa").unwrap();
+        buf.write(b"bbb").unwrap();
+        buf.read().unwrap().release(5);
+        buf.write(b"cc").unwrap();
+        // This only fits at the start, in front of the unread data.
+        buf.write(b"dddd").unwrap();
+        assert_eq!(buf.write(b"ee"), Err(WriteError::Full));
+        assert_eq!(buf.read().unwrap().view, b"bbbcc");
+        assert_eq!(buf.read().unwrap().view, b"dddd");
+        assert!(buf.read().is_none());
+    }
+
+    #[test]
+    fn writes_that_exactly_fit() {
+        let mut buf = Buffer::new(10);
+        buf.write(&[1; 10]).unwrap();
+        assert_eq!(buf.write(&[2]), Err(WriteError::Full));
+        assert_eq!(buf.read().unwrap().view, [1; 10]);
+        buf.write(&[3; 10]).unwrap();
+    }
+
+    #[test]
+    fn release_leaves_the_rest_unread() {
+        let mut buf = Buffer::new(10);
+        buf.write(b"abcdef").unwrap();
+        buf.read().unwrap().release(0);
+        buf.read().unwrap().release(2);
+        assert_eq!(buf.read().unwrap().view, b"cdef");
+    }
+
+    #[test]
+    #[should_panic(expected = "released 7 of 6 bytes")]
+    fn release_past_the_end() {
+        let mut buf = Buffer::new(10);
+        buf.write(b"abcdef").unwrap();
+        buf.read().unwrap().release(7);
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut buf = Buffer::new(10);
+        buf.write(b"old").unwrap();
+        buf.clear_fast();
+        buf.write(b"new").unwrap();
+        assert_eq!(buf.read().unwrap().view, b"new");
+
+        buf.write(b"secret").unwrap();
+        buf.read().unwrap().release(3);
+        buf.clear();
+        assert_eq!(*buf.data, [0; 10]);
+        assert!(buf.read().is_none());
+        buf.write(b"fresh").unwrap();
+        assert_eq!(buf.read().unwrap().view, b"fresh");
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_capacity() {
+        assert_eq!(Buffer::try_new(0).err(), Some(CreateError::ZeroCapacity));
+        assert_eq!(
+            Buffer::try_new(usize::MAX).err(),
+            Some(CreateError::CapacityTooLarge(usize::MAX))
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use crossbeam::utils::CachePadded;
+
+use crate::{
+    error::{CreateError, validate_capacity},
+    storage::Storage,
+    sync::{Arc, AtomicU64, Ordering},
+};
+
+// Shared is the multi-producer counterpart of spsc.rs's Shared. Producers
+// can't take turns publishing a single write offset, so instead every write
+// is framed as a record: an 8-byte header followed by the payload, padded out
+// to a multiple of 8 bytes.
+//
+//   - Producers claim space by advancing `reserve` with a CAS, fill in their
+//     payload, and then publish the record by storing its header (Release).
+//     Records can be published in any order.
+//   - The consumer walks the records starting at `read`, and stops at the
+//     first header that hasn't been published, so a record that's still
+//     being written hides everything reserved after it.
+//   - The consumer zeroes each record as it's released, before advancing
+//     `read` (Release). So everything outside of the unread records is zero,
+//     and in particular an unpublished header always reads as 0.
+//
+// `reserve` and `read` count bytes since the buffer was created rather than
+// offsets, so `reserve - read` is always the number of bytes in use. When a
+// record doesn't fit in the space left before the end of the buffer, its
+// producer also reserves that space and fills it with a padding record.
+struct Shared {
+    reserve: CachePadded<AtomicU64>,
+    read: CachePadded<AtomicU64>,
+    capacity: usize,
+    data: Storage,
+}
+
+// Headers are 0 until published. Once published, they hold the payload's
+// length and the PUBLISHED bit, or just the PADDING and PUBLISHED bits for a
+// padding record that runs to the end of the buffer.
+const HEADER: usize = 8;
+const PUBLISHED: u64 = 1 << 63;
+const PADDING: u64 = 1 << 62;
+const LEN_MASK: u64 = PADDING - 1;
+
+// record_size is how much space a record with an `len`-byte payload takes up.
+fn record_size(len: usize) -> usize {
+    HEADER + len.next_multiple_of(HEADER)
+}
+
+// Reader and Writer mirror buffer.rs. Writers can be cloned and used from
+// any number of threads at once without ever blocking each other.
+pub struct Reader(Arc<Shared>);
+#[derive(Clone)]
+pub struct Writer(Arc<Shared>);
+
+// try_create creates a buffer with room for `capacity` bytes, rounded up to a
+// multiple of 8. Each message takes up 8 bytes more than its length, rounded
+// up to a multiple of 8.
+pub fn try_create(capacity: usize) -> Result<(Reader, Writer), CreateError> {
+    validate_capacity(capacity)?;
+    let capacity = capacity
+        .checked_next_multiple_of(HEADER)
+        .ok_or(CreateError::CapacityTooLarge(capacity))?;
+    validate_capacity(capacity)?;
+    let mut data = Storage::alloc(capacity, HEADER)?;
+    // Producers write concurrently, so the storage has to be fully
+    // initialized up front (see Storage::write). It also has to start out
+    // zeroed, which is exactly what prefault does.
+    data.prefault();
+    let shared = Arc::new(Shared {
+        reserve: CachePadded::new(AtomicU64::new(0)),
+        read: CachePadded::new(AtomicU64::new(0)),
+        capacity,
+        data,
+    });
+    Ok((Reader(shared.clone()), Writer(shared)))
+}
+
+// create is like try_create, but panics if the capacity is invalid.
+pub fn create(capacity: usize) -> (Reader, Writer) {
+    match try_create(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+impl Shared {
+    fn header(&self, offset: usize) -> &AtomicU64 {
+        // Headers always start at multiples of 8, and the storage is
+        // 8-aligned and zeroed up front.
+        unsafe { self.data.atomic_u64(offset) }
+    }
+}
+
+impl Writer {
+    // try_write writes `p` as a single message, or returns false if there's
+    // not enough room for it.
+    pub fn try_write(&mut self, p: &[u8]) -> bool {
+        let s = &*self.0;
+        let size = record_size(p.len());
+        let mut reserve = s.reserve.load(Ordering::Relaxed);
+        let (offset, padding) = loop {
+            // `read` only ever grows, so a stale value just means we may see
+            // less free space than there really is. Acquire makes sure the
+            // consumer is done zeroing the space it freed.
+            let read = s.read.load(Ordering::Acquire);
+            let offset = (reserve % s.capacity as u64) as usize;
+            let tail = s.capacity - offset;
+            let padding = if size <= tail { 0 } else { tail };
+            let end = reserve + (padding + size) as u64;
+            if end - read > s.capacity as u64 {
+                return false;
+            }
+            match s.reserve.compare_exchange_weak(
+                reserve,
+                end,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break (offset, padding),
+                Err(actual) => reserve = actual,
+            }
+        };
+
+        let start = if padding > 0 {
+            s.header(offset)
+                .store(PUBLISHED | PADDING, Ordering::Release);
+            0
+        } else {
+            offset
+        };
+        unsafe { s.data.write(start + HEADER, p) };
+        s.header(start)
+            .store(PUBLISHED | p.len() as u64, Ordering::Release);
+        true
+    }
+}
+
+impl Reader {
+    // read returns the oldest unread message, or None if it hasn't been
+    // published yet (even if later messages have been).
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        let s = &*self.0;
+        let mut read = s.read.load(Ordering::Relaxed);
+        loop {
+            let offset = (read % s.capacity as u64) as usize;
+            let header = s.header(offset).load(Ordering::Acquire);
+            if header == 0 {
+                return None;
+            }
+            if header & PADDING != 0 {
+                s.header(offset).store(0, Ordering::Relaxed);
+                read += (s.capacity - offset) as u64;
+                s.read.store(read, Ordering::Release);
+                continue;
+            }
+            let len = (header & LEN_MASK) as usize;
+            let view = unsafe { s.data.slice(offset + HEADER, len) };
+            return Some(Lease {
+                shared: s,
+                offset,
+                view,
+            });
+        }
+    }
+}
+
+// Lease is a single message. Dropping it frees up the space it used.
+pub struct Lease<'a> {
+    shared: &'a Shared,
+    offset: usize,
+    pub view: &'a [u8],
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let s = self.shared;
+        let size = record_size(self.view.len());
+        s.header(self.offset).store(0, Ordering::Relaxed);
+        unsafe { s.data.zero(self.offset + HEADER, size - HEADER) };
+        s.read.fetch_add(size as u64, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let (mut reader, mut writer) = create(64);
+
+        assert!(reader.read().is_none());
+
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+
+        assert_eq!(reader.read().unwrap().view, b"asdf");
+        assert_eq!(reader.read().unwrap().view, b"pqrs");
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn capacity_counts_headers() {
+        assert_eq!(try_create(0).err(), Some(CreateError::ZeroCapacity));
+        let (mut reader, mut writer) = create(30);
+        // 30 rounds up to 32 bytes, which fits one record with up to 24 bytes
+        // of payload.
+        assert!(!writer.try_write(&[0; 25]));
+        assert!(writer.try_write(&[1; 24]));
+        assert!(!writer.try_write(&[]));
+        assert_eq!(reader.read().unwrap().view, [1; 24]);
+
+        assert!(writer.try_write(&[2; 9]));
+        // A 1-byte message needs 16 bytes, but there are only 8 left before
+        // the end of the buffer. Those 8 bytes are only enough for an empty
+        // message.
+        assert!(!writer.try_write(&[3]));
+        assert!(writer.try_write(&[]));
+        assert_eq!(reader.read().unwrap().view, [2; 9]);
+        assert_eq!(reader.read().unwrap().view, b"");
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn write_during_read_lease() {
+        let (mut reader, mut writer) = create(64);
+
+        assert!(writer.try_write(b"asdf"));
+
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"asdf");
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(l.view, b"asdf");
+
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"pqrs");
+    }
+
+    #[test]
+    fn write_wraparound() {
+        let (mut reader, mut writer) = create(48);
+
+        assert!(writer.try_write(&[1; 16]));
+        assert!(writer.try_write(&[2; 4]));
+        drop(reader.read().unwrap());
+        // There are 16 bytes free at the end, which isn't enough, so this
+        // pads them out and writes at the start instead.
+        assert!(writer.try_write(&[3; 12]));
+        // The padding and [3; 12] take up all the free space.
+        assert!(!writer.try_write(&[]));
+        assert_eq!(reader.read().unwrap().view, [2; 4]);
+        assert_eq!(reader.read().unwrap().view, [3; 12]);
+        assert!(reader.read().is_none());
+        assert!(writer.try_write(&[4; 16]));
+        assert_eq!(reader.read().unwrap().view, [4; 16]);
+    }
+
+    #[test]
+    fn unpublished_records_hide_later_ones() {
+        let (mut reader, mut writer) = create(64);
+        let s = writer.0.clone();
+        // Simulate a producer that has reserved space for [1; 4] but hasn't
+        // published it yet.
+        s.reserve.store(record_size(4) as u64, Ordering::Relaxed);
+        assert!(writer.try_write(b"pqrs"));
+        assert!(reader.read().is_none());
+
+        unsafe { s.data.write(HEADER, &[1; 4]) };
+        s.header(0).store(PUBLISHED | 4, Ordering::Release);
+        assert_eq!(reader.read().unwrap().view, [1; 4]);
+        assert_eq!(reader.read().unwrap().view, b"pqrs");
+    }
+
+    #[test]
+    fn many_producers() {
+        let (mut reader, writer) = create(256);
+        let producers: u8 = 4;
+        let total: usize = 20_000;
+        std::thread::scope(|scope| {
+            for id in 0..producers {
+                let mut writer = writer.clone();
+                scope.spawn(move || {
+                    let mut i = 0;
+                    while i < total {
+                        // Vary the length so records wrap at different
+                        // places.
+                        let msg = [id; 16];
+                        if writer.try_write(&msg[..1 + i % 16]) {
+                            i += 1;
+                        } else {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+            let mut next = vec![0; producers as usize];
+            while next.iter().sum::<usize>() < producers as usize * total {
+                match reader.read() {
+                    Some(l) => {
+                        let id = l.view[0] as usize;
+                        // Each producer's messages arrive in order.
+                        assert_eq!(l.view, &[id as u8; 16][..1 + next[id] % 16]);
+                        next[id] += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert!(reader.read().is_none());
+    }
+}
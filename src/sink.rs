@@ -1,63 +1,3499 @@
-use crossbeam::channel::Sender;
+use std::{
+    io::IoSlice,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{JoinHandle, ScopedJoinHandle},
+    time::{Duration, Instant},
+};
 
-use crate::buffer;
+use crossbeam::channel::{Receiver, Sender, TrySendError, at, never};
 
+use crate::{
+    buffer::{self, BufferOptions, CreateError, OverflowPolicy, WriteError},
+    spsc,
+    storage::Storage,
+};
+
+// rotate has a size-capped log file to use as a sink's inner writer.
+mod rotate;
+pub use rotate::RotatingFile;
+
+// The sink can run on top of either the Mutex-based buffer or the lock-free
+// SPSC one. These traits are sealed: they only exist to abstract over those
+// two.
+mod sealed {
+    use std::{
+        sync::{Arc, OnceLock},
+        time::Instant,
+    };
+
+    pub trait Produce {
+        fn try_write(&mut self, p: &[u8]) -> bool;
+        // overwritten is how many unread bytes have been discarded to make
+        // room for writes (see OverflowPolicy::Overwrite).
+        fn overwritten(&self) -> usize {
+            0
+        }
+    }
+    pub trait Consume: Send {
+        // drain passes every currently readable region to `f`, in order. If
+        // a region wraps around the end of the buffer, the second slice is
+        // the part after the wrap; otherwise it's empty.
+        fn drain(&mut self, f: &mut dyn FnMut(&[u8], &[u8]));
+    }
+    // Output is what the sink thread does with the data it drains.
+    pub trait Output: Send {
+        type Done: Send;
+        type Inner: Send;
+        fn put(&mut self, p: &[u8]);
+        // put_wrapped puts `head` and then `tail`.
+        fn put_wrapped(&mut self, head: &[u8], tail: &[u8]) {
+            self.put(head);
+            if !tail.is_empty() {
+                self.put(tail);
+            }
+        }
+        // abandon counts `len` bytes that were never put, because a
+        // shutdown's deadline passed first.
+        fn abandon(&mut self, _len: usize) {}
+        // watch hands over the shutdown deadline (see Sink::shutdown_timeout),
+        // so that a write that keeps failing can be given up on once it
+        // passes.
+        fn watch(&mut self, _deadline: Arc<OnceLock<Instant>>) {}
+        // flush passes on whatever's been put so far, as far as it can.
+        fn flush(&mut self) -> std::io::Result<()>;
+        // replace flushes the inner writer and swaps `new` in for it. If the
+        // flush fails, nothing is swapped.
+        fn replace(&mut self, new: Self::Inner) -> std::io::Result<Self::Inner>;
+        fn finish(self) -> Self::Done;
+    }
+}
+use sealed::{Consume, Output, Produce};
+
+// Plain passes data straight through to the inner writer, keeping track of
+// how that went for Sink::shutdown, and passing each failure to `on_error`.
+struct Plain<W, E = fn(&WriteFailure)> {
+    inner: W,
+    written: u64,
+    // records is how many records Datagrams has written.
+    records: u64,
+    error: Option<std::io::Error>,
+    // dropped and failures are how many bytes have been lost, and in how
+    // many failed writes.
+    dropped: u64,
+    failures: u64,
+    // abandoned is how many bytes were never put, for a shutdown deadline.
+    abandoned: u64,
+    consecutive: usize,
+    on_error: E,
+    retry: RetryPolicy,
+    // persist retries every write that fails with a retryable error until
+    // it goes through, or the shutdown deadline passes, with the retry
+    // policy's delays but none of its limits.
+    persist: bool,
+    deadline: Arc<OnceLock<Instant>>,
+}
+impl<W> Plain<W> {
+    fn new(inner: W) -> Self {
+        Self::with_error_handler(inner, |_| {})
+    }
+}
+impl<W, E: FnMut(&WriteFailure)> Plain<W, E> {
+    fn with_error_handler(inner: W, on_error: E) -> Self {
+        Self {
+            inner,
+            written: 0,
+            records: 0,
+            error: None,
+            dropped: 0,
+            failures: 0,
+            abandoned: 0,
+            consecutive: 0,
+            on_error,
+            retry: RetryPolicy::default(),
+            persist: false,
+            deadline: Arc::default(),
+        }
+    }
+
+    // write_with passes a `len`-byte write to the inner writer, one `step`
+    // at a time, each of which writes as much of what's left after the
+    // first `done` bytes as it can, and says how much that was. Errors are
+    // retried as the retry policy says; after that, what's left is dropped.
+    fn write_with(
+        &mut self,
+        len: usize,
+        mut step: impl FnMut(&mut W, usize) -> std::io::Result<usize>,
+    ) {
+        let (mut done, mut failures) = (0, 0);
+        while done < len {
+            let err = match step(&mut self.inner, done) {
+                Ok(0) => std::io::ErrorKind::WriteZero.into(),
+                Ok(n) => {
+                    done += n;
+                    failures = 0;
+                    continue;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => err,
+            };
+            failures += 1;
+            let until = self.deadline.get().copied();
+            let retry = if self.persist && self.retry.retryable.contains(&err.kind()) {
+                self.retry.pause(failures, until);
+                true
+            } else {
+                self.retry.wait(&err, failures, until)
+            };
+            if !retry {
+                self.written += done as u64;
+                return self.fail(err, len - done);
+            }
+            // Past the shutdown deadline, what's left is abandoned rather
+            // than retried.
+            if passed(&self.deadline) {
+                self.written += done as u64;
+                self.abandoned += (len - done) as u64;
+                return;
+            }
+        }
+        self.written += len as u64;
+        self.consecutive = 0;
+    }
+
+    // fail records a write of `len` bytes that failed with `error`.
+    fn fail(&mut self, error: std::io::Error, len: usize) {
+        self.dropped += len as u64;
+        self.failures += 1;
+        self.consecutive += 1;
+        let failure = WriteFailure {
+            error,
+            len,
+            consecutive: self.consecutive,
+        };
+        (self.on_error)(&failure);
+        self.error.get_or_insert(failure.error);
+    }
+}
+impl<W: std::io::Write + Send, E: FnMut(&WriteFailure) + Send> Output for Plain<W, E> {
+    type Done = Finished<W>;
+    type Inner = W;
+    fn put(&mut self, p: &[u8]) {
+        self.write_with(p.len(), |inner, done| inner.write(&p[done..]));
+    }
+    // put_wrapped passes both parts to the inner writer together, as a
+    // vectored write, so that a writer that supports those (like a File) can
+    // take them in a single call. Others write them one at a time anyway.
+    fn put_wrapped(&mut self, head: &[u8], tail: &[u8]) {
+        if tail.is_empty() {
+            return self.put(head);
+        }
+        let mut slices = [IoSlice::new(head), IoSlice::new(tail)];
+        let mut bufs = &mut slices[..];
+        self.write_with(head.len() + tail.len(), |inner, _| {
+            let n = inner.write_vectored(bufs)?;
+            IoSlice::advance_slices(&mut bufs, n);
+            Ok(n)
+        });
+    }
+    fn abandon(&mut self, len: usize) {
+        self.abandoned += len as u64;
+    }
+    fn watch(&mut self, deadline: Arc<OnceLock<Instant>>) {
+        self.deadline = deadline;
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+    fn replace(&mut self, new: W) -> std::io::Result<W> {
+        self.inner.flush()?;
+        Ok(std::mem::replace(&mut self.inner, new))
+    }
+    fn finish(mut self) -> Self::Done {
+        if let Err(err) = self.inner.flush() {
+            self.error.get_or_insert(err);
+        }
+        Finished {
+            inner: self.inner,
+            written: self.written,
+            records: self.records,
+            abandoned: self.abandoned,
+            error: self.error,
+            destinations: Vec::new(),
+        }
+    }
+}
+
+// RetryPolicy is how a sink retries writes to its inner writer that fail
+// with an error that might go away, e.g. WouldBlock from a non-blocking
+// socket. The sink thread holds on to the data while it waits, so if the
+// buffer fills up in the meantime, writes through its Handles fail (or
+// block, under OverflowPolicy::Block). By default, nothing is retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // max_attempts is how many times a write is tried before its data is
+    // dropped, counting the first try. Progress starts the count over.
+    pub max_attempts: u32,
+    // delay is how long to wait before the first retry. Every retry after
+    // that waits twice as long as the one before, up to max_delay.
+    pub delay: Duration,
+    pub max_delay: Duration,
+    // retryable is which errors are worth retrying; the rest fail straight
+    // away. Interrupted writes are always retried, straight away.
+    pub retryable: Vec<std::io::ErrorKind>,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+            retryable: vec![std::io::ErrorKind::WouldBlock, std::io::ErrorKind::TimedOut],
+        }
+    }
+}
+impl RetryPolicy {
+    // wait waits (but not past `until`) before the next try at a write
+    // that's failed `failures` times in a row, the last time with `err`, or
+    // returns false if it shouldn't be tried again.
+    fn wait(&self, err: &std::io::Error, failures: u32, until: Option<Instant>) -> bool {
+        if failures >= self.max_attempts || !self.retryable.contains(&err.kind()) {
+            return false;
+        }
+        self.pause(failures, until);
+        true
+    }
+
+    // pause waits before the next try at a write that's failed `failures`
+    // times in a row, but not past `until`.
+    fn pause(&self, failures: u32, until: Option<Instant>) {
+        let doublings = (failures - 1).min(31);
+        let mut delay = self
+            .delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay);
+        if let Some(until) = until {
+            delay = delay.min(until.saturating_duration_since(Instant::now()));
+        }
+        std::thread::sleep(delay);
+    }
+}
+
+// Finished is what's left of Plain once the sink thread is done with it.
+struct Finished<W> {
+    inner: W,
+    written: u64,
+    records: u64,
+    abandoned: u64,
+    error: Option<std::io::Error>,
+    // destinations is how each of a Tee's inner writers did.
+    destinations: Vec<DestinationStats>,
+}
+
+// Datagrams is Plain for a record sink: it passes each record to the inner
+// writer as a single write call, like a datagram socket needs, instead of
+// looping with write_all. A write that only takes part of the record is a
+// failure, and the rest of the record is dropped.
+struct Datagrams<W, E>(Plain<W, E>);
+impl<W: std::io::Write + Send, E: FnMut(&WriteFailure) + Send> Output for Datagrams<W, E> {
+    type Done = Finished<W>;
+    type Inner = W;
+    fn put(&mut self, p: &[u8]) {
+        let out = &mut self.0;
+        let mut failures = 0;
+        let res = loop {
+            match out.inner.write(p) {
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    failures += 1;
+                    if !out.retry.wait(&err, failures, out.deadline.get().copied()) {
+                        break Err(err);
+                    }
+                    if passed(&out.deadline) {
+                        out.abandoned += p.len() as u64;
+                        return;
+                    }
+                }
+                res => break res,
+            }
+        };
+        match res {
+            Ok(n) if n == p.len() => {
+                out.written += n as u64;
+                out.records += 1;
+                out.consecutive = 0;
+            }
+            Ok(n) => {
+                out.written += n as u64;
+                let msg = format!("wrote {n} of a {}-byte record", p.len());
+                out.fail(
+                    std::io::Error::new(std::io::ErrorKind::WriteZero, msg),
+                    p.len() - n,
+                );
+            }
+            Err(error) => out.fail(error, p.len()),
+        }
+    }
+    fn abandon(&mut self, len: usize) {
+        self.0.abandon(len);
+    }
+    fn watch(&mut self, deadline: Arc<OnceLock<Instant>>) {
+        self.0.watch(deadline);
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+    fn replace(&mut self, new: W) -> std::io::Result<W> {
+        self.0.replace(new)
+    }
+    fn finish(self) -> Self::Done {
+        self.0.finish()
+    }
+}
+
+// Tee passes everything to each of its inner writers in turn. Each one
+// keeps track of its own progress, so a write that fails on one of them is
+// never passed to the others twice.
+struct Tee<W>(Vec<Plain<W>>);
+impl<W: std::io::Write + Send> Output for Tee<W> {
+    type Done = Finished<Vec<W>>;
+    type Inner = Vec<W>;
+    fn put(&mut self, p: &[u8]) {
+        for out in &mut self.0 {
+            out.put(p);
+        }
+    }
+    fn put_wrapped(&mut self, head: &[u8], tail: &[u8]) {
+        for out in &mut self.0 {
+            out.put_wrapped(head, tail);
+        }
+    }
+    // What's abandoned is lost to every inner writer alike.
+    fn abandon(&mut self, len: usize) {
+        for out in &mut self.0 {
+            out.abandon(len);
+        }
+    }
+    fn watch(&mut self, deadline: Arc<OnceLock<Instant>>) {
+        for out in &mut self.0 {
+            out.watch(deadline.clone());
+        }
+    }
+    // Every inner writer is flushed, even if one fails.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut res = Ok(());
+        for out in &mut self.0 {
+            if let Err(err) = out.flush() {
+                res = res.and(Err(err));
+            }
+        }
+        res
+    }
+    // The writers are swapped one for one, so their stats carry on.
+    fn replace(&mut self, new: Vec<W>) -> std::io::Result<Vec<W>> {
+        if new.len() != self.0.len() {
+            let msg = format!("{} writers to replace {}", new.len(), self.0.len());
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+        }
+        self.flush()?;
+        let swapped = self.0.iter_mut().zip(new);
+        Ok(swapped
+            .map(|(out, new)| std::mem::replace(&mut out.inner, new))
+            .collect())
+    }
+    fn finish(self) -> Self::Done {
+        let mut done = Finished {
+            inner: Vec::new(),
+            written: 0,
+            records: 0,
+            // Under TeePolicy::Backpressure, a writer that was stuck at the
+            // deadline abandons more than the rest.
+            abandoned: self.0.iter().map(|out| out.abandoned).max().unwrap_or(0),
+            error: None,
+            destinations: Vec::new(),
+        };
+        for out in self.0 {
+            let (dropped, failures) = (out.dropped, out.failures);
+            let finished = out.finish();
+            done.written += finished.written;
+            done.inner.push(finished.inner);
+            done.destinations.push(DestinationStats {
+                written: finished.written,
+                dropped_bytes: dropped,
+                failed_writes: failures,
+                error: finished.error.map(|err| err.kind()),
+            });
+        }
+        done
+    }
+}
+
+// Durable is Plain with calls to sync_data, as its SyncPolicy says. A sync
+// that fails is reported by Handle::flush under OnFlushBarrier, and by
+// shutdown otherwise.
+struct Durable<W, E = fn(&WriteFailure)> {
+    out: Plain<W, E>,
+    policy: SyncPolicy,
+    // unsynced is how much has been put since the last sync.
+    unsynced: u64,
+}
+impl<W: std::io::Write + Syncable, E> Durable<W, E> {
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.unsynced = 0;
+        self.out.inner.flush()?;
+        self.out.inner.sync_data()
+    }
+
+    fn sync_or_record(&mut self) {
+        if let Err(err) = self.sync() {
+            self.out.error.get_or_insert(err);
+        }
+    }
+}
+impl<W, E> Output for Durable<W, E>
+where
+    W: std::io::Write + Syncable + Send,
+    E: FnMut(&WriteFailure) + Send,
+{
+    type Done = Finished<W>;
+    type Inner = W;
+    fn put(&mut self, p: &[u8]) {
+        self.put_wrapped(p, &[]);
+    }
+    fn put_wrapped(&mut self, head: &[u8], tail: &[u8]) {
+        self.out.put_wrapped(head, tail);
+        self.unsynced += (head.len() + tail.len()) as u64;
+        if let SyncPolicy::EveryNBytes(n) = self.policy
+            && self.unsynced >= n
+        {
+            self.sync_or_record();
+        }
+    }
+    fn abandon(&mut self, len: usize) {
+        self.out.abandon(len);
+    }
+    fn watch(&mut self, deadline: Arc<OnceLock<Instant>>) {
+        self.out.watch(deadline);
+    }
+    // Under EveryDuration, the sink thread flushes once that long has
+    // passed since the last flush, so this is where that sync happens.
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.policy {
+            SyncPolicy::OnFlushBarrier if self.unsynced > 0 => self.sync(),
+            SyncPolicy::EveryDuration(_) if self.unsynced > 0 => {
+                self.out.flush()?;
+                self.sync_or_record();
+                Ok(())
+            }
+            _ => self.out.flush(),
+        }
+    }
+    fn replace(&mut self, new: W) -> std::io::Result<W> {
+        if self.policy != SyncPolicy::Never && self.unsynced > 0 {
+            self.sync()?;
+        }
+        self.out.replace(new)
+    }
+    // Whatever's left is synced on the way out, whatever the policy, except
+    // Never.
+    fn finish(mut self) -> Self::Done {
+        if self.policy != SyncPolicy::Never && self.unsynced > 0 {
+            self.sync_or_record();
+        }
+        self.out.finish()
+    }
+}
+
+// RecordReader drains a record-mode buffer one record at a time, so that
+// records are never merged or split.
+struct RecordReader(buffer::Reader);
+impl Consume for RecordReader {
+    fn drain(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) {
+        // Without checksums, records can't be corrupt.
+        while let Some(Ok((_, lease))) = self.0.read_record() {
+            f(lease.view, &[]);
+        }
+    }
+}
+
+// Evicting drains like buffer::Reader (or RecordReader, for `records`),
+// but copies what it reads out and releases it before passing it on. Under
+// OverflowPolicy::Overwrite, that leaves nothing leased while the inner
+// writer works, so a stalled writer doesn't stop writes from evicting the
+// oldest unread data.
+struct Evicting {
+    reader: buffer::Reader,
+    records: bool,
+    scratch: Vec<u8>,
+}
+impl Evicting {
+    fn new(reader: buffer::Reader, records: bool) -> Self {
+        Self {
+            reader,
+            records,
+            scratch: Vec::new(),
+        }
+    }
+}
+impl Consume for Evicting {
+    fn drain(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) {
+        loop {
+            self.scratch.clear();
+            if self.records {
+                let Some(Ok((_, lease))) = self.reader.read_record() else {
+                    break;
+                };
+                self.scratch.extend_from_slice(lease.view);
+            } else {
+                let Some(lease) = self.reader.read_wrapped() else {
+                    break;
+                };
+                self.scratch.extend_from_slice(lease.view);
+                self.scratch.extend_from_slice(lease.wrapped_view);
+            }
+            f(&self.scratch, &[]);
+        }
+    }
+}
+
+// Blocks only ever passes whole, block-aligned blocks to the inner writer,
+// as O_DIRECT needs: data is staged in an aligned block until it's full, and
+// the last partial block is padded with zeroes.
+// A block that fails to write is dropped, and the first such error is
+// kept for the end.
+struct Blocks<W> {
+    inner: W,
+    block: Storage,
+    filled: usize,
+    error: Option<std::io::Error>,
+}
+impl<W: std::io::Write + Send> Blocks<W> {
+    fn flush_block(&mut self) {
+        let block = unsafe { self.block.slice(0, self.block.len()) };
+        if let Err(err) = self.inner.write_all(block) {
+            self.error.get_or_insert(err);
+        }
+        self.filled = 0;
+    }
+}
+impl<W: std::io::Write + Send> Output for Blocks<W> {
+    // The number of zero bytes padded onto the end, or the first error.
+    type Done = std::io::Result<usize>;
+    type Inner = W;
+    fn put(&mut self, mut p: &[u8]) {
+        while !p.is_empty() {
+            let n = p.len().min(self.block.len() - self.filled);
+            unsafe { self.block.write(self.filled, &p[..n]) };
+            self.filled += n;
+            p = &p[n..];
+            if self.filled == self.block.len() {
+                self.flush_block();
+            }
+        }
+    }
+    // A partial block can't be written yet, so it stays staged.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+    // The partial block stays staged, for the new writer.
+    fn replace(&mut self, new: W) -> std::io::Result<W> {
+        self.inner.flush()?;
+        Ok(std::mem::replace(&mut self.inner, new))
+    }
+    fn finish(mut self) -> std::io::Result<usize> {
+        let mut padding = 0;
+        if self.filled > 0 {
+            padding = self.block.len() - self.filled;
+            unsafe { self.block.write(self.filled, &vec![0; padding]) };
+            self.flush_block();
+        }
+        if let Err(err) = self.inner.flush() {
+            self.error.get_or_insert(err);
+        }
+        self.error.map_or(Ok(padding), Err)
+    }
+}
+
+// Lines only passes whole lines to the inner writer, so that output from
+// several processes sharing it (e.g. on a terminal) interleaves by line
+// rather than mid-line. A line longer than `max` is passed on anyway, and
+// any trailing partial line is passed on at the end. Lines that fail to
+// write are dropped, and counted in `failures` (see Guard::failed_writes).
+struct Lines<W> {
+    inner: W,
+    staged: Vec<u8>,
+    max: usize,
+    failures: Arc<AtomicUsize>,
+}
+impl<W: std::io::Write + Send> Lines<W> {
+    fn flush_staged(&mut self) {
+        if self.inner.write_all(&self.staged).is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.staged.clear();
+    }
+}
+impl<W: std::io::Write + Send> Output for Lines<W> {
+    type Done = ();
+    type Inner = W;
+    fn put(&mut self, p: &[u8]) {
+        match p.iter().rposition(|&b| b == b'\n') {
+            Some(i) => {
+                self.staged.extend_from_slice(&p[..=i]);
+                self.flush_staged();
+                self.staged.extend_from_slice(&p[i + 1..]);
+            }
+            None => self.staged.extend_from_slice(p),
+        }
+        if self.staged.len() >= self.max {
+            self.flush_staged();
+        }
+    }
+    // Flushing is an explicit request, so it passes on a partial line too.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.staged)?;
+        self.staged.clear();
+        self.inner.flush()
+    }
+    fn replace(&mut self, new: W) -> std::io::Result<W> {
+        self.flush()?;
+        Ok(std::mem::replace(&mut self.inner, new))
+    }
+    fn finish(mut self) {
+        if !self.staged.is_empty() {
+            self.flush_staged();
+        }
+        if self.inner.flush().is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The default buffer's writes go through write_retry, so that they honor
+// the buffer's overflow policy.
+impl Produce for buffer::Writer {
+    fn try_write(&mut self, p: &[u8]) -> bool {
+        self.write_retry(p).is_ok()
+    }
+    fn overwritten(&self) -> usize {
+        self.stats().bytes_overwritten
+    }
+}
+impl Consume for buffer::Reader {
+    fn drain(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) {
+        while let Some(lease) = self.read_wrapped() {
+            f(lease.view, lease.wrapped_view);
+        }
+    }
+}
+impl Produce for spsc::Writer {
+    fn try_write(&mut self, p: &[u8]) -> bool {
+        self.try_write(p)
+    }
+}
+impl Consume for spsc::Reader {
+    fn drain(&mut self, f: &mut dyn FnMut(&[u8], &[u8])) {
+        while let Some(lease) = self.read() {
+            f(lease.view, &[]);
+        }
+    }
+}
+
+// Handle is the producer side of a sink. Handles for the default buffer can be
+// cloned; handles for an SPSC sink can't, since that buffer only supports a
+// single producer.
 #[derive(Clone)]
-pub struct Handle {
-    writer: buffer::Writer,
+pub struct Handle<W = buffer::Writer> {
+    writer: W,
     tx: Sender<()>,
+    flushes: Sender<Sender<std::io::Result<()>>>,
+    counters: Arc<Counters>,
+    max_fmt: usize,
+    // scratch is where write_fmt formats to.
+    scratch: Vec<u8>,
+}
+impl<W: std::fmt::Debug> std::fmt::Debug for Handle<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("writer", &self.writer)
+            .field("dropped", &self.counters.stats().dropped_writes)
+            .finish()
+    }
+}
+
+// Counters are the running totals behind HandleStats, shared by a Handle
+// and all of its clones. They're only updated with relaxed operations,
+// since they're on every write's path.
+#[derive(Default)]
+struct Counters {
+    accepted_bytes: AtomicUsize,
+    dropped_writes: AtomicUsize,
+    dropped_bytes: AtomicUsize,
+    overwritten_bytes: AtomicUsize,
+}
+impl Counters {
+    fn stats(&self) -> HandleStats {
+        HandleStats {
+            accepted_bytes: self.accepted_bytes.load(Ordering::Relaxed),
+            dropped_writes: self.dropped_writes.load(Ordering::Relaxed),
+            dropped_bytes: self.dropped_bytes.load(Ordering::Relaxed),
+            overwritten_bytes: self.overwritten_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// HandleStats is a snapshot of what's been written through a sink's
+// Handles. Like buffer::Stats, each field is read separately. The byte
+// counts wrap around on targets with 32-bit pointers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandleStats {
+    // accepted_bytes is the total size of every write that was buffered.
+    pub accepted_bytes: usize,
+    // dropped_writes is how many writes failed (see SinkWriteError), and
+    // dropped_bytes is their total size.
+    pub dropped_writes: usize,
+    pub dropped_bytes: usize,
+    // overwritten_bytes is how much accepted data was discarded unwritten,
+    // to make room for newer writes (see OverflowPolicy::Overwrite).
+    pub overwritten_bytes: usize,
+}
+impl<W: Produce> Handle<W> {
+    // write buffers `p` for the sink thread. If it doesn't fit, what happens
+    // depends on the overflow policy (see spawn_with_policy): by default,
+    // it fails with Full. It fails with Closed if the sink thread is gone,
+    // since nothing would ever write `p` out.
+    pub fn write(&mut self, p: &[u8]) -> Result<(), SinkWriteError> {
+        let res = match self.writer.try_write(p) {
+            true => Ok(()),
+            false => Err(SinkWriteError::Full),
+        };
+        let res = self.notify(res);
+        self.count(p.len(), res)
+    }
+
+    // write_lossy is write for callers that don't need to know whether `p`
+    // made it: a failed write is only counted (see stats).
+    pub fn write_lossy(&mut self, p: &[u8]) {
+        let _ = self.write(p);
+    }
+
+    // write_fmt formats `args` into a scratch space that's kept for the next
+    // call, and then writes that like write does, so that `write!` works on
+    // a Handle without a String for every call. The formatted text can be
+    // at most `max_fmt` bytes long (see set_max_fmt), or it fails with
+    // TooLong. Like format!, it panics if a formatting trait implementation
+    // returns an error.
+    pub fn write_fmt(&mut self, args: std::fmt::Arguments<'_>) -> Result<(), SinkWriteError> {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        let mut capped = Capped {
+            buf: &mut scratch,
+            max: self.max_fmt,
+            len: 0,
+        };
+        std::fmt::write(&mut capped, args)
+            .expect("a formatting trait implementation returned an error");
+        let len = capped.len;
+        let res = match len <= self.max_fmt {
+            true => self.write(&scratch),
+            false => {
+                let res = self.notify(Err(SinkWriteError::TooLong));
+                self.count(len, res)
+            }
+        };
+        self.scratch = scratch;
+        res
+    }
+
+    // set_max_fmt sets how long a write_fmt's text can get. Clones of this
+    // Handle made afterwards inherit it; existing clones are unaffected.
+    pub fn set_max_fmt(&mut self, max: usize) {
+        self.max_fmt = max;
+    }
+
+    // notify tells the sink thread that there's something to write. It's
+    // told even if the write failed, since that's how we find out whether
+    // it's still there: if not, the write fails with Closed.
+    fn notify(&self, res: Result<(), SinkWriteError>) -> Result<(), SinkWriteError> {
+        match self.tx.try_send(()) {
+            Err(TrySendError::Disconnected(())) => Err(SinkWriteError::Closed),
+            _ => res,
+        }
+    }
+
+    // count adds the outcome of a write of `len` bytes to the Handles'
+    // stats.
+    fn count(&self, len: usize, res: Result<(), SinkWriteError>) -> Result<(), SinkWriteError> {
+        let c = &self.counters;
+        if res.is_ok() {
+            c.accepted_bytes.fetch_add(len, Ordering::Relaxed);
+            // Only writes discard anything, so this is up to date once the
+            // last one has been counted.
+            let overwritten = self.writer.overwritten();
+            c.overwritten_bytes
+                .fetch_max(overwritten, Ordering::Relaxed);
+        } else {
+            c.dropped_writes.fetch_add(1, Ordering::Relaxed);
+            c.dropped_bytes.fetch_add(len, Ordering::Relaxed);
+        }
+        res
+    }
+
+    // flush waits until everything written so far (through any Handle) has
+    // been passed to the inner writer and the inner writer has been flushed,
+    // and returns the error from that flush, if any. A direct I/O sink holds
+    // on to a partial block until it's full. It fails with BrokenPipe if the
+    // sink thread has already exited.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let gone = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "sink thread is gone");
+        let (ack, done) = crossbeam::channel::bounded(1);
+        self.flushes.send(ack).map_err(|_| gone())?;
+        done.recv().map_err(|_| gone())?
+    }
+
+    // dropped is how many writes, across this Handle and all of its clones,
+    // failed.
+    pub fn dropped(&self) -> usize {
+        self.counters.dropped_writes.load(Ordering::Relaxed)
+    }
+
+    // stats is what's been written through this Handle and all of its
+    // clones.
+    pub fn stats(&self) -> HandleStats {
+        self.counters.stats()
+    }
 }
 impl Handle {
-    pub fn write(&mut self, p: &[u8]) {
-        if self.writer.try_write(p) {
-            let _ = self.tx.try_send(());
+    // write_timeout is like write, but if `p` doesn't fit, it waits up to
+    // `timeout` for the sink thread to make room instead of dropping it, so
+    // that the caller can do something else with `p` if it times out. It
+    // fails with Closed as soon as it sees that the sink thread is gone.
+    pub fn write_timeout(&mut self, p: &[u8], timeout: Duration) -> Result<(), SinkWriteError> {
+        let res = match self.writer.write_timeout(p, timeout) {
+            Ok(()) => self.notify(Ok(())),
+            Err(WriteError::Disconnected) => Err(SinkWriteError::Closed),
+            // Sinks don't hand out a Control, so their writes can't be paused.
+            Err(WriteError::Full | WriteError::Paused) => Err(SinkWriteError::TimedOut),
+        };
+        self.count(p.len(), res)
+    }
+}
+
+// DEFAULT_MAX_FMT is how long a Handle::write_fmt's text can get, unless
+// it's changed with Handle::set_max_fmt.
+const DEFAULT_MAX_FMT: usize = 1024;
+
+// Capped is a fmt::Write into `buf` that stops taking anything once it's
+// passed `max` bytes, but carries on counting, so that a write that's too
+// long can still be counted in full.
+struct Capped<'a> {
+    buf: &'a mut Vec<u8>,
+    max: usize,
+    len: usize,
+}
+impl std::fmt::Write for Capped<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.len += s.len();
+        if self.len <= self.max {
+            self.buf.extend_from_slice(s.as_bytes());
         }
+        Ok(())
     }
 }
+
+// SinkWriteError is why a Handle couldn't buffer a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkWriteError {
+    // There wasn't room, and the overflow policy said not to make any.
+    Full,
+    // There still wasn't room once the timeout was up.
+    TimedOut,
+    // A Handle::write_fmt's text was longer than the Handle allows.
+    TooLong,
+    // The sink thread has exited, so nothing will ever be written out.
+    Closed,
+}
+impl std::fmt::Display for SinkWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkWriteError::Full => write!(f, "sink buffer is full"),
+            SinkWriteError::TooLong => write!(f, "formatted text is too long"),
+            SinkWriteError::TimedOut => write!(f, "timed out waiting for room"),
+            SinkWriteError::Closed => write!(f, "sink thread is gone"),
+        }
+    }
+}
+impl std::error::Error for SinkWriteError {}
+
 pub fn spawn<'scope, 'env: 'scope, W>(
     scope: &'scope std::thread::Scope<'scope, 'env>,
     capacity: usize,
-    mut inner: W,
+    inner: W,
 ) -> Handle
 where
     W: std::io::Write + Send + 'env,
 {
-    let (mut reader, writer) = crate::buffer::create(capacity);
-    let (tx, rx) = crossbeam::channel::bounded(1);
-    scope.spawn(move || {
-        while let Ok(()) = rx.recv() {
-            while let Some(lease) = reader.read() {
-                if let Err(_err) = inner.write_all(lease.view) {
-                    // emit telemetry
-                }
-            }
-        }
-        // Once all the notifiers have dropped, we are guaranteed that no more data
-        // can be buffered. There may be some existing data, so drain the buffer
-        // and then exit.
-        while let Some(lease) = reader.read() {
-            if let Err(_err) = inner.write_all(lease.view) {
-                // emit telemetry
-            }
+    match try_spawn(scope, capacity, inner) {
+        Ok(handle) => handle,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// try_spawn is like spawn, but reports an invalid capacity instead of
+// panicking. No thread is spawned if the capacity is rejected.
+pub fn try_spawn<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+) -> Result<Handle, CreateError>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let (reader, writer) = crate::buffer::try_create(capacity)?;
+    Ok(spawn_on(
+        scope,
+        reader,
+        writer,
+        Plain::new(inner),
+        Orders::none(),
+        None,
+    )
+    .0)
+}
+
+// spawn_sink is like spawn, but also returns a Sink, which can stop the
+// thread and get `inner` back (see Sink::shutdown).
+pub fn spawn_sink<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+) -> (Handle, Sink<'scope, W>)
+where
+    W: std::io::Write + Send + 'env,
+{
+    match try_spawn_sink(scope, capacity, inner) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn try_spawn_sink<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+) -> Result<(Handle, Sink<'scope, W>), CreateError>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let (reader, writer) = crate::buffer::try_create(capacity)?;
+    let (controls, orders) = orders();
+    let (handle, thread) = spawn_on(scope, reader, writer, Plain::new(inner), orders, None);
+    let sink = Sink {
+        controls,
+        thread: Thread::Scoped(thread),
+        counters: handle.counters.clone(),
+    };
+    Ok((handle, sink))
+}
+
+// spawn_owned is like spawn_sink, but for a writer that can outlive any
+// scope, e.g. in a long-running service: the thread isn't scoped, and runs
+// until the Sink shuts it down.
+pub fn spawn_owned<W>(capacity: usize, inner: W) -> (Handle, Sink<'static, W>)
+where
+    W: std::io::Write + Send + 'static,
+{
+    match try_spawn_owned(capacity, inner) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn try_spawn_owned<W>(
+    capacity: usize,
+    inner: W,
+) -> Result<(Handle, Sink<'static, W>), CreateError>
+where
+    W: std::io::Write + Send + 'static,
+{
+    let (reader, writer) = crate::buffer::try_create(capacity)?;
+    let (controls, orders) = orders();
+    let (handle, inbox) = handle(writer);
+    let out = Plain::new(inner);
+    let thread = std::thread::spawn(move || run(inbox, orders, reader, out, None));
+    let sink = Sink {
+        controls,
+        thread: Thread::Owned(thread),
+        counters: handle.counters.clone(),
+    };
+    Ok((handle, sink))
+}
+
+// Sink is the thread side of a sink from spawn_sink or spawn_owned.
+// Dropping it leaves the thread running until every Handle is gone, as with
+// spawn, and then it drains what's left and exits. An unscoped thread is
+// detached, so nothing waits for that: if the process exits first, whatever
+// it hadn't written yet is lost.
+pub struct Sink<'scope, W> {
+    controls: Controls<W>,
+    thread: Thread<'scope, Finished<W>>,
+    counters: Arc<Counters>,
+}
+
+// Thread is a sink thread that's either scoped or not.
+enum Thread<'scope, T> {
+    Scoped(ScopedJoinHandle<'scope, T>),
+    Owned(JoinHandle<T>),
+}
+impl<T> Thread<'_, T> {
+    fn join(self) -> std::thread::Result<T> {
+        match self {
+            Thread::Scoped(thread) => thread.join(),
+            Thread::Owned(thread) => thread.join(),
         }
-        let _ = inner.flush();
-    });
+    }
+}
+impl<W> std::fmt::Debug for Sink<'_, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sink").finish_non_exhaustive()
+    }
+}
 
-    Handle { writer, tx }
+// SinkReport is how a sink did, from Sink::shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkReport {
+    // written is how many bytes were passed to the inner writer, or for a
+    // tee (see spawn_tee), to all of them together.
+    pub written: u64,
+    // records is how many records a record sink (see spawn_records) has
+    // written whole. It's always 0 for other sinks.
+    pub records: u64,
+    // abandoned is how many buffered bytes were never written, because
+    // Sink::shutdown_timeout's deadline passed first.
+    pub abandoned: u64,
+    // handles is what was written through the Handles (see Handle::stats).
+    pub handles: HandleStats,
+    // destinations is how each of a tee's inner writers did, in order. It's
+    // always empty for other sinks.
+    pub destinations: Vec<DestinationStats>,
+    // error is the kind of the first error the inner writer returned, if
+    // any. Sink::shutdown returns the error itself instead of the report.
+    pub error: Option<std::io::ErrorKind>,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+// DestinationStats is how one of a tee's inner writers did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DestinationStats {
+    // written is how many bytes were passed to it.
+    pub written: u64,
+    // dropped_bytes is how many bytes it never got, across failed_writes
+    // failed writes.
+    pub dropped_bytes: u64,
+    pub failed_writes: u64,
+    // error is the kind of the first error it returned, if any.
+    pub error: Option<std::io::ErrorKind>,
+}
 
-    #[test]
-    fn smoke() {
-        let mut buf = Vec::new();
-        std::thread::scope(|scope| {
-            let mut h = spawn(scope, 100, &mut buf);
-            h.write(b"asdf");
-            h.write(b"pqrs");
-        });
+impl<W> Sink<'_, W> {
+    // shutdown stops the thread once it's written out everything buffered
+    // so far and flushed, and returns the inner writer along with a report,
+    // or the first error the inner writer returned. Anything written through
+    // a Handle after that is lost. If the thread panicked, so does this.
+    pub fn shutdown(self) -> (W, Result<SinkReport, std::io::Error>) {
+        let (inner, report, error) = self.stop();
+        (inner, error.map_or(Ok(report), Err))
+    }
+
+    // shutdown_timeout is like shutdown, but only carries on writing out
+    // what's buffered until `deadline` has passed. Whatever's left then is
+    // abandoned, and counted in SinkReport::abandoned. The deadline is
+    // checked between reads from the buffer and between retries of a failed
+    // write, so it's best-effort: a single write to the inner writer that's
+    // already under way (or the final flush) can still take as long as it
+    // takes, and the thread has to finish it before it can hand the inner
+    // writer back. The report always comes back, with any error in
+    // SinkReport::error.
+    pub fn shutdown_timeout(self, deadline: Duration) -> (W, SinkReport) {
+        let _ = self.controls.deadline.set(Instant::now() + deadline);
+        let (inner, report, _) = self.stop();
+        (inner, report)
+    }
+
+    // stop returns the inner writer, the report, and the first error, which
+    // the report only has the kind of.
+    fn stop(self) -> (W, SinkReport, Option<std::io::Error>) {
+        let _ = self.controls.stop.send(());
+        let done = match self.thread.join() {
+            Ok(done) => done,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        let report = SinkReport {
+            written: done.written,
+            records: done.records,
+            abandoned: done.abandoned,
+            handles: self.counters.stats(),
+            destinations: done.destinations,
+            error: done.error.as_ref().map(|err| err.kind()),
+        };
+        (done.inner, report, done.error)
+    }
+
+    // replace_writer swaps `new` in for the inner writer, e.g. to rotate a
+    // log file, and returns the old one. Everything written through a
+    // Handle before the call goes to the old writer, which is flushed
+    // first; everything after goes to `new`. If that flush fails, the old
+    // writer stays, `new` is dropped, and this returns the error. It fails
+    // with BrokenPipe if the sink thread has already exited.
+    pub fn replace_writer(&self, new: W) -> std::io::Result<W> {
+        let gone = || std::io::Error::new(std::io::ErrorKind::BrokenPipe, "sink thread is gone");
+        let (ack, done) = crossbeam::channel::bounded(1);
+        self.controls.swaps.send((new, ack)).map_err(|_| gone())?;
+        done.recv().map_err(|_| gone())?
+    }
+}
+
+// WriteFailure is a write to a sink's inner writer that failed. The data
+// is dropped, and the sink carries on with what comes next.
+#[derive(Debug)]
+pub struct WriteFailure {
+    pub error: std::io::Error,
+    // len is how many bytes were lost.
+    pub len: usize,
+    // consecutive is how many writes in a row have failed, including this
+    // one.
+    pub consecutive: usize,
+}
+
+// spawn_with_error_handler is like spawn, but passes every failed write to
+// `on_error`, on the sink thread, instead of silently dropping it.
+pub fn spawn_with_error_handler<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+    on_error: E,
+) -> Handle
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    match try_spawn_with_error_handler(scope, capacity, inner, on_error) {
+        Ok(handle) => handle,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_with_error_handler<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+    on_error: E,
+) -> std::io::Result<Handle>
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    // Dropping the Sink leaves the thread running until the Handles are
+    // gone, as with spawn.
+    let builder = Builder::new().capacity(capacity).on_error(on_error);
+    Ok(builder.spawn_scoped(scope, inner)?.0)
+}
+
+// spawn_with_flush_interval is like spawn, but also flushes `inner` once
+// `interval` has passed since it was last flushed, so that data doesn't sit
+// in a buffered writer (like a BufWriter) for long while things are quiet.
+// Nothing is flushed if nothing's been written since the last flush.
+pub fn spawn_with_flush_interval<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    interval: Duration,
+    inner: W,
+) -> Handle
+where
+    W: std::io::Write + Send + 'env,
+{
+    match try_spawn_with_flush_interval(scope, capacity, interval, inner) {
+        Ok(handle) => handle,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_with_flush_interval<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    interval: Duration,
+    inner: W,
+) -> std::io::Result<Handle>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let builder = Builder::new().capacity(capacity).flush_interval(interval);
+    Ok(builder.spawn_scoped(scope, inner)?.0)
+}
+
+// SyncPolicy is how often a sink from spawn_with_sync makes what it's
+// written durable, with Syncable::sync_data. That happens on the sink
+// thread, so Handles don't wait for it, except through Handle::flush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    // Never sync, as with spawn_sink.
+    #[default]
+    Never,
+    // Sync once at least this many bytes have been written since the last
+    // sync.
+    EveryNBytes(u64),
+    // Sync once this long has passed since the last flush, if anything's
+    // been written since then. A Handle::flush syncs too, so nothing is left
+    // unsynced for longer than this, but it doesn't see sync errors.
+    EveryDuration(Duration),
+    // Sync whenever a Handle flushes, and pass any error from the sync back
+    // to it.
+    OnFlushBarrier,
+}
+
+// Syncable is an inner writer that can make what's been written to it
+// durable, like File::sync_data. The default does nothing, for writers
+// that have nothing to sync.
+pub trait Syncable {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+impl Syncable for std::fs::File {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        std::fs::File::sync_data(self)
+    }
+}
+impl Syncable for &std::fs::File {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        std::fs::File::sync_data(self)
+    }
+}
+impl Syncable for RotatingFile {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        RotatingFile::sync_data(self)
+    }
+}
+impl<W: std::io::Write + Syncable> Syncable for std::io::BufWriter<W> {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(self)?;
+        self.get_mut().sync_data()
+    }
+}
+impl<S: Syncable + ?Sized> Syncable for &mut S {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        (**self).sync_data()
+    }
+}
+impl<S: Syncable + ?Sized> Syncable for Box<S> {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        (**self).sync_data()
+    }
+}
+impl Syncable for Vec<u8> {}
+impl Syncable for std::io::Sink {}
+
+// spawn_with_sync is like spawn_sink, but also syncs `inner` as `sync`
+// says. Whatever's left unsynced is synced at shutdown (unless `sync` is
+// Never). Shutdown reports the first sync that failed, unless it was
+// already reported to a Handle::flush.
+pub fn spawn_with_sync<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    sync: SyncPolicy,
+    inner: W,
+) -> (Handle, Sink<'scope, W>)
+where
+    W: std::io::Write + Syncable + Send + 'env,
+{
+    match try_spawn_with_sync(scope, capacity, sync, inner) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_with_sync<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    sync: SyncPolicy,
+    inner: W,
+) -> std::io::Result<(Handle, Sink<'scope, W>)>
+where
+    W: std::io::Write + Syncable + Send + 'env,
+{
+    let builder = Builder::new().capacity(capacity).sync(sync);
+    builder.spawn_scoped(scope, inner)
+}
+
+// spawn_records is like spawn_sink, but keeps writes apart: every
+// successful Handle::write is passed to `inner` as a single write call of
+// its own, never merged with others or split up, e.g. for a datagram
+// socket. The buffer runs in record mode, holding up to `records` unread
+// writes (see BufferOptions::records), and empty writes fail with Full. An
+// inner write that fails, or only takes part of a record (which fails with
+// WriteZero), drops the record, and is passed to `on_error`.
+pub fn spawn_records<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    records: usize,
+    inner: W,
+    on_error: E,
+) -> (Handle, Sink<'scope, W>)
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    match try_spawn_records(scope, capacity, records, inner, on_error) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_records<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    records: usize,
+    inner: W,
+    on_error: E,
+) -> std::io::Result<(Handle, Sink<'scope, W>)>
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    let builder = Builder::new().capacity(capacity).records(records);
+    builder.on_error(on_error).spawn_scoped(scope, inner)
+}
+
+// spawn_with_retry is like spawn_with_error_handler, but retries failed
+// writes to `inner` according to `retry`. Only a write that's still failing
+// once it runs out of retries is passed to `on_error`, with the length of
+// whatever part of it was lost.
+pub fn spawn_with_retry<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    retry: RetryPolicy,
+    inner: W,
+    on_error: E,
+) -> Handle
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    match try_spawn_with_retry(scope, capacity, retry, inner, on_error) {
+        Ok(handle) => handle,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_with_retry<'scope, 'env: 'scope, W, E>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    retry: RetryPolicy,
+    inner: W,
+    on_error: E,
+) -> std::io::Result<Handle>
+where
+    W: std::io::Write + Send + 'env,
+    E: FnMut(&WriteFailure) + Send + 'env,
+{
+    let builder = Builder::new().capacity(capacity).retry(retry);
+    Ok(builder.on_error(on_error).spawn_scoped(scope, inner)?.0)
+}
+
+// Destinations is the inner writers of a tee, from spawn_tee.
+pub type Destinations<'a> = Vec<Box<dyn std::io::Write + Send + 'a>>;
+
+// TeePolicy is what a tee (see spawn_tee) does about an inner writer that
+// fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TeePolicy {
+    // Drop the failed write for that writer alone, and carry on. The others
+    // still get everything.
+    #[default]
+    Skip,
+    // Retry the failed write until it goes through, with a delay that
+    // doubles up to a second (as with RetryPolicy), if it failed with one of
+    // RetryPolicy's default retryable errors (e.g. WouldBlock). Nothing is
+    // lost to those, but the sink thread stops draining in the meantime, so
+    // once the buffer fills up, writes through the Handles fail. Any other
+    // error is treated as under Skip, and a shutdown_timeout deadline cuts
+    // the retrying short.
+    Backpressure,
+}
+
+// spawn_tee is like spawn_sink, but passes everything written to each of
+// `writers` in turn, e.g. to a local file and to a network forwarder. A
+// failed write only affects the writer it failed on, as `policy` says, and
+// is counted in its SinkReport::destinations entry. Shutting down doesn't
+// return an error for those: the report says which writers failed.
+pub fn spawn_tee<'scope, 'env: 'scope>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    policy: TeePolicy,
+    writers: Destinations<'env>,
+) -> (Handle, Sink<'scope, Destinations<'env>>) {
+    match try_spawn_tee(scope, capacity, policy, writers) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn try_spawn_tee<'scope, 'env: 'scope>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    policy: TeePolicy,
+    writers: Destinations<'env>,
+) -> Result<(Handle, Sink<'scope, Destinations<'env>>), CreateError> {
+    let (reader, writer) = crate::buffer::try_create(capacity)?;
+    let outs = writers.into_iter().map(|inner| Plain {
+        persist: policy == TeePolicy::Backpressure,
+        ..Plain::new(inner)
+    });
+    let (controls, orders) = orders();
+    let out = Tee(outs.collect());
+    let (handle, thread) = spawn_on(scope, reader, writer, out, orders, None);
+    let sink = Sink {
+        controls,
+        thread: Thread::Scoped(thread),
+        counters: handle.counters.clone(),
+    };
+    Ok((handle, sink))
+}
+
+// spawn_with_policy is like spawn, but lets the caller choose what
+// `Handle::write` does when the buffer is full:
+//
+// - Fail drops the new write, as spawn does, and counts it in
+//   HandleStats::dropped_writes.
+// - Overwrite drops the oldest buffered data instead, and counts it in
+//   HandleStats::overwritten_bytes. The sink thread copies out whatever
+//   it's writing, so even that can't hold up a write; a write larger than
+//   the whole buffer still fails.
+// - Block waits for the sink thread to catch up, so nothing is dropped,
+//   but a stalled inner writer stalls every producer with it.
+pub fn spawn_with_policy<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    inner: W,
+) -> Handle
+where
+    W: std::io::Write + Send + 'env,
+{
+    match try_spawn_with_policy(scope, capacity, overflow, inner) {
+        Ok(handle) => handle,
+        Err(err) => panic!("failed to start sink: {err}"),
+    }
+}
+
+pub fn try_spawn_with_policy<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    inner: W,
+) -> std::io::Result<Handle>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let builder = Builder::new().capacity(capacity).overflow(overflow);
+    Ok(builder.spawn_scoped(scope, inner)?.0)
+}
+
+// spawn_spsc is like spawn, but runs on top of the lock-free SPSC buffer. The
+// returned Handle can't be cloned.
+pub fn spawn_spsc<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+) -> Handle<spsc::Writer>
+where
+    W: std::io::Write + Send + 'env,
+{
+    match try_spawn_spsc(scope, capacity, inner) {
+        Ok(handle) => handle,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn try_spawn_spsc<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    inner: W,
+) -> Result<Handle<spsc::Writer>, CreateError>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let (reader, writer) = spsc::try_create(capacity)?;
+    Ok(spawn_on(
+        scope,
+        reader,
+        writer,
+        Plain::new(inner),
+        Orders::none(),
+        None,
+    )
+    .0)
+}
+
+// spawn_direct_io is like spawn, but for an inner writer that only accepts
+// whole blocks of `block_size` bytes from `block_size`-aligned memory, like a
+// file opened with O_DIRECT. Writes are staged until they fill a block, and
+// once every Handle is gone, the last partial block is padded with zeroes.
+// The returned thread handle yields how many bytes of padding that was, so
+// that the caller can truncate the file to what was actually written, or
+// the first error from the inner writer, since a block that failed to write
+// leaves a hole that no truncation can fix.
+pub fn spawn_direct_io<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    block_size: usize,
+    inner: W,
+) -> (Handle, ScopedJoinHandle<'scope, std::io::Result<usize>>)
+where
+    W: std::io::Write + Send + 'env,
+{
+    match try_spawn_direct_io(scope, capacity, block_size, inner) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn try_spawn_direct_io<'scope, 'env: 'scope, W>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    capacity: usize,
+    block_size: usize,
+    inner: W,
+) -> Result<(Handle, ScopedJoinHandle<'scope, std::io::Result<usize>>), CreateError>
+where
+    W: std::io::Write + Send + 'env,
+{
+    let block = Storage::alloc(block_size, block_size)?;
+    let (reader, writer) = crate::buffer::try_create_aligned(capacity, block_size)?;
+    let blocks = Blocks {
+        inner,
+        block,
+        filled: 0,
+        error: None,
+    };
+    Ok(spawn_on(
+        scope,
+        reader,
+        writer,
+        blocks,
+        Orders::none(),
+        None,
+    ))
+}
+
+// stderr spawns a thread that drains a buffer of `capacity` bytes into
+// stderr, a whole line at a time (see spawn_lines). Unlike spawn, the thread
+// isn't scoped: it runs until the returned Guard is dropped.
+pub fn stderr(capacity: usize) -> (Handle, Guard) {
+    match stderr_with_options(capacity, BufferOptions::default()) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn stderr_with_options(
+    capacity: usize,
+    options: BufferOptions,
+) -> Result<(Handle, Guard), std::io::Error> {
+    spawn_lines(capacity, options, std::io::stderr())
+}
+
+// stdout is stderr for stdout.
+pub fn stdout(capacity: usize) -> (Handle, Guard) {
+    match stdout_with_options(capacity, BufferOptions::default()) {
+        Ok(spawned) => spawned,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+pub fn stdout_with_options(
+    capacity: usize,
+    options: BufferOptions,
+) -> Result<(Handle, Guard), std::io::Error> {
+    spawn_lines(capacity, options, std::io::stdout())
+}
+
+// spawn_lines spawns a thread that drains a buffer into `inner`, only ever
+// passing it whole lines, so that each write_all holds `inner`'s lock (if
+// it has one, like stderr) for a whole number of lines. A line that doesn't
+// end within `capacity` bytes is written as is.
+pub fn spawn_lines<W>(
+    capacity: usize,
+    options: BufferOptions,
+    inner: W,
+) -> Result<(Handle, Guard), std::io::Error>
+where
+    W: std::io::Write + Send + 'static,
+{
+    let (reader, writer) = crate::buffer::create_with_options(capacity, options)?;
+    let failures = Arc::new(AtomicUsize::new(0));
+    let lines = Lines {
+        inner,
+        staged: Vec::new(),
+        max: capacity,
+        failures: failures.clone(),
+    };
+    let (handle, inbox) = handle(writer);
+    let (stop, stopped) = crossbeam::channel::bounded(0);
+    let orders = Orders {
+        stop: stopped,
+        ..Orders::none()
+    };
+    let thread = std::thread::spawn(move || run(inbox, orders, reader, lines, None));
+    let guard = Guard {
+        stop,
+        thread: Some(thread),
+        failures,
+    };
+    Ok((handle, guard))
+}
+
+// Guard owns the thread behind a stderr or stdout sink. Dropping it writes
+// out whatever's been buffered (even a partial line), flushes, and waits for
+// the thread to exit. Anything written through a Handle after that is lost,
+// so the Guard should be the last thing to go.
+pub struct Guard {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+    failures: Arc<AtomicUsize>,
+}
+impl Guard {
+    // failed_writes is how many writes to the inner writer have failed so
+    // far. What each of them held is lost.
+    pub fn failed_writes(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+impl std::fmt::Debug for Guard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Guard").finish_non_exhaustive()
+    }
+}
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// DEFAULT_CAPACITY and DEFAULT_RECORDS are the Builder's buffer capacity
+// and, in record mode, how many unread records it holds.
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+const DEFAULT_RECORDS: usize = 1024;
+
+// Builder sets up a sink with any combination of the options that the
+// spawn_with_* functions take one at a time (they're all built on it). By
+// default it's a spawn_sink with a 64 KiB buffer:
+//
+//     let (handle, sink) = sink::Builder::new()
+//         .capacity(1 << 20)
+//         .thread_name("audit-log")
+//         .flush_interval(Duration::from_secs(1))
+//         .spawn(file)?;
+//
+// Once `sync` is set (the S parameter is then a SyncPolicy), the inner
+// writer has to be Syncable.
+pub struct Builder<E = fn(&WriteFailure), S = ()> {
+    capacity: usize,
+    thread_name: Option<String>,
+    overflow: OverflowPolicy,
+    flush_interval: Option<Duration>,
+    on_error: E,
+    // records is how many unread records the buffer holds in record mode,
+    // or None outside it.
+    records: Option<usize>,
+    retry: RetryPolicy,
+    sync: S,
+}
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            thread_name: None,
+            overflow: OverflowPolicy::default(),
+            flush_interval: None,
+            on_error: |_| {},
+            records: None,
+            retry: RetryPolicy::default(),
+            sync: (),
+        }
+    }
+}
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<E, S: std::fmt::Debug> std::fmt::Debug for Builder<E, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builder")
+            .field("capacity", &self.capacity)
+            .field("thread_name", &self.thread_name)
+            .field("overflow", &self.overflow)
+            .field("flush_interval", &self.flush_interval)
+            .field("records", &self.records)
+            .field("retry", &self.retry)
+            .field("sync", &self.sync)
+            .finish_non_exhaustive()
+    }
+}
+impl<E: FnMut(&WriteFailure) + Send, S> Builder<E, S> {
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    // thread_name names the sink thread, as std::thread::Builder::name
+    // does, so that it shows up in profilers and panic messages.
+    pub fn thread_name(mut self, name: &str) -> Self {
+        self.thread_name = Some(name.to_string());
+        self
+    }
+
+    // overflow is what Handle::write does when the buffer is full, as for
+    // spawn_with_policy.
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    // flush_interval is as for spawn_with_flush_interval.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    // on_error is as for spawn_with_error_handler.
+    pub fn on_error<F: FnMut(&WriteFailure) + Send>(self, on_error: F) -> Builder<F, S> {
+        Builder {
+            capacity: self.capacity,
+            thread_name: self.thread_name,
+            overflow: self.overflow,
+            flush_interval: self.flush_interval,
+            on_error,
+            records: self.records,
+            retry: self.retry,
+            sync: self.sync,
+        }
+    }
+
+    // record_mode keeps writes apart, as spawn_records does, with room for
+    // 1024 unread records (or as many as `records` says).
+    pub fn record_mode(mut self, record_mode: bool) -> Self {
+        self.records = match record_mode {
+            true => self.records.or(Some(DEFAULT_RECORDS)),
+            false => None,
+        };
+        self
+    }
+
+    // records turns on record mode, with room for `records` unread records.
+    pub fn records(mut self, records: usize) -> Self {
+        self.records = Some(records);
+        self
+    }
+
+    // retry is as for spawn_with_retry.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    // sync is as for spawn_with_sync. Under EveryDuration, the sink is
+    // flushed at least that often, whatever flush_interval says. It can't
+    // be combined with record mode: spawning fails with InvalidInput.
+    pub fn sync(self, sync: SyncPolicy) -> Builder<E, SyncPolicy> {
+        Builder {
+            capacity: self.capacity,
+            thread_name: self.thread_name,
+            overflow: self.overflow,
+            flush_interval: self.flush_interval,
+            on_error: self.on_error,
+            records: self.records,
+            retry: self.retry,
+            sync,
+        }
+    }
+
+    // launch sets up the buffer and the sink thread's body, passing the
+    // inner writer (as a Plain) through `wrap`, and has `spawn` run it.
+    fn launch<'a, 'scope, W, O>(
+        self,
+        inner: W,
+        wrap: impl FnOnce(Plain<W, E>) -> O,
+        spawn: impl FnOnce(
+            std::thread::Builder,
+            Box<dyn FnOnce() -> Finished<W> + Send + 'a>,
+        ) -> std::io::Result<Thread<'scope, Finished<W>>>,
+    ) -> std::io::Result<(Handle, Sink<'scope, W>)>
+    where
+        W: std::io::Write + Send + 'a,
+        O: Output<Done = Finished<W>, Inner = W> + 'a,
+    {
+        let options = BufferOptions {
+            overflow: self.overflow,
+            records: self.records,
+            ..BufferOptions::default()
+        };
+        let (reader, writer) = crate::buffer::create_with_options(self.capacity, options)?;
+        let (handle, inbox) = handle(writer);
+        let (controls, orders) = orders();
+        let out = wrap(Plain {
+            retry: self.retry,
+            ..Plain::with_error_handler(inner, self.on_error)
+        });
+        let interval = self.flush_interval;
+        let evict = self.overflow == OverflowPolicy::Overwrite;
+        let record_mode = self.records.is_some();
+        let body: Box<dyn FnOnce() -> Finished<W> + Send + 'a> = match (record_mode, evict) {
+            (false, false) => Box::new(move || run(inbox, orders, reader, out, interval)),
+            (false, true) => {
+                let reader = Evicting::new(reader, false);
+                Box::new(move || run(inbox, orders, reader, out, interval))
+            }
+            (true, false) => {
+                let reader = RecordReader(reader);
+                Box::new(move || run(inbox, orders, reader, out, interval))
+            }
+            (true, true) => {
+                let reader = Evicting::new(reader, true);
+                Box::new(move || run(inbox, orders, reader, out, interval))
+            }
+        };
+        let mut thread = std::thread::Builder::new();
+        if let Some(name) = self.thread_name {
+            thread = thread.name(name);
+        }
+        let sink = Sink {
+            controls,
+            thread: spawn(thread, body)?,
+            counters: handle.counters.clone(),
+        };
+        Ok((handle, sink))
+    }
+}
+impl<E: FnMut(&WriteFailure) + Send> Builder<E> {
+    // spawn_scoped starts the sink on a thread in `scope`. It fails if the
+    // buffer or the thread can't be created.
+    pub fn spawn_scoped<'scope, 'env: 'scope, W>(
+        self,
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        inner: W,
+    ) -> std::io::Result<(Handle, Sink<'scope, W>)>
+    where
+        W: std::io::Write + Send + 'env,
+        E: 'env,
+    {
+        self.start(inner, |thread, body| {
+            thread.spawn_scoped(scope, body).map(Thread::Scoped)
+        })
+    }
+
+    // spawn is like spawn_scoped, but on an unscoped thread, as with
+    // spawn_owned.
+    pub fn spawn<W>(self, inner: W) -> std::io::Result<(Handle, Sink<'static, W>)>
+    where
+        W: std::io::Write + Send + 'static,
+        E: 'static,
+    {
+        self.start(inner, |thread, body| thread.spawn(body).map(Thread::Owned))
+    }
+
+    // start launches with Datagrams in record mode, and Plain otherwise.
+    fn start<'a, 'scope, W>(
+        self,
+        inner: W,
+        spawn: impl FnOnce(
+            std::thread::Builder,
+            Box<dyn FnOnce() -> Finished<W> + Send + 'a>,
+        ) -> std::io::Result<Thread<'scope, Finished<W>>>,
+    ) -> std::io::Result<(Handle, Sink<'scope, W>)>
+    where
+        W: std::io::Write + Send + 'a,
+        E: 'a,
+    {
+        match self.records {
+            Some(_) => self.launch(inner, Datagrams, spawn),
+            None => self.launch(inner, |out| out, spawn),
+        }
+    }
+}
+impl<E: FnMut(&WriteFailure) + Send> Builder<E, SyncPolicy> {
+    // spawn_scoped is as for an unsynced Builder, but for a Syncable
+    // writer.
+    pub fn spawn_scoped<'scope, 'env: 'scope, W>(
+        self,
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        inner: W,
+    ) -> std::io::Result<(Handle, Sink<'scope, W>)>
+    where
+        W: std::io::Write + Syncable + Send + 'env,
+        E: 'env,
+    {
+        self.start(inner, |thread, body| {
+            thread.spawn_scoped(scope, body).map(Thread::Scoped)
+        })
+    }
+
+    // spawn is as for an unsynced Builder, but for a Syncable writer.
+    pub fn spawn<W>(self, inner: W) -> std::io::Result<(Handle, Sink<'static, W>)>
+    where
+        W: std::io::Write + Syncable + Send + 'static,
+        E: 'static,
+    {
+        self.start(inner, |thread, body| thread.spawn(body).map(Thread::Owned))
+    }
+
+    // start launches with Durable.
+    fn start<'a, 'scope, W>(
+        mut self,
+        inner: W,
+        spawn: impl FnOnce(
+            std::thread::Builder,
+            Box<dyn FnOnce() -> Finished<W> + Send + 'a>,
+        ) -> std::io::Result<Thread<'scope, Finished<W>>>,
+    ) -> std::io::Result<(Handle, Sink<'scope, W>)>
+    where
+        W: std::io::Write + Syncable + Send + 'a,
+        E: 'a,
+    {
+        if self.records.is_some() {
+            let msg = "a synced sink can't run in record mode";
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+        }
+        let policy = self.sync;
+        if let SyncPolicy::EveryDuration(d) = policy {
+            self.flush_interval = Some(self.flush_interval.map_or(d, |i| i.min(d)));
+        }
+        let durable = |out| Durable {
+            out,
+            policy,
+            unsynced: 0,
+        };
+        self.launch(inner, durable, spawn)
+    }
+}
+
+fn spawn_on<'scope, 'env: 'scope, R, P, O>(
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    reader: R,
+    writer: P,
+    out: O,
+    orders: Orders<O::Inner>,
+    flush_interval: Option<Duration>,
+) -> (Handle<P>, ScopedJoinHandle<'scope, O::Done>)
+where
+    R: Consume + 'env,
+    O: Output + 'env,
+{
+    let (handle, inbox) = handle(writer);
+    let thread = scope.spawn(move || run(inbox, orders, reader, out, flush_interval));
+    (handle, thread)
+}
+
+// Inbox is the sink thread's end of the channels from its Handles.
+struct Inbox {
+    rx: Receiver<()>,
+    flushes: Receiver<Sender<std::io::Result<()>>>,
+}
+
+// Swap is a new inner writer for the sink thread, and where to send the
+// old one.
+type Swap<W> = (W, Sender<std::io::Result<W>>);
+
+// Controls is a Sink's end of the channels to its thread. The deadline is
+// set before a stop, so that the thread can check it between reads.
+struct Controls<W> {
+    stop: Sender<()>,
+    swaps: Sender<Swap<W>>,
+    deadline: Arc<OnceLock<Instant>>,
+}
+
+// Orders is the sink thread's end of the channels from its Sink.
+struct Orders<W> {
+    stop: Receiver<()>,
+    swaps: Receiver<Swap<W>>,
+    deadline: Arc<OnceLock<Instant>>,
+}
+impl<W> Orders<W> {
+    // none is for a sink without a Sink, which runs until its Handles go.
+    fn none() -> Self {
+        Self {
+            stop: never(),
+            swaps: never(),
+            deadline: Arc::default(),
+        }
+    }
+}
+
+fn orders<W>() -> (Controls<W>, Orders<W>) {
+    let (stop, stopped) = crossbeam::channel::bounded(0);
+    let (swap_tx, swaps) = crossbeam::channel::bounded(0);
+    let deadline = Arc::<OnceLock<Instant>>::default();
+    let controls = Controls {
+        stop,
+        swaps: swap_tx,
+        deadline: deadline.clone(),
+    };
+    let orders = Orders {
+        stop: stopped,
+        swaps,
+        deadline,
+    };
+    (controls, orders)
+}
+
+fn handle<P>(writer: P) -> (Handle<P>, Inbox) {
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    let (flush_tx, flushes) = crossbeam::channel::bounded(1);
+    let handle = Handle {
+        writer,
+        tx,
+        flushes: flush_tx,
+        counters: Arc::default(),
+        max_fmt: DEFAULT_MAX_FMT,
+        scratch: Vec::new(),
+    };
+    (handle, Inbox { rx, flushes })
+}
+
+// run is the sink thread: it drains `reader` into `out` whenever it's
+// notified, until either every Handle is gone or it's sent a stop. If the
+// Sink is dropped instead, it carries on until the Handles go. A swap
+// drains what's there to the old writer before replacing it.
+//
+// With a `flush_interval`, `out` is also flushed once that long has passed
+// since it was last flushed, if anything's been put since then.
+fn run<R: Consume, O: Output>(
+    inbox: Inbox,
+    mut orders: Orders<O::Inner>,
+    mut reader: R,
+    mut out: O,
+    flush_interval: Option<Duration>,
+) -> O::Done {
+    out.watch(orders.deadline.clone());
+    let mut flushed = Instant::now();
+    let mut dirty = false;
+    loop {
+        let tick = match flush_interval {
+            Some(interval) if dirty => at(flushed + interval),
+            _ => never(),
+        };
+        let mut detached = false;
+        crossbeam::channel::select! {
+            recv(inbox.rx) -> msg => if msg.is_err() { break },
+            recv(inbox.flushes) -> ack => if let Ok(ack) = ack {
+                drain(&mut reader, &mut out, &orders.deadline);
+                let _ = ack.send(out.flush());
+                (flushed, dirty) = (Instant::now(), false);
+                continue;
+            },
+            recv(tick) -> _ => {
+                drain(&mut reader, &mut out, &orders.deadline);
+                // There's no one to report an error to, but the next write
+                // will most likely see it again.
+                let _ = out.flush();
+                (flushed, dirty) = (Instant::now(), false);
+                continue;
+            },
+            recv(orders.swaps) -> msg => match msg {
+                Ok((new, ack)) => {
+                    drain(&mut reader, &mut out, &orders.deadline);
+                    let _ = ack.send(out.replace(new));
+                    (flushed, dirty) = (Instant::now(), false);
+                    continue;
+                }
+                Err(_) => detached = true,
+            },
+            recv(orders.stop) -> msg => if msg.is_ok() { break } else { detached = true },
+        }
+        if detached {
+            (orders.stop, orders.swaps) = (never(), never());
+        }
+        dirty |= drain(&mut reader, &mut out, &orders.deadline);
+    }
+    // Once all the notifiers have dropped, we are guaranteed that no more data
+    // can be buffered. There may be some existing data, so drain the buffer
+    // and then exit.
+    drain(&mut reader, &mut out, &orders.deadline);
+    out.finish()
+}
+
+// passed is whether `deadline` has been set, and has passed.
+fn passed(deadline: &OnceLock<Instant>) -> bool {
+    deadline.get().is_some_and(|d| Instant::now() >= *d)
+}
+
+// drain passes everything readable in `reader` to `out`, and says whether
+// there was anything. Once `deadline` (if it's been set) has passed, what's
+// left is abandoned instead.
+fn drain<R: Consume, O: Output>(reader: &mut R, out: &mut O, deadline: &OnceLock<Instant>) -> bool {
+    let mut any = false;
+    reader.drain(&mut |head, tail| {
+        if passed(deadline) {
+            out.abandon(head.len() + tail.len());
+        } else {
+            out.put_wrapped(head, tail);
+            any = true;
+        }
+    });
+    any
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn(scope, 100, &mut buf);
+            h.write(b"asdf").unwrap();
+            h.write(b"pqrs").unwrap();
+        });
+        assert_eq!(buf, b"asdfpqrs");
+    }
+
+    #[test]
+    fn spsc_smoke() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_spsc(scope, 100, &mut buf);
+            h.write(b"asdf").unwrap();
+            h.write(b"pqrs").unwrap();
+        });
+        assert_eq!(buf, b"asdfpqrs");
+    }
+
+    #[test]
+    fn fail_counts_dropped_writes() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn(scope, 4, &mut buf);
+            assert_eq!(h.write(b"too long"), Err(SinkWriteError::Full));
+            h.write(b"asdf").unwrap();
+            assert_eq!(h.dropped(), 1);
+        });
+        assert_eq!(buf, b"asdf");
+    }
+
+    #[test]
+    fn stats_count_exactly_what_was_dropped() {
+        let (open, gate) = crossbeam::channel::unbounded::<()>();
+        let (handle, clone) = std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 16, Gate(gate, Pipe::default()));
+            // The sink thread gets stuck on its first write, and holds on to
+            // everything it's read until then.
+            let mut clone = h.clone();
+            for i in 0..10 {
+                clone.write_lossy(&[i; 3]);
+            }
+            h.write_lossy(b"x");
+            let stats = (h.stats(), clone.stats());
+            drop(open);
+            assert_eq!(sink.shutdown().1.unwrap().handles, stats.0);
+            stats
+        });
+        assert_eq!(handle, clone);
+        assert_eq!(
+            handle,
+            HandleStats {
+                accepted_bytes: 16,
+                dropped_writes: 5,
+                dropped_bytes: 15,
+                overwritten_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn debug_describes_the_buffer() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn(scope, 4, &mut buf);
+            h.write_lossy(b"too long");
+            let d = format!("{h:?}");
+            for want in ["capacity: 4", "reader_alive: true", "dropped: 1"] {
+                assert!(d.contains(want), "{d} should contain {want}");
+            }
+        });
+    }
+
+    #[test]
+    fn block_never_drops() {
+        let mut buf = Vec::new();
+        let mut want = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_policy(scope, 8, OverflowPolicy::Block, &mut buf);
+            for i in 0..1000u32 {
+                h.write(&i.to_le_bytes()).unwrap();
+                want.extend(i.to_le_bytes());
+            }
+            assert_eq!(h.dropped(), 0);
+        });
+        assert_eq!(buf, want);
+    }
+
+    #[test]
+    fn overwrite_keeps_writes_in_order() {
+        let mut buf = Vec::new();
+        let mut dropped = 0;
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_policy(scope, 8, OverflowPolicy::Overwrite, &mut buf);
+            for i in 0..=255u8 {
+                h.write_lossy(&[i]);
+            }
+            // Writes are only dropped if the sink thread is in the middle
+            // of reading, since that data can't be overwritten.
+            dropped = h.dropped();
+        });
+        assert!(buf.windows(2).all(|w| w[0] < w[1]), "{buf:?}");
+        assert!(buf.len() + dropped <= 256);
+    }
+
+    // Stalled says when it's started on a write, and then holds it up until
+    // it's let through, or until the other end is dropped.
+    struct Stalled {
+        started: Sender<()>,
+        open: Receiver<()>,
+        pipe: Pipe,
+    }
+    impl std::io::Write for Stalled {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            let _ = self.started.send(());
+            let _ = self.open.recv();
+            self.pipe.write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // stall_on_aaaa runs `f` with a Handle to a sink with room for 8 bytes,
+    // whose inner writer is stuck writing "aaaa". It returns everything that
+    // was written, once `f` is done.
+    fn stall_on_aaaa(overflow: OverflowPolicy, f: impl FnOnce(&mut Handle) + Send) -> Vec<u8> {
+        let pipe = Pipe::default();
+        let (started, started_rx) = crossbeam::channel::unbounded();
+        let (open, open_rx) = crossbeam::channel::unbounded();
+        let out = Stalled {
+            started,
+            open: open_rx,
+            pipe: pipe.clone(),
+        };
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_policy(scope, 8, overflow, out);
+            h.write(b"aaaa").unwrap();
+            started_rx.recv().unwrap();
+            f(&mut h);
+            drop(open);
+        });
+        pipe.0.lock().unwrap().concat()
+    }
+
+    #[test]
+    fn stalled_fail_drops_the_newest() {
+        let mut stats = HandleStats::default();
+        let out = stall_on_aaaa(OverflowPolicy::Fail, |h| {
+            // The sink thread is still holding on to "aaaa".
+            h.write(b"bbbb").unwrap();
+            assert_eq!(h.write(b"cccc"), Err(SinkWriteError::Full));
+            stats = h.stats();
+        });
+        assert_eq!(out, b"aaaabbbb");
+        assert_eq!(stats.dropped_bytes, 4);
+        assert_eq!(stats.overwritten_bytes, 0);
+    }
+
+    #[test]
+    fn stalled_overwrite_drops_the_oldest() {
+        let mut stats = HandleStats::default();
+        let out = stall_on_aaaa(OverflowPolicy::Overwrite, |h| {
+            // The sink thread has a copy of "aaaa", so the whole buffer is
+            // free, and then "bbbb" makes way for "dddd".
+            h.write(b"bbbb").unwrap();
+            h.write(b"cccc").unwrap();
+            h.write(b"dddd").unwrap();
+            stats = h.stats();
+        });
+        assert_eq!(out, b"aaaaccccdddd");
+        assert_eq!(stats.accepted_bytes, 16);
+        assert_eq!(stats.dropped_writes, 0);
+        assert_eq!(stats.overwritten_bytes, 4);
+    }
+
+    #[test]
+    fn stalled_block_waits() {
+        let (done, finished) = crossbeam::channel::unbounded();
+        let mut stats = HandleStats::default();
+        let out = stall_on_aaaa(OverflowPolicy::Block, |h| {
+            h.write(b"bbbb").unwrap();
+            let mut clone = h.clone();
+            std::thread::spawn(move || {
+                clone.write(b"cccc").unwrap();
+                done.send(()).unwrap();
+            });
+            let wait = Duration::from_millis(20);
+            assert!(finished.recv_timeout(wait).is_err(), "didn't block");
+            stats = h.stats();
+        });
+        finished.recv().unwrap();
+        assert_eq!(out, b"aaaabbbbcccc");
+        assert_eq!(stats.accepted_bytes, 8);
+        assert_eq!(stats.dropped_writes, 0);
+    }
+
+    // Blocked records the length and alignment of every write.
+    #[derive(Default)]
+    struct Blocked {
+        data: Vec<u8>,
+        writes: Vec<(usize, usize)>,
+    }
+    impl std::io::Write for Blocked {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.writes.push((p.len(), p.as_ptr() as usize));
+            self.data.extend_from_slice(p);
+            Ok(p.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn direct_io_writes_whole_aligned_blocks() {
+        let mut out = Blocked::default();
+        let mut want = Vec::new();
+        let padding = std::thread::scope(|scope| {
+            let (mut h, thread) = spawn_direct_io(scope, 1024, 16, &mut out);
+            for i in 0..100u8 {
+                h.write(&[i; 3]).unwrap();
+                want.extend([i; 3]);
+            }
+            assert_eq!(h.dropped(), 0);
+            drop(h);
+            thread.join().unwrap().unwrap()
+        });
+        // 300 bytes is 18 blocks and 12 bytes.
+        assert_eq!(padding, 4);
+        for (len, addr) in out.writes {
+            assert_eq!(len, 16);
+            assert_eq!(addr % 16, 0);
+        }
+        want.extend([0; 4]);
+        assert_eq!(out.data, want);
+    }
+
+    #[test]
+    fn direct_io_reports_failed_blocks() {
+        std::thread::scope(|scope| {
+            let (mut h, thread) = spawn_direct_io(scope, 64, 16, Broken);
+            h.write(&[1; 20]).unwrap();
+            drop(h);
+            let err = thread.join().unwrap().unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+        });
+    }
+
+    #[test]
+    fn try_spawn_direct_io_rejects_bad_block_sizes() {
+        let mut out = Blocked::default();
+        std::thread::scope(|scope| {
+            assert_eq!(
+                try_spawn_direct_io(scope, 64, 12, &mut out)
+                    .err()
+                    .map(|e| e.to_string()),
+                Some("alignment 12 is not a power of two".to_string())
+            );
+        });
+        assert!(out.writes.is_empty());
+    }
+
+    // Pipe records each write it gets, like the other end of a pipe would
+    // see them.
+    #[derive(Clone, Default)]
+    struct Pipe(Arc<std::sync::Mutex<Vec<Vec<u8>>>>);
+    impl std::io::Write for Pipe {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().push(p.to_vec());
+            Ok(p.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn blocking() -> BufferOptions {
+        BufferOptions {
+            overflow: OverflowPolicy::Block,
+            ..BufferOptions::default()
+        }
+    }
+
+    #[test]
+    fn lines_are_never_split() {
+        let pipe = Pipe::default();
+        let (mut h, guard) = spawn_lines(64, blocking(), pipe.clone()).unwrap();
+        let mut want = Vec::new();
+        for i in 0..100 {
+            let line = format!("line {i}\n");
+            for piece in line.as_bytes().chunks(3) {
+                h.write(piece).unwrap();
+            }
+            want.extend(line.bytes());
+        }
+        h.write(b"no newline").unwrap();
+        want.extend(b"no newline");
+        drop(guard);
+        let writes = pipe.0.lock().unwrap().clone();
+        let (last, rest) = writes.split_last().unwrap();
+        assert!(rest.iter().all(|w| w.ends_with(b"\n")), "{writes:?}");
+        assert_eq!(last, b"no newline");
+        assert_eq!(writes.concat(), want);
+        // The thread is gone, but the Handle is still usable.
+        assert_eq!(h.write(b"lost"), Err(SinkWriteError::Closed));
+    }
+
+    #[test]
+    fn failed_lines_are_counted() {
+        let (mut h, guard) = spawn_lines(64, blocking(), Broken).unwrap();
+        h.write(b"one\ntwo\n").unwrap();
+        h.flush().unwrap();
+        assert_eq!(guard.failed_writes(), 1);
+        h.write(b"three\n").unwrap();
+        h.flush().unwrap();
+        assert_eq!(guard.failed_writes(), 2);
+    }
+
+    #[test]
+    fn long_lines_are_written_anyway() {
+        let pipe = Pipe::default();
+        let (mut h, guard) = spawn_lines(8, blocking(), pipe.clone()).unwrap();
+        for _ in 0..4 {
+            h.write(b"abcd").unwrap();
+        }
+        drop(guard);
+        assert_eq!(pipe.0.lock().unwrap().concat(), b"abcdabcdabcdabcd");
+    }
+
+    // Slow is a Pipe that takes its time over every write.
+    struct Slow(Pipe);
+    impl std::io::Write for Slow {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            self.0.write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Gate holds up each write until it's let through, or until the other
+    // end is dropped.
+    struct Gate(Receiver<()>, Pipe);
+    impl std::io::Write for Gate {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            let _ = self.0.recv();
+            self.1.write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_timeout_waits_for_room() {
+        let pipe = Pipe::default();
+        let (open, gate) = crossbeam::channel::unbounded();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 8, Gate(gate, pipe.clone()));
+            let short = Duration::from_millis(20);
+            h.write_timeout(b"aaaaaaaa", short).unwrap();
+            // The sink thread is stuck writing that out, so there's no room.
+            assert_eq!(h.write_timeout(b"b", short), Err(SinkWriteError::TimedOut));
+            open.send(()).unwrap();
+            h.write_timeout(b"b", Duration::from_secs(10)).unwrap();
+            drop(open);
+            let (_, report) = sink.shutdown();
+            assert_eq!(report.unwrap().handles.accepted_bytes, 9);
+            assert_eq!(pipe.0.lock().unwrap().concat(), b"aaaaaaaab");
+            assert_eq!(h.write_timeout(b"c", short), Err(SinkWriteError::Closed));
+        });
+    }
+
+    #[test]
+    fn write_fmt_formats_in_place() {
+        let pipe = Pipe::default();
+        let (mut h, sink) = spawn_owned(16, pipe.clone());
+        h.set_max_fmt(8);
+        let (n, s) = (12, "ab");
+        write!(h, "{n}-{s}").unwrap();
+        assert_eq!(write!(h, "{:>9}", 1), Err(SinkWriteError::TooLong));
+        write!(h, "{:>8}", 2).unwrap();
+        // There are only 3 bytes left in the buffer, unless the sink thread
+        // has already taken what's been written so far.
+        let res = write!(h, "{:>4}", 3);
+        assert!(matches!(res, Ok(()) | Err(SinkWriteError::Full)), "{res:?}");
+        let (_, report) = sink.shutdown();
+        let mut want = b"12-ab       2".to_vec();
+        let mut dropped = 9;
+        match res {
+            Ok(()) => want.extend(b"   3"),
+            Err(_) => dropped += 4,
+        }
+        assert_eq!(pipe.0.lock().unwrap().concat(), want);
+        let stats = report.unwrap().handles;
+        assert_eq!(stats.accepted_bytes, want.len());
+        assert_eq!(stats.dropped_bytes, dropped);
+        assert_eq!(write!(h, "x"), Err(SinkWriteError::Closed));
+    }
+
+    // Stall is a Display that waits to be let go once it's started.
+    struct Stall(Sender<()>, Receiver<()>);
+    impl std::fmt::Display for Stall {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.send(()).unwrap();
+            self.1.recv().unwrap();
+            f.write_str("slow")
+        }
+    }
+
+    #[test]
+    fn write_fmt_doesnt_hold_up_other_writes() {
+        let pipe = Pipe::default();
+        let (mut h, sink) = spawn_owned(64, pipe.clone());
+        let mut other = h.clone();
+        let (started_tx, started) = crossbeam::channel::bounded(0);
+        let (go, go_rx) = crossbeam::channel::bounded(0);
+        std::thread::scope(|scope| {
+            scope.spawn(|| write!(h, "{}", Stall(started_tx, go_rx)).unwrap());
+            started.recv().unwrap();
+            other.write(b"fast ").unwrap();
+            go.send(()).unwrap();
+        });
+        sink.shutdown().1.unwrap();
+        assert_eq!(pipe.0.lock().unwrap().concat(), b"fast slow");
+    }
+
+    #[test]
+    fn flush_waits_for_the_inner_writer() {
+        let pipe = Pipe::default();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_policy(scope, 64, OverflowPolicy::Block, Slow(pipe.clone()));
+            let mut want = Vec::new();
+            for i in 0..20u8 {
+                h.write(&[i; 5]).unwrap();
+                want.extend([i; 5]);
+            }
+            h.flush().unwrap();
+            assert_eq!(pipe.0.lock().unwrap().concat(), want);
+        });
+    }
+
+    // Flushes is a Pipe that records when it's flushed, as the number of
+    // writes it's had by then.
+    #[derive(Clone, Default)]
+    struct Flushes(Pipe, Arc<std::sync::Mutex<Vec<usize>>>);
+    impl std::io::Write for Flushes {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.0.write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            let writes = self.0.0.lock().unwrap().len();
+            self.1.lock().unwrap().push(writes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_interval_flushes_while_idle() {
+        let out = Flushes::default();
+        let interval = Duration::from_millis(10);
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_flush_interval(scope, 64, interval, out.clone());
+            h.write(b"asdf").unwrap();
+            std::thread::sleep(interval * 10);
+            assert_eq!(*out.1.lock().unwrap(), [1]);
+            h.write(b"pqrs").unwrap();
+            std::thread::sleep(interval * 10);
+            assert_eq!(*out.1.lock().unwrap(), [1, 2]);
+        });
+        // And once more on the way out.
+        assert_eq!(*out.1.lock().unwrap(), [1, 2, 2]);
+    }
+
+    #[test]
+    fn flush_fails_once_the_thread_is_gone() {
+        let (mut h, guard) = spawn_lines(64, blocking(), Pipe::default()).unwrap();
+        h.write(b"partial").unwrap();
+        h.flush().unwrap();
+        drop(guard);
+        assert_eq!(
+            h.flush().unwrap_err().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn shutdown_returns_the_writer() {
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 8, Vec::new());
+            h.write(b"asdf").unwrap();
+            assert_eq!(h.write(b"far too long"), Err(SinkWriteError::Full));
+            let (buf, report) = sink.shutdown();
+            assert_eq!(buf, b"asdf");
+            assert_eq!(
+                report.unwrap(),
+                SinkReport {
+                    written: 4,
+                    records: 0,
+                    abandoned: 0,
+                    handles: HandleStats {
+                        accepted_bytes: 4,
+                        dropped_writes: 1,
+                        dropped_bytes: 12,
+                        overwritten_bytes: 0,
+                    },
+                    destinations: vec![],
+                    error: None,
+                }
+            );
+            // The Handle outlives the thread harmlessly.
+            assert_eq!(h.write(b"lost"), Err(SinkWriteError::Closed));
+        });
+    }
+
+    // Broken fails every write.
+    struct Broken;
+    impl std::io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::StorageFull.into())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // start_owned sets up a sink, and lets it outlive the call.
+    fn start_owned(pipe: Pipe) -> (Handle, Sink<'static, Pipe>) {
+        spawn_owned(64, pipe)
+    }
+
+    #[test]
+    fn owned_sink_outlives_its_creator() {
+        let pipe = Pipe::default();
+        let (h, sink) = start_owned(pipe.clone());
+        let writers: Vec<_> = (0..4u8)
+            .map(|i| {
+                let mut h = h.clone();
+                std::thread::spawn(move || h.write(&[i; 4]).unwrap())
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        let (out, report) = sink.shutdown();
+        assert_eq!(report.unwrap().written, 16);
+        let mut writes = out.0.lock().unwrap().concat();
+        writes.sort();
+        assert_eq!(writes, [[0; 4], [1; 4], [2; 4], [3; 4]].concat());
+        assert_eq!(h.stats().accepted_bytes, 16);
+    }
+
+    #[test]
+    fn replace_writer_splits_the_stream() {
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 16, Vec::new());
+            h.write(b"one").unwrap();
+            h.write(b"two").unwrap();
+            assert_eq!(sink.replace_writer(Vec::new()).unwrap(), b"onetwo");
+            h.write(b"three").unwrap();
+            assert_eq!(sink.replace_writer(Vec::new()).unwrap(), b"three");
+            let (out, report) = sink.shutdown();
+            assert_eq!(out, b"");
+            assert_eq!(report.unwrap().written, 11);
+        });
+    }
+
+    #[test]
+    fn replace_writer_loses_nothing_mid_stream() {
+        let want: Vec<u8> = (0..1000)
+            .flat_map(|i| format!("{i}\n").into_bytes())
+            .collect();
+        let mut got = Vec::new();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 64, Vec::new());
+            // The thread exits once every Handle is gone, so keep one until
+            // the swaps are done.
+            let keep = h.clone();
+            let producer = scope.spawn(move || {
+                for i in 0..1000 {
+                    let line = format!("{i}\n");
+                    while h.write(line.as_bytes()).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            for _ in 0..20 {
+                got.extend(sink.replace_writer(Vec::new()).unwrap());
+            }
+            drop(keep);
+            producer.join().unwrap();
+            got.extend(sink.shutdown().0);
+        });
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn shutdown_reports_the_first_error() {
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 8, Broken);
+            h.write(b"asdf").unwrap();
+            let (_, report) = sink.shutdown();
+            assert_eq!(report.unwrap_err().kind(), std::io::ErrorKind::StorageFull);
+        });
+    }
+
+    #[test]
+    fn records_are_written_one_at_a_time() {
+        let pipe = Pipe::default();
+        let mut want = Vec::new();
+        let report = std::thread::scope(|scope| {
+            let slow = Slow(pipe.clone());
+            let (mut h, sink) = spawn_records(scope, 256, 64, slow, |f| panic!("{f:?}"));
+            for i in 1..=30u8 {
+                let record = vec![i; usize::from(i % 7) + 1];
+                h.write(&record).unwrap();
+                want.push(record);
+            }
+            assert_eq!(h.write(b""), Err(SinkWriteError::Full));
+            sink.shutdown().1.unwrap()
+        });
+        assert_eq!(*pipe.0.lock().unwrap(), want);
+        assert_eq!(report.records, 30);
+        assert_eq!(report.written, want.concat().len() as u64);
+    }
+
+    #[test]
+    fn builder_defaults_to_spawn_sink() {
+        std::thread::scope(|scope| {
+            let (mut h, sink) = Builder::new().spawn_scoped(scope, Vec::new()).unwrap();
+            h.write(b"asdf").unwrap();
+            h.write(b"pqrs").unwrap();
+            let (out, report) = sink.shutdown();
+            assert_eq!(out, b"asdfpqrs");
+            assert_eq!(report.unwrap().written, 8);
+        });
+        let (mut h, sink) = Builder::new().capacity(4).spawn(Vec::new()).unwrap();
+        assert_eq!(h.write(b"too long"), Err(SinkWriteError::Full));
+        h.write(b"asdf").unwrap();
+        assert_eq!(sink.shutdown().0, b"asdf");
+    }
+
+    // Names records the name of the thread each write is made on.
+    #[derive(Default)]
+    struct Names(Vec<Option<String>>);
+    impl std::io::Write for Names {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            let name = std::thread::current().name().map(str::to_string);
+            self.0.push(name);
+            Ok(p.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn builder_names_the_thread() {
+        let (mut h, sink) = Builder::new()
+            .thread_name("audit-log")
+            .spawn(Names::default())
+            .unwrap();
+        h.write(b"asdf").unwrap();
+        let names = sink.shutdown().0;
+        assert_eq!(names.0, [Some("audit-log".to_string())]);
+    }
+
+    #[test]
+    fn builder_overflow_blocks() {
+        let pipe = Pipe::default();
+        let (open, gate) = crossbeam::channel::unbounded();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = Builder::new()
+                .capacity(8)
+                .overflow(OverflowPolicy::Block)
+                .spawn_scoped(scope, Gate(gate, pipe.clone()))
+                .unwrap();
+            h.write(b"aaaaaaaa").unwrap();
+            // The sink thread is stuck writing that out, so this has to wait
+            // for it, instead of failing.
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                drop(open);
+            });
+            h.write(b"b").unwrap();
+            sink.shutdown().1.unwrap();
+        });
+        assert_eq!(pipe.0.lock().unwrap().concat(), b"aaaaaaaab");
+    }
+
+    #[test]
+    fn builder_flush_interval() {
+        let out = Flushes::default();
+        let interval = Duration::from_millis(10);
+        let (mut h, sink) = Builder::new()
+            .flush_interval(interval)
+            .spawn(out.clone())
+            .unwrap();
+        h.write(b"asdf").unwrap();
+        std::thread::sleep(interval * 10);
+        assert_eq!(*out.1.lock().unwrap(), [1]);
+        sink.shutdown().1.unwrap();
+    }
+
+    #[test]
+    fn builder_on_error() {
+        let mut failures = Vec::new();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = Builder::new()
+                .on_error(|f| failures.push(f.len))
+                .spawn_scoped(scope, Broken)
+                .unwrap();
+            h.write(b"asdf").unwrap();
+            assert!(sink.shutdown().1.is_err());
+        });
+        assert_eq!(failures, [4]);
+    }
+
+    #[test]
+    fn builder_record_mode() {
+        let pipe = Pipe::default();
+        let (mut h, sink) = Builder::new()
+            .record_mode(true)
+            .spawn(Slow(pipe.clone()))
+            .unwrap();
+        for i in 1..=10u8 {
+            h.write(&vec![i; usize::from(i)]).unwrap();
+        }
+        assert_eq!(sink.shutdown().1.unwrap().records, 10);
+        let want: Vec<_> = (1..=10u8).map(|i| vec![i; usize::from(i)]).collect();
+        assert_eq!(*pipe.0.lock().unwrap(), want);
+    }
+
+    #[test]
+    fn builder_retry() {
+        let retry = RetryPolicy {
+            max_attempts: 2,
+            delay: Duration::ZERO,
+            retryable: vec![std::io::ErrorKind::StorageFull],
+            ..RetryPolicy::default()
+        };
+        let mut flaky = Flaky::default();
+        let mut failures = 0;
+        std::thread::scope(|scope| {
+            let (mut h, sink) = Builder::new()
+                .retry(retry)
+                .on_error(|_| failures += 1)
+                .spawn_scoped(scope, &mut flaky)
+                .unwrap();
+            for _ in 0..20 {
+                h.write(b"asdf").unwrap();
+                h.flush().unwrap();
+            }
+            assert_eq!(sink.shutdown().1.unwrap().written, 80);
+        });
+        // Every write after the first fails once, and goes through on the
+        // retry.
+        assert_eq!(flaky.failed, 19);
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn builder_sync() {
+        let out = Recorder::default();
+        let (mut h, sink) = Builder::new()
+            .capacity(64)
+            .thread_name("synced")
+            .sync(SyncPolicy::OnFlushBarrier)
+            .spawn(out.clone())
+            .unwrap();
+        h.write(b"ab").unwrap();
+        h.flush().unwrap();
+        h.write(b"cde").unwrap();
+        sink.shutdown().1.unwrap();
+        assert_eq!(*out.syncs.lock().unwrap(), [2, 5]);
+
+        let err = Builder::new()
+            .record_mode(true)
+            .sync(SyncPolicy::Never)
+            .spawn(Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    // Sleepy takes its time over every write.
+    struct Sleepy(Duration, Pipe);
+    impl std::io::Write for Sleepy {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(self.0);
+            self.1.write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shutdown_timeout_abandons_the_rest() {
+        let pipe = Pipe::default();
+        let out = Sleepy(Duration::from_millis(20), pipe.clone());
+        let (mut h, sink) = Builder::new().record_mode(true).spawn(out).unwrap();
+        for i in 0..50u8 {
+            h.write(&[i; 4]).unwrap();
+        }
+        let start = Instant::now();
+        let (_, report) = sink.shutdown_timeout(Duration::from_millis(50));
+        // The deadline, plus the write that was under way then.
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(report.abandoned > 0);
+        assert_eq!(report.written + report.abandoned, 200);
+        // Whatever was written went in order, with nothing after a gap.
+        let written = pipe.0.lock().unwrap().concat();
+        assert_eq!(written.len() as u64, report.written);
+        let want: Vec<u8> = (0..50u8).flat_map(|i| [i; 4]).collect();
+        assert_eq!(written, want[..written.len()]);
+    }
+
+    #[test]
+    fn shutdown_timeout_writes_everything_in_time() {
+        let (mut h, sink) = Builder::new().spawn(Vec::new()).unwrap();
+        h.write(b"asdf").unwrap();
+        let (out, report) = sink.shutdown_timeout(Duration::from_secs(10));
+        assert_eq!(out, b"asdf");
+        assert_eq!(report.abandoned, 0);
+    }
+
+    #[test]
+    fn shutdown_timeout_reports_errors() {
+        let (mut h, sink) = Builder::new().spawn(Broken).unwrap();
+        h.write(b"asdf").unwrap();
+        let (_, report) = sink.shutdown_timeout(Duration::from_secs(10));
+        assert_eq!(report.error, Some(std::io::ErrorKind::StorageFull));
+        assert_eq!(report.handles.accepted_bytes, 4);
+    }
+
+    // deadline is a shutdown deadline `after` from now.
+    fn deadline(after: Duration) -> Arc<OnceLock<Instant>> {
+        let deadline = Arc::<OnceLock<Instant>>::default();
+        deadline.set(Instant::now() + after).unwrap();
+        deadline
+    }
+
+    #[test]
+    fn retries_stop_at_the_deadline() {
+        let retry = RetryPolicy {
+            max_attempts: u32::MAX,
+            ..RetryPolicy::default()
+        };
+        let stuck = stutter(std::io::ErrorKind::WouldBlock, 1, 1, 0);
+        let mut out = Plain {
+            retry: retry.clone(),
+            ..Plain::new(stuck)
+        };
+        out.watch(deadline(Duration::from_millis(20)));
+        let start = Instant::now();
+        out.put(b"abcd");
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!((out.abandoned, out.failures), (4, 0));
+
+        let stuck = stutter(std::io::ErrorKind::WouldBlock, 1, 1, 0);
+        let mut out = Datagrams(Plain {
+            retry,
+            ..Plain::new(stuck)
+        });
+        out.watch(deadline(Duration::from_millis(20)));
+        out.put(b"abcd");
+        assert_eq!((out.0.abandoned, out.0.failures), (4, 0));
+    }
+
+    // Short only takes up to 3 bytes per write.
+    struct Short(Pipe);
+    impl std::io::Write for Short {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.0.write(&p[..p.len().min(3)])
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_record_writes_are_failures() {
+        let pipe = Pipe::default();
+        let mut failures = Vec::new();
+        let report = std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_records(scope, 64, 8, Short(pipe.clone()), |f| {
+                assert_eq!(f.error.kind(), std::io::ErrorKind::WriteZero);
+                failures.push(f.len);
+            });
+            h.write(b"abc").unwrap();
+            h.write(b"defgh").unwrap();
+            h.write(b"ij").unwrap();
+            sink.shutdown().1
+        });
+        assert_eq!(report.unwrap_err().kind(), std::io::ErrorKind::WriteZero);
+        assert_eq!(*pipe.0.lock().unwrap(), [&b"abc"[..], b"def", b"ij"]);
+        assert_eq!(failures, [2]);
+    }
+
+    #[test]
+    fn dropped_sink_keeps_running() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_sink(scope, 8, &mut buf);
+            drop(sink);
+            h.write(b"asdf").unwrap();
+            h.flush().unwrap();
+            h.write(b"pqrs").unwrap();
+        });
         assert_eq!(buf, b"asdfpqrs");
     }
+
+    // Flaky fails every other write.
+    #[derive(Default)]
+    struct Flaky {
+        calls: usize,
+        failed: usize,
+    }
+    impl std::io::Write for Flaky {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls.is_multiple_of(2) {
+                self.failed += 1;
+                return Err(std::io::ErrorKind::StorageFull.into());
+            }
+            Ok(p.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn error_handler_sees_every_failure() {
+        let mut flaky = Flaky::default();
+        let mut failures = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_error_handler(scope, 64, &mut flaky, |f| {
+                assert_eq!(f.error.kind(), std::io::ErrorKind::StorageFull);
+                failures.push((f.len, f.consecutive));
+            });
+            for _ in 0..20 {
+                h.write(b"asdf").unwrap();
+                h.flush().unwrap();
+            }
+        });
+        assert_eq!(flaky.failed, 10);
+        assert_eq!(failures, [(4, 1); 10]);
+
+        let mut consecutive = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_error_handler(scope, 64, Broken, |f| {
+                consecutive.push(f.consecutive);
+            });
+            for _ in 0..3 {
+                h.write(b"asdf").unwrap();
+                h.flush().unwrap();
+            }
+        });
+        assert_eq!(consecutive, [1, 2, 3]);
+    }
+
+    // Shapes records the length of every slice passed to each call, and only
+    // takes up to `limit` bytes per vectored write.
+    struct Shapes {
+        calls: Vec<Vec<usize>>,
+        data: Vec<u8>,
+        limit: usize,
+    }
+    impl std::io::Write for Shapes {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.calls.push(vec![p.len()]);
+            self.data.extend_from_slice(p);
+            Ok(p.len())
+        }
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+            self.calls.push(bufs.iter().map(|b| b.len()).collect());
+            let all: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+            let n = all.len().min(self.limit);
+            self.data.extend_from_slice(&all[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // drain_wrapped runs a sink over a buffer whose unread data wraps, as
+    // "bbbb" at the end and "cccc" at the start, and returns `out`.
+    fn drain_wrapped(out: Shapes) -> Shapes {
+        let (mut reader, mut writer) = buffer::create(10);
+        assert!(writer.try_write(b"aaaaa"));
+        let lease = reader.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        drop(lease);
+        assert!(writer.try_write(b"cccc"));
+        std::thread::scope(|scope| {
+            let (h, thread) =
+                spawn_on(scope, reader, writer, Plain::new(out), Orders::none(), None);
+            drop(h);
+            let done = thread.join().unwrap();
+            assert_eq!((done.written, done.error.is_none()), (8, true));
+            done.inner
+        })
+    }
+
+    #[test]
+    fn wrapped_data_is_one_vectored_write() {
+        let out = drain_wrapped(Shapes {
+            calls: Vec::new(),
+            data: Vec::new(),
+            limit: usize::MAX,
+        });
+        assert_eq!(out.calls, [[4, 4]]);
+        assert_eq!(out.data, b"bbbbcccc");
+
+        // A short write carries on from where it stopped.
+        let out = drain_wrapped(Shapes {
+            calls: Vec::new(),
+            data: Vec::new(),
+            limit: 6,
+        });
+        assert_eq!(out.calls, [vec![4, 4], vec![2]]);
+        assert_eq!(out.data, b"bbbbcccc");
+    }
+
+    // Stutter fails with `kind` `fails` times, then takes up to `take`
+    // bytes, and does that over again, until it's done that `budget` times.
+    // After that, it always fails.
+    struct Stutter {
+        kind: std::io::ErrorKind,
+        fails: usize,
+        take: usize,
+        budget: usize,
+        calls: usize,
+        data: Vec<u8>,
+    }
+    impl std::io::Write for Stutter {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.budget == 0 || !self.calls.is_multiple_of(self.fails + 1) {
+                return Err(self.kind.into());
+            }
+            self.budget -= 1;
+            let n = p.len().min(self.take);
+            self.data.extend_from_slice(&p[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stutter(kind: std::io::ErrorKind, fails: usize, take: usize, budget: usize) -> Stutter {
+        Stutter {
+            kind,
+            fails,
+            take,
+            budget,
+            calls: 0,
+            data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn tee_failures_only_affect_their_destination() {
+        let mut good = Vec::new();
+        let mut bad = stutter(std::io::ErrorKind::StorageFull, 1, 2, usize::MAX);
+        let report = std::thread::scope(|scope| {
+            let writers: Destinations = vec![Box::new(&mut good), Box::new(&mut bad)];
+            let (mut h, sink) = spawn_tee(scope, 64, TeePolicy::Skip, writers);
+            // The first write fails on `bad` outright; the second gets two
+            // bytes in before it fails.
+            h.write(b"abcd").unwrap();
+            h.flush().unwrap();
+            h.write(b"efgh").unwrap();
+            let (writers, report) = sink.shutdown();
+            assert_eq!(writers.len(), 2);
+            report.unwrap()
+        });
+        assert_eq!(good, b"abcdefgh");
+        assert_eq!(bad.data, b"ef");
+        assert_eq!(report.written, 10);
+        assert_eq!(
+            report.destinations,
+            [
+                DestinationStats {
+                    written: 8,
+                    dropped_bytes: 0,
+                    failed_writes: 0,
+                    error: None,
+                },
+                DestinationStats {
+                    written: 2,
+                    dropped_bytes: 6,
+                    failed_writes: 2,
+                    error: Some(std::io::ErrorKind::StorageFull),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tee_backpressure_loses_nothing() {
+        let mut good = Vec::new();
+        let mut bad = stutter(std::io::ErrorKind::WouldBlock, 2, 3, usize::MAX);
+        let mut want = Vec::new();
+        let report = std::thread::scope(|scope| {
+            let writers: Destinations = vec![Box::new(&mut good), Box::new(&mut bad)];
+            let (mut h, sink) = spawn_tee(scope, 64, TeePolicy::Backpressure, writers);
+            for i in 0..5u8 {
+                h.write(&[i; 4]).unwrap();
+                want.extend_from_slice(&[i; 4]);
+            }
+            sink.shutdown().1.unwrap()
+        });
+        assert_eq!(good, want);
+        assert_eq!(bad.data, want);
+        assert_eq!(report.written, 40);
+        assert!(report.destinations.iter().all(|d| d.failed_writes == 0));
+    }
+
+    #[test]
+    fn tee_backpressure_skips_permanent_errors() {
+        let mut good = Vec::new();
+        let mut bad = stutter(std::io::ErrorKind::BrokenPipe, 1, 2, usize::MAX);
+        let report = std::thread::scope(|scope| {
+            let writers: Destinations = vec![Box::new(&mut good), Box::new(&mut bad)];
+            let (mut h, sink) = spawn_tee(scope, 64, TeePolicy::Backpressure, writers);
+            h.write(b"abcd").unwrap();
+            sink.shutdown().1.unwrap()
+        });
+        assert_eq!(good, b"abcd");
+        assert!(bad.data.is_empty());
+        let d = report.destinations[1];
+        assert_eq!((d.dropped_bytes, d.failed_writes), (4, 1));
+        assert_eq!(d.error, Some(std::io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn tee_backpressure_gives_up_at_the_deadline() {
+        let mut good = Vec::new();
+        let mut stuck = stutter(std::io::ErrorKind::WouldBlock, 1, 1, 0);
+        let report = std::thread::scope(|scope| {
+            let writers: Destinations = vec![Box::new(&mut good), Box::new(&mut stuck)];
+            let (mut h, sink) = spawn_tee(scope, 64, TeePolicy::Backpressure, writers);
+            h.write(b"abcd").unwrap();
+            h.write(b"efgh").unwrap();
+            let start = Instant::now();
+            let (_, report) = sink.shutdown_timeout(Duration::from_millis(50));
+            assert!(start.elapsed() < Duration::from_millis(500));
+            report
+        });
+        assert!(stuck.data.is_empty());
+        // Nothing ever got through to `stuck`.
+        assert_eq!(report.abandoned, 8);
+        assert_eq!(report.destinations[1].failed_writes, 0);
+    }
+
+    #[test]
+    fn tee_replaces_writers_one_for_one() {
+        std::thread::scope(|scope| {
+            let writers: Destinations = vec![Box::new(Vec::new()), Box::new(Vec::new())];
+            let (mut h, sink) = spawn_tee(scope, 64, TeePolicy::Skip, writers);
+            h.write(b"asdf").unwrap();
+            let err = sink.replace_writer(vec![Box::new(Vec::new())]).err();
+            assert_eq!(err.unwrap().kind(), std::io::ErrorKind::InvalidInput);
+            let old = sink.replace_writer(vec![Box::new(Vec::new()), Box::new(Vec::new())]);
+            assert_eq!(old.unwrap().len(), 2);
+            h.write(b"pq").unwrap();
+            let report = sink.shutdown().1.unwrap();
+            let written: Vec<_> = report.destinations.iter().map(|d| d.written).collect();
+            assert_eq!(written, [6, 6]);
+        });
+    }
+
+    // Recorder records how much had been written at each sync.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        data: Arc<std::sync::Mutex<Vec<u8>>>,
+        syncs: Arc<std::sync::Mutex<Vec<usize>>>,
+        broken: bool,
+    }
+    impl std::io::Write for Recorder {
+        fn write(&mut self, p: &[u8]) -> std::io::Result<usize> {
+            self.data.lock().unwrap().write(p)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Syncable for Recorder {
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            if self.broken {
+                return Err(std::io::ErrorKind::StorageFull.into());
+            }
+            let len = self.data.lock().unwrap().len();
+            self.syncs.lock().unwrap().push(len);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sync_every_n_bytes() {
+        let out = Recorder::default();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_with_sync(scope, 64, SyncPolicy::EveryNBytes(8), out.clone());
+            for i in 0..5u8 {
+                h.write(&[i; 4]).unwrap();
+                h.flush().unwrap();
+            }
+            assert_eq!(*out.syncs.lock().unwrap(), [8, 16]);
+            // Whatever's left is synced on the way out.
+            sink.shutdown().1.unwrap();
+        });
+        assert_eq!(*out.syncs.lock().unwrap(), [8, 16, 20]);
+    }
+
+    #[test]
+    fn sync_on_flush_barrier() {
+        let out = Recorder::default();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_with_sync(scope, 64, SyncPolicy::OnFlushBarrier, out.clone());
+            h.write(b"ab").unwrap();
+            h.flush().unwrap();
+            // Nothing new to sync.
+            h.flush().unwrap();
+            h.write(b"cde").unwrap();
+            h.flush().unwrap();
+            assert_eq!(*out.syncs.lock().unwrap(), [2, 5]);
+            sink.shutdown().1.unwrap();
+        });
+
+        let broken = Recorder {
+            broken: true,
+            ..Recorder::default()
+        };
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_with_sync(scope, 64, SyncPolicy::OnFlushBarrier, broken);
+            h.write(b"ab").unwrap();
+            let err = h.flush().unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+            assert!(sink.shutdown().1.is_ok());
+        });
+    }
+
+    #[test]
+    fn sync_every_duration_while_idle() {
+        let out = Recorder::default();
+        let policy = SyncPolicy::EveryDuration(Duration::from_millis(10));
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_with_sync(scope, 64, policy, out.clone());
+            h.write(b"ab").unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while out.syncs.lock().unwrap().is_empty() {
+                assert!(Instant::now() < deadline, "never synced");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(*out.syncs.lock().unwrap(), [2]);
+            sink.shutdown().1.unwrap();
+        });
+        assert_eq!(*out.syncs.lock().unwrap(), [2]);
+    }
+
+    #[test]
+    fn never_sync() {
+        let out = Recorder::default();
+        std::thread::scope(|scope| {
+            let (mut h, sink) = spawn_with_sync(scope, 64, SyncPolicy::Never, out.clone());
+            h.write(b"ab").unwrap();
+            h.flush().unwrap();
+            sink.shutdown().1.unwrap();
+        });
+        assert!(out.syncs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retries_lose_nothing() {
+        let retry = RetryPolicy {
+            max_attempts: 4,
+            delay: Duration::from_micros(10),
+            ..RetryPolicy::default()
+        };
+        let mut out = stutter(std::io::ErrorKind::WouldBlock, 3, 5, usize::MAX);
+        let mut want = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_retry(scope, 64, retry, &mut out, |f| panic!("{f:?}"));
+            for i in 0..20u8 {
+                h.write(&[i; 7]).unwrap();
+                want.extend([i; 7]);
+                h.flush().unwrap();
+            }
+        });
+        assert_eq!(out.data, want);
+    }
+
+    #[test]
+    fn retries_run_out() {
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            delay: Duration::from_micros(10),
+            ..RetryPolicy::default()
+        };
+        // The write gets 2 bytes through before failing for good.
+        let mut out = stutter(std::io::ErrorKind::WouldBlock, 1, 2, 1);
+        let mut lost = Vec::new();
+        std::thread::scope(|scope| {
+            let mut h = spawn_with_retry(scope, 64, retry, &mut out, |f| lost.push(f.len));
+            h.write(b"abcdef").unwrap();
+            h.flush().unwrap();
+        });
+        assert_eq!((out.calls, &out.data[..]), (5, &b"ab"[..]));
+        assert_eq!(lost, [4]);
+
+        // Errors that aren't retryable aren't retried.
+        let mut out = stutter(std::io::ErrorKind::PermissionDenied, 1, 64, usize::MAX);
+        let mut lost = Vec::new();
+        std::thread::scope(|scope| {
+            let retry = RetryPolicy {
+                max_attempts: 3,
+                ..RetryPolicy::default()
+            };
+            let mut h = spawn_with_retry(scope, 64, retry, &mut out, |f| lost.push(f.len));
+            h.write(b"abcdef").unwrap();
+            h.flush().unwrap();
+        });
+        assert_eq!((out.calls, lost), (1, vec![6]));
+    }
+
+    #[test]
+    fn try_spawn_rejects_zero_capacity() {
+        let mut buf = Vec::new();
+        std::thread::scope(|scope| {
+            assert_eq!(
+                try_spawn(scope, 0, &mut buf).err(),
+                Some(CreateError::ZeroCapacity)
+            );
+        });
+        assert!(buf.is_empty());
+    }
 }
@@ -0,0 +1,241 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, IoSlice, Write},
+    path::{Path, PathBuf},
+};
+
+// RotatingFile is a log file that's capped at `max_bytes`: a write that
+// would take it past that first moves it aside to `<path>.1` (after moving
+// `<path>.1` to `<path>.2`, and so on), and starts a new one. Only the
+// newest `max_files` of those are kept. As a sink's inner writer, all of
+// that happens on the sink thread.
+//
+// A single write call is never split across files, so in a record sink
+// (see spawn_records), neither is a record. A write that's larger than
+// `max_bytes` by itself gets a file of its own.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    // written is how big the current file is.
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+    // resume is where a rotation that failed partway through picks up: at
+    // the step that moves file i - 1 to i (file 0 being the current one),
+    // or at opening the new file for 0.
+    resume: Option<usize>,
+}
+
+impl RotatingFile {
+    // new opens `path` to append to, creating it if needed. What's already
+    // there counts towards `max_bytes`.
+    pub fn new(path: impl AsRef<Path>, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+            max_files,
+            resume: None,
+        })
+    }
+
+    // make_room rotates the file if `len` more bytes won't fit in it.
+    fn make_room(&mut self, len: usize) -> io::Result<()> {
+        if self.written > 0 && self.written + len as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    // rotate shifts every old file along by one, dropping the oldest, and
+    // starts a new file at `path`. A step that fails is tried again on the
+    // next write, which carries on from there, so no file is shifted twice.
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut next = self.resume.take().unwrap_or(self.max_files);
+        while next > 0 {
+            let from = match next - 1 {
+                0 => self.path.clone(),
+                i => self.numbered(i),
+            };
+            if let Err(err) = rename_if_exists(&from, &self.numbered(next)) {
+                self.resume = Some(next);
+                return Err(err);
+            }
+            next -= 1;
+        }
+        if self.max_files == 0
+            && let Err(err) = std::fs::remove_file(&self.path)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            return Err(err);
+        }
+        match open(&self.path) {
+            Ok(file) => self.file = file,
+            Err(err) => {
+                self.resume = Some(0);
+                return Err(err);
+            }
+        }
+        self.written = 0;
+        Ok(())
+    }
+
+    // sync_data syncs the current file (see File::sync_data). Old files
+    // aren't written to again, so they're synced as of the last call to
+    // this before they were rotated out.
+    pub fn sync_data(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    // numbered is the path of the `i`th newest old file.
+    fn numbered(&self, i: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{i}"));
+        path.into()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.make_room(buf.len())?;
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.make_room(bufs.iter().map(|b| b.len()).sum())?;
+        let n = self.file.write_vectored(bufs)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // scratch is an empty directory of its own for a test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bbuf-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn contents(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read(&path).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn rotates_at_the_cap() {
+        let dir = scratch("rotates_at_the_cap");
+        let mut f = RotatingFile::new(dir.join("foo.log"), 10, 2).unwrap();
+        f.write_all(b"aaaaaa").unwrap();
+        f.write_all(b"bbbb").unwrap();
+        assert_eq!(contents(&dir).len(), 1);
+        for p in [b"cccccc", b"dddddd", b"eeeeee"] {
+            f.write_all(p).unwrap();
+        }
+        assert_eq!(
+            contents(&dir),
+            [
+                ("foo.log".to_string(), b"eeeeee".to_vec()),
+                ("foo.log.1".to_string(), b"dddddd".to_vec()),
+                ("foo.log.2".to_string(), b"cccccc".to_vec()),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_no_old_files() {
+        let dir = scratch("keeps_no_old_files");
+        let mut f = RotatingFile::new(dir.join("foo.log"), 4, 0).unwrap();
+        f.write_all(b"old").unwrap();
+        // Too big for any file, so it gets one of its own.
+        f.write_all(b"toolong").unwrap();
+        f.write_all(b"new").unwrap();
+        assert_eq!(contents(&dir), [("foo.log".to_string(), b"new".to_vec())]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_rotation_carries_on() {
+        let dir = scratch("a_failed_rotation_carries_on");
+        let mut f = RotatingFile::new(dir.join("foo.log"), 4, 3).unwrap();
+        for p in [b"aaaa", b"bbbb", b"cccc"] {
+            f.write_all(p).unwrap();
+        }
+        // Act out a rotation that shifted the old files along, but then
+        // failed to move foo.log aside.
+        std::fs::rename(dir.join("foo.log.2"), dir.join("foo.log.3")).unwrap();
+        std::fs::rename(dir.join("foo.log.1"), dir.join("foo.log.2")).unwrap();
+        f.resume = Some(1);
+        f.write_all(b"dddd").unwrap();
+        assert_eq!(
+            contents(&dir),
+            [
+                ("foo.log".to_string(), b"dddd".to_vec()),
+                ("foo.log.1".to_string(), b"cccc".to_vec()),
+                ("foo.log.2".to_string(), b"bbbb".to_vec()),
+                ("foo.log.3".to_string(), b"aaaa".to_vec()),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_are_never_split() {
+        let dir = scratch("records_are_never_split");
+        let path = dir.join("foo.log");
+        let want: Vec<u8> = (0..20u8).flat_map(|i| [b'a' + i; 7]).collect();
+        std::thread::scope(|scope| {
+            let f = RotatingFile::new(&path, 20, 100).unwrap();
+            let (mut h, sink) = crate::sink::spawn_records(scope, 256, 32, f, |e| panic!("{e:?}"));
+            for record in want.chunks(7) {
+                h.write(record).unwrap();
+            }
+            sink.shutdown().1.unwrap();
+        });
+        let files = contents(&dir);
+        assert_eq!(files.len(), 10);
+        // Two records fit in each file; the oldest is the highest numbered.
+        let mut got = Vec::new();
+        for (_, data) in files[1..].iter().rev().chain(&files[..1]) {
+            assert_eq!(data.len(), 14);
+            got.extend_from_slice(data);
+        }
+        assert_eq!(got, want);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+
+use crossbeam::utils::CachePadded;
+
+use crate::{
+    error::CreateError,
+    storage::Storage,
+    sync::{Arc, AtomicU64, Mutex, Ordering, lock},
+};
+
+// Shared is the multi-consumer counterpart of spsc.rs's Shared. Every byte
+// that's written is handed to exactly one consumer.
+//
+//   - The Writer publishes data by advancing `write` (Release).
+//   - Consumers compete to claim everything between `claim` and `write` by
+//     advancing `claim` with a CAS.
+//   - Claims can be released in any order, but space can only be reused once
+//     every claim before it has been released too. `released` is the end of
+//     that fully-released prefix; it's what the Writer checks for free
+//     space. Releases that arrive early wait in `pending`.
+//
+// Offsets count bytes since the buffer was created, so `write - released` is
+// always the number of bytes in use. Like mpsc, a write that doesn't fit
+// before the end of the storage goes at the start instead, and the space it
+// skips is padding: `padded` is where that padding starts. A claim never
+// extends into padding or past the end of the storage, so every claim holds
+// whole writes. The first consumer to get to the padding claims it and
+// releases it straight away.
+struct Shared {
+    write: CachePadded<AtomicU64>,
+    claim: CachePadded<AtomicU64>,
+    released: CachePadded<AtomicU64>,
+    // padded is only ever stored before `write` moves past it, and until
+    // the storage has gone all the way round once more, so a consumer can
+    // tell whether it's for the lap that it's claiming from.
+    padded: AtomicU64,
+    // pending maps the start of each released claim that's waiting on an
+    // earlier one to its end.
+    pending: Mutex<BTreeMap<u64, u64>>,
+    capacity: usize,
+    data: Storage,
+}
+
+// Reader and Writer mirror buffer.rs, except that it's the Reader that can be
+// cloned: the algorithm relies on there being exactly one producer.
+#[derive(Clone)]
+pub struct Reader(Arc<Shared>);
+pub struct Writer(Arc<Shared>);
+
+pub fn try_create(capacity: usize) -> Result<(Reader, Writer), CreateError> {
+    let data = Storage::alloc(capacity, 1)?;
+    let shared = Arc::new(Shared {
+        write: CachePadded::new(AtomicU64::new(0)),
+        claim: CachePadded::new(AtomicU64::new(0)),
+        released: CachePadded::new(AtomicU64::new(0)),
+        padded: AtomicU64::new(u64::MAX),
+        pending: Mutex::new(BTreeMap::new()),
+        capacity,
+        data,
+    });
+    Ok((Reader(shared.clone()), Writer(shared)))
+}
+
+// create is like try_create, but panics if the capacity is invalid.
+pub fn create(capacity: usize) -> (Reader, Writer) {
+    match try_create(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+impl Shared {
+    fn offset(&self, pos: u64) -> usize {
+        (pos % self.capacity as u64) as usize
+    }
+
+    // lap is how many times the storage had been gone round by `pos`.
+    fn lap(&self, pos: u64) -> u64 {
+        pos / self.capacity as u64
+    }
+
+    // release gives back the claim from `start` to `end`. Space is only
+    // reused once every claim before it has been given back too.
+    fn release(&self, start: u64, end: u64) {
+        let mut pending = lock(&self.pending);
+        let mut released = self.released.load(Ordering::Relaxed);
+        if start != released {
+            pending.insert(start, end);
+            return;
+        }
+        released = end;
+        while let Some(end) = pending.remove(&released) {
+            released = end;
+        }
+        self.released.store(released, Ordering::Release);
+    }
+}
+
+impl Writer {
+    pub fn try_write(&mut self, p: &[u8]) -> bool {
+        let s = &*self.0;
+        let write = s.write.load(Ordering::Relaxed);
+        let released = s.released.load(Ordering::Acquire);
+        let mut offset = s.offset(write);
+        let pad = match s.capacity - offset {
+            room if p.len() > room && offset > 0 => room,
+            _ => 0,
+        };
+        if write + (pad + p.len()) as u64 - released > s.capacity as u64 {
+            return false;
+        }
+        if pad > 0 {
+            s.padded.store(write, Ordering::Relaxed);
+            offset = 0;
+        }
+        unsafe { s.data.write(offset, p) };
+        s.write
+            .store(write + (pad + p.len()) as u64, Ordering::Release);
+        true
+    }
+}
+
+impl Reader {
+    // read claims everything that's been written but not yet claimed by any
+    // consumer, up to the end of the storage or the padding before it,
+    // whichever comes first. That's always a whole number of writes.
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        let s = &*self.0;
+        let mut claim = s.claim.load(Ordering::Relaxed);
+        loop {
+            let write = s.write.load(Ordering::Acquire);
+            if claim == write {
+                return None;
+            }
+            let offset = s.offset(claim);
+            let lap_end = claim + (s.capacity - offset) as u64;
+            let mut end = write.min(lap_end);
+            // If `write` is past the end of this lap, the Writer stored any
+            // padding for it before moving on, and the Acquire load above
+            // makes that visible.
+            let padded = s.padded.load(Ordering::Relaxed);
+            if write > lap_end && s.lap(padded) == s.lap(claim) && padded >= claim {
+                end = padded;
+            }
+            if end == claim {
+                // The padding itself: whoever claims it gives it back.
+                if s.claim
+                    .compare_exchange_weak(claim, lap_end, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    s.release(claim, lap_end);
+                    claim = lap_end;
+                } else {
+                    claim = s.claim.load(Ordering::Relaxed);
+                }
+                continue;
+            }
+            match s
+                .claim
+                .compare_exchange_weak(claim, end, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    // The Acquire load of `write` above made everything up to
+                    // `end` visible, and the Writer won't reuse it until
+                    // we've released it.
+                    let view = unsafe { s.data.slice(offset, (end - claim) as usize) };
+                    return Some(Lease {
+                        shared: s,
+                        start: claim,
+                        end,
+                        view,
+                    });
+                }
+                Err(actual) => claim = actual,
+            }
+        }
+    }
+}
+
+pub struct Lease<'a> {
+    shared: &'a Shared,
+    start: u64,
+    end: u64,
+    pub view: &'a [u8],
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.shared.release(self.start, self.end);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(reader.read().is_none());
+
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+
+        {
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, b"asdfpqrs")
+        }
+
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn claims_are_distinct() {
+        let (mut r1, mut writer) = create(10);
+        let mut r2 = r1.clone();
+
+        assert!(writer.try_write(b"asdf"));
+        let l1 = r1.read().unwrap();
+        assert_eq!(l1.view, b"asdf");
+        assert!(r2.read().is_none());
+
+        assert!(writer.try_write(b"pqrs"));
+        let l2 = r2.read().unwrap();
+        assert_eq!(l2.view, b"pqrs");
+        assert!(!writer.try_write(b"xyz"));
+        drop(l1);
+        drop(l2);
+        assert!(writer.try_write(b"xyz"));
+    }
+
+    #[test]
+    fn space_is_reclaimed_in_order() {
+        let (mut r1, mut writer) = create(10);
+        let mut r2 = r1.clone();
+
+        assert!(writer.try_write(b"aaaa"));
+        let l1 = r1.read().unwrap();
+        assert!(writer.try_write(b"bbbb"));
+        let l2 = r2.read().unwrap();
+
+        // Releasing the later claim doesn't free anything while the earlier
+        // one is still outstanding.
+        drop(l2);
+        assert!(!writer.try_write(b"ccc"));
+        drop(l1);
+        // Two bytes are left before the end of the storage, and this
+        // write skips them.
+        assert!(writer.try_write(b"cccccccc"));
+    }
+
+    #[test]
+    fn writes_wrap_around() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(writer.try_write(b"aaaaaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"bbbbb"));
+        // The write doesn't fit before the end of the storage, so it goes
+        // at the start, whole.
+        assert_eq!(reader.read().unwrap().view, b"bbbbb");
+        assert!(reader.read().is_none());
+        // The padding was given back along the way, so all 10 bytes are
+        // free again.
+        assert!(writer.try_write(b"ccccc"));
+        assert!(writer.try_write(b"ddddd"));
+        assert!(!writer.try_write(b"e"));
+    }
+
+    #[test]
+    fn padding_is_released_in_order() {
+        let (mut r1, mut writer) = create(10);
+        let mut r2 = r1.clone();
+
+        assert!(writer.try_write(b"aaaaa"));
+        drop(r1.read().unwrap());
+        assert!(writer.try_write(b"bb"));
+        let l1 = r1.read().unwrap();
+        // This skips the last 3 bytes of the storage.
+        assert!(writer.try_write(b"cccc"));
+        assert_eq!(r2.read().unwrap().view, b"cccc");
+        // The padding is claimed, but it can't be reused until `l1` is
+        // released.
+        assert!(!writer.try_write(b"dd"));
+        drop(l1);
+        assert!(writer.try_write(b"dd"));
+    }
+
+    #[test]
+    fn three_consumers() {
+        let (reader, mut writer) = create(64);
+        // Message `i` is `len(i)` copies of `len(i)`, so that any part of one
+        // that's claimed on its own stands out. Neighbors never have the same
+        // length.
+        let count = 20_000;
+        let len = |i: usize| (i % 17 + 1) as u8;
+        let total: u64 = (0..count).map(|i| u64::from(len(i))).sum();
+        let claimed = AtomicU64::new(0);
+        let messages = std::thread::scope(|scope| {
+            let consumers: Vec<_> = (0..3)
+                .map(|_| {
+                    let mut reader = reader.clone();
+                    let claimed = &claimed;
+                    scope.spawn(move || {
+                        let mut messages = 0;
+                        while claimed.load(Ordering::Relaxed) < total {
+                            let Some(l) = reader.read() else {
+                                std::thread::yield_now();
+                                continue;
+                            };
+                            // Every lease holds only whole writes.
+                            let mut rest = l.view;
+                            while let Some(&n) = rest.first() {
+                                let (msg, tail) = rest.split_at(usize::from(n).min(rest.len()));
+                                assert_eq!(msg, vec![n; usize::from(n)], "{:?}", l.view);
+                                rest = tail;
+                                messages += 1;
+                            }
+                            claimed.fetch_add(l.view.len() as u64, Ordering::Relaxed);
+                        }
+                        messages
+                    })
+                })
+                .collect();
+
+            for i in 0..count {
+                let msg = vec![len(i); usize::from(len(i))];
+                while !writer.try_write(&msg) {
+                    std::thread::yield_now();
+                }
+            }
+            consumers
+                .into_iter()
+                .map(|c| c.join().unwrap())
+                .sum::<usize>()
+        });
+
+        // Every message was claimed exactly once.
+        assert_eq!(messages, count);
+        assert_eq!(claimed.load(Ordering::Relaxed), total);
+    }
+}
@@ -0,0 +1,241 @@
+use crossbeam::utils::CachePadded;
+
+use crate::{
+    error::CreateError,
+    storage::Storage,
+    sync::{Arc, AtomicUsize, Ordering},
+};
+
+// Shared is the lock-free counterpart of buffer.rs's Buffer. It uses the same
+// bip-buffer layout as Tracker, but each offset has exactly one writer:
+//   - `write` and `inverted_at` are only ever stored by the Writer
+//   - `read` is only ever stored by the Reader
+// Each side publishes its progress with a Release store and observes the
+// other side's progress with an Acquire load, which is what makes the bytes
+// behind those offsets safe to touch.
+//
+// The producer's and consumer's offsets live on separate cache lines, so that
+// each side's stores don't keep invalidating the line the other side is
+// writing to. `capacity` and `data` are never modified, so they can share a
+// line that both sides keep cached.
+struct Shared {
+    producer: CachePadded<ProducerState>,
+    consumer: CachePadded<ConsumerState>,
+    capacity: usize,
+    data: Storage,
+}
+struct ProducerState {
+    // write is where the next write will start.
+    write: AtomicUsize,
+    // inverted_at is where the data at the end of the buffer stops. It's
+    // only meaningful while the buffer is inverted, i.e. while
+    // `write < read`. Unlike Tracker we can't use 0 as a sentinel, since the
+    // two sides can't update their offsets in one step.
+    inverted_at: AtomicUsize,
+}
+struct ConsumerState {
+    // read is where the next read will start.
+    read: AtomicUsize,
+}
+
+// Reader and Writer mirror buffer.rs, except that the Writer can't be cloned:
+// the algorithm relies on there being exactly one producer.
+pub struct Reader(Arc<Shared>);
+pub struct Writer(Arc<Shared>);
+
+pub fn try_create(capacity: usize) -> Result<(Reader, Writer), CreateError> {
+    let data = Storage::alloc(capacity, 1)?;
+    let shared = Arc::new(Shared {
+        producer: CachePadded::new(ProducerState {
+            write: AtomicUsize::new(0),
+            inverted_at: AtomicUsize::new(0),
+        }),
+        consumer: CachePadded::new(ConsumerState {
+            read: AtomicUsize::new(0),
+        }),
+        capacity,
+        data,
+    });
+    Ok((Reader(shared.clone()), Writer(shared)))
+}
+
+// create is like try_create, but panics if the capacity is invalid.
+pub fn create(capacity: usize) -> (Reader, Writer) {
+    match try_create(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+impl Writer {
+    pub fn try_write(&mut self, p: &[u8]) -> bool {
+        let s = &*self.0;
+        let sz = p.len();
+        let write = s.producer.write.load(Ordering::Relaxed);
+        let read = s.consumer.read.load(Ordering::Acquire);
+
+        let start = if write < read {
+            // Already inverted: we can write up to the unread data, but must
+            // not catch up to it entirely, since `write == read` means empty.
+            if write + sz >= read {
+                return false;
+            }
+            write
+        } else if write + sz <= s.capacity {
+            write
+        } else if sz < read {
+            // Invert. The reader won't look at inverted_at until it sees
+            // `write < read`, which our Release store of `write` publishes.
+            s.producer.inverted_at.store(write, Ordering::Relaxed);
+            0
+        } else {
+            return false;
+        };
+
+        unsafe { s.data.write(start, p) };
+        s.producer.write.store(start + sz, Ordering::Release);
+        true
+    }
+}
+
+impl Reader {
+    pub fn read(&mut self) -> Option<Lease<'_>> {
+        let s = &*self.0;
+        let write = s.producer.write.load(Ordering::Acquire);
+        let mut read = s.consumer.read.load(Ordering::Relaxed);
+        if write < read {
+            let inverted_at = s.producer.inverted_at.load(Ordering::Relaxed);
+            if read == inverted_at {
+                // Nothing left at the end of the buffer; follow the writer
+                // back to the start.
+                read = 0;
+                s.consumer.read.store(0, Ordering::Release);
+            }
+        }
+        let end = if write < read {
+            s.producer.inverted_at.load(Ordering::Relaxed)
+        } else {
+            write
+        };
+        if read == end {
+            return None;
+        }
+        let view = unsafe { s.data.slice(read, end - read) };
+        Some(Lease {
+            shared: s,
+            end,
+            view,
+        })
+    }
+}
+
+pub struct Lease<'a> {
+    shared: &'a Shared,
+    end: usize,
+    pub view: &'a [u8],
+}
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.shared.consumer.read.store(self.end, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoke() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(reader.read().is_none());
+
+        assert!(writer.try_write(b"asdf"));
+        assert!(writer.try_write(b"pqrs"));
+
+        {
+            let l = reader.read().unwrap();
+            assert_eq!(l.view, b"asdfpqrs")
+        }
+
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn write_during_read_lease() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(writer.try_write(b"asdf"));
+
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"asdf");
+        assert!(writer.try_write(b"pqrs"));
+        assert_eq!(l.view, b"asdf");
+
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"pqrs");
+    }
+
+    #[test]
+    fn write_wraparound() {
+        let (mut reader, mut writer) = create(10);
+
+        assert!(writer.try_write(b"aaaaa"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"aaaaa");
+        assert!(writer.try_write(b"bbbb"));
+        drop(l);
+        assert!(writer.try_write(b"cccc"));
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"bbbb");
+        drop(l);
+        let l = reader.read().unwrap();
+        assert_eq!(l.view, b"cccc");
+        drop(l);
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn inverted_buffer_keeps_one_byte_free() {
+        let (mut reader, mut writer) = create(10);
+        assert!(writer.try_write(b"aaaaa"));
+        drop(reader.read().unwrap());
+        assert!(writer.try_write(b"bbbbb"));
+        // Only 5 bytes are free, at the start of the buffer, and filling all
+        // of them would make the buffer look empty.
+        assert!(!writer.try_write(b"ccccc"));
+        assert!(writer.try_write(b"cccc"));
+        assert_eq!(reader.read().unwrap().view, b"bbbbb");
+        assert_eq!(reader.read().unwrap().view, b"cccc");
+        assert!(reader.read().is_none());
+    }
+
+    #[test]
+    fn two_threads() {
+        let (mut reader, mut writer) = create(64);
+        let total: usize = 100_000;
+        let producer = std::thread::spawn(move || {
+            let mut i = 0;
+            while i < total {
+                let msg = [(i % 251) as u8; 3];
+                if writer.try_write(&msg) {
+                    i += 1;
+                } else {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut seen = Vec::new();
+        while seen.len() < 3 * total {
+            match reader.read() {
+                Some(l) => seen.extend_from_slice(l.view),
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        for (i, chunk) in seen.chunks(3).enumerate() {
+            assert_eq!(chunk, [(i % 251) as u8; 3]);
+        }
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    alloc::Layout,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::error::{CreateError, validate_capacity};
+
+// Storage is the raw data region behind a buffer. It hands out raw pointers
+// rather than slices so that the reader and writer can hold disjoint views
+// into it at the same time; the tracker decides which views are allowed.
+//
+// Freshly allocated storage is left uninitialized. Writes always land either
+// at offset 0 or directly after the previous write, so the written bytes form
+// a prefix of the storage; `initialized` tracks the end of that prefix. Reads
+// only ever cover committed bytes, which are inside the prefix, so no
+// uninitialized byte is ever exposed as a `&[u8]`.
+pub(crate) struct Storage {
+    ptr: NonNull<u8>,
+    len: usize,
+    initialized: AtomicUsize,
+    kind: Kind,
+    // locked is set once the storage has been mlock'd, and must be unlocked
+    // before it's freed.
+    locked: bool,
+    // scrub is set if the storage must be zeroed before it's freed.
+    scrub: bool,
+}
+
+enum Kind {
+    // Memory owned by a Box<[u8]>.
+    Boxed,
+    // Memory from std::alloc, possibly with a non-default alignment.
+    Alloc(Layout),
+    // Memory that outlives the storage and is never freed by it.
+    Static,
+    // A shared mapping of a file, unmapped on drop.
+    #[cfg(all(feature = "mmap", unix))]
+    Mmap,
+    // Two adjacent mappings of the same memory, so that offsets in
+    // `len..2 * len` alias offsets in `0..len`.
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    Mirrored,
+}
+
+// Storage is just an owned allocation; synchronizing access to it is the
+// job of whoever holds it.
+unsafe impl Send for Storage {}
+unsafe impl Sync for Storage {}
+
+impl Storage {
+    pub fn boxed(b: Box<[u8]>) -> Self {
+        let len = b.len();
+        let ptr = NonNull::new(Box::into_raw(b).cast::<u8>()).expect("Box is non-null");
+        Self {
+            ptr,
+            len,
+            initialized: AtomicUsize::new(len),
+            locked: false,
+            scrub: false,
+            kind: Kind::Boxed,
+        }
+    }
+
+    // borrowed uses memory that lives forever (a linker-placed static region,
+    // or memory handed out by a driver) without ever freeing it.
+    pub fn borrowed(s: &'static mut [u8]) -> Self {
+        Self {
+            ptr: NonNull::from(&mut *s).cast(),
+            len: s.len(),
+            initialized: AtomicUsize::new(s.len()),
+            locked: false,
+            scrub: false,
+            kind: Kind::Static,
+        }
+    }
+
+    // alloc allocates `capacity` uninitialized bytes starting at a multiple of
+    // `align`.
+    pub fn alloc(capacity: usize, align: usize) -> Result<Self, CreateError> {
+        validate_capacity(capacity)?;
+        if !align.is_power_of_two() {
+            return Err(CreateError::InvalidAlignment(align));
+        }
+        let layout = Layout::from_size_align(capacity, align)
+            .map_err(|_| CreateError::CapacityTooLarge(capacity))?;
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            return Err(CreateError::OutOfMemory(capacity));
+        };
+        Ok(Self {
+            ptr,
+            len: capacity,
+            initialized: AtomicUsize::new(0),
+            locked: false,
+            scrub: false,
+            kind: Kind::Alloc(layout),
+        })
+    }
+
+    // mmap maps `capacity` bytes of the file at `path` (creating or extending
+    // it as needed) and uses the mapping as storage. The OS is free to page the
+    // data out, but no particular durability is promised.
+    #[cfg(feature = "mmap")]
+    pub fn mmap(path: &std::path::Path, capacity: usize) -> std::io::Result<Self> {
+        validate_capacity(capacity)?;
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            if file.metadata()?.len() < capacity as u64 {
+                file.set_len(capacity as u64)?;
+            }
+            let ptr = unsafe {
+                sys::mmap(
+                    std::ptr::null_mut(),
+                    capacity,
+                    sys::PROT_READ | sys::PROT_WRITE,
+                    sys::MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == sys::MAP_FAILED {
+                return Err(std::io::Error::last_os_error());
+            }
+            // The mapping stays valid after the file is closed.
+            Ok(Self {
+                ptr: NonNull::new(ptr.cast()).expect("mmap succeeded"),
+                len: capacity,
+                initialized: AtomicUsize::new(capacity),
+                locked: false,
+                scrub: false,
+                kind: Kind::Mmap,
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "memory-mapped buffers are only supported on unix",
+            ))
+        }
+    }
+
+    // mirrored allocates `capacity` bytes (rounded up to a whole number of
+    // pages) and maps them twice, back to back. Any range of up to `len()`
+    // bytes starting below `len()` is then contiguous in memory, even if it
+    // runs off the end of the buffer.
+    #[cfg(feature = "mmap")]
+    pub fn mirrored(capacity: usize) -> std::io::Result<Self> {
+        validate_capacity(capacity)?;
+        #[cfg(target_os = "linux")]
+        {
+            let page = unsafe { sys::sysconf(sys::_SC_PAGESIZE) } as usize;
+            let capacity = capacity
+                .checked_next_multiple_of(page)
+                .filter(|c| c.checked_mul(2).is_some_and(|c| c <= isize::MAX as usize))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        CreateError::CapacityTooLarge(capacity),
+                    )
+                })?;
+            unsafe {
+                let fd = sys::memfd_create(c"bbuf".as_ptr(), 0);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let result = Self::map_mirrored(fd, capacity);
+                sys::close(fd);
+                result
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "mirrored buffers are only supported on linux",
+            ))
+        }
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    unsafe fn map_mirrored(fd: std::ffi::c_int, capacity: usize) -> std::io::Result<Self> {
+        use sys::*;
+
+        unsafe {
+            if ftruncate(fd, capacity as isize) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // Reserve enough address space for both copies, then map the
+            // memfd over each half.
+            let base = mmap(
+                std::ptr::null_mut(),
+                2 * capacity,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == MAP_FAILED {
+                return Err(std::io::Error::last_os_error());
+            }
+            for half in [base, base.byte_add(capacity)] {
+                let ptr = mmap(
+                    half,
+                    capacity,
+                    PROT_READ | PROT_WRITE,
+                    MAP_SHARED | MAP_FIXED,
+                    fd,
+                    0,
+                );
+                if ptr == MAP_FAILED {
+                    let err = std::io::Error::last_os_error();
+                    munmap(base, 2 * capacity);
+                    return Err(err);
+                }
+            }
+            Ok(Self {
+                ptr: NonNull::new(base.cast()).expect("mmap succeeded"),
+                len: capacity,
+                // memfds start out zeroed.
+                initialized: AtomicUsize::new(2 * capacity),
+                locked: false,
+                scrub: false,
+                kind: Kind::Mirrored,
+            })
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // prefault touches every page of the storage up front, so that the first
+    // write into each page doesn't take a page fault later. It does so by
+    // zeroing everything, which also leaves the whole storage initialized.
+    pub fn prefault(&mut self) {
+        unsafe { std::ptr::write_bytes(self.as_ptr(), 0, self.len) };
+        self.initialized.store(self.span(), Ordering::Relaxed);
+    }
+
+    // mlock pins the storage in RAM so it's never paged out. It's unlocked
+    // again when the storage is dropped.
+    pub fn mlock(&mut self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            if !self.locked {
+                if unsafe { sys::mlock(self.as_ptr().cast(), self.span()) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                self.locked = true;
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "mlock is only supported on unix",
+            ))
+        }
+    }
+
+    fn munlock(&mut self) {
+        #[cfg(unix)]
+        if std::mem::take(&mut self.locked) {
+            unsafe { sys::munlock(self.as_ptr().cast(), self.span()) };
+        }
+    }
+
+    // scrub_on_drop makes dropping the storage zero everything that was
+    // ever written (see `scrub`) before it's freed.
+    pub fn scrub_on_drop(&mut self) {
+        self.scrub = true;
+    }
+
+    // resized allocates `capacity` bytes of storage to replace this one: with
+    // the same alignment, and mlock'd and scrubbed on drop if this is. Only
+    // storage on the heap can be resized; anything else (a borrowed region,
+    // or a mapping) gives None.
+    pub fn resized(&self, capacity: usize) -> Option<std::io::Result<Self>> {
+        let align = match self.kind {
+            Kind::Boxed => 1,
+            Kind::Alloc(layout) => layout.align(),
+            _ => return None,
+        };
+        let new = || {
+            let mut s = Self::alloc(capacity, align)?;
+            if self.locked {
+                s.mlock()?;
+            }
+            s.scrub = self.scrub;
+            Ok(s)
+        };
+        Some(new())
+    }
+
+    // is_mirrored reports whether offsets past `len()` alias the start of the
+    // storage.
+    pub fn is_mirrored(&self) -> bool {
+        #[cfg(all(feature = "mmap", target_os = "linux"))]
+        if let Kind::Mirrored = self.kind {
+            return true;
+        }
+        false
+    }
+
+    // span is how many bytes are addressable, including any mirror.
+    fn span(&self) -> usize {
+        if self.is_mirrored() {
+            2 * self.len
+        } else {
+            self.len
+        }
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    // write copies `p` into the storage at `offset`.
+    //
+    // Safety: the caller must have exclusive access to
+    // `offset..offset + p.len()` (i.e. hold a write lease covering it), and
+    // calls to `write` must not race with each other unless the storage is
+    // already fully initialized (see `prefault`).
+    pub unsafe fn write(&self, offset: usize, p: &[u8]) {
+        debug_assert!(offset + p.len() <= self.span());
+        let initialized = self.initialized.load(Ordering::Relaxed);
+        unsafe {
+            if offset > initialized {
+                // Never expected given how the tracker hands out space, but
+                // cheap insurance that the initialized region stays a prefix.
+                std::ptr::write_bytes(self.as_ptr().add(initialized), 0, offset - initialized);
+            }
+            std::ptr::copy_nonoverlapping(p.as_ptr(), self.as_ptr().add(offset), p.len());
+        }
+        self.initialized
+            .store(initialized.max(offset + p.len()), Ordering::Relaxed);
+    }
+
+    // zero zeroes `len` bytes starting at `offset`, which must already be
+    // initialized.
+    //
+    // Safety: same as `write`.
+    pub unsafe fn zero(&self, offset: usize, len: usize) {
+        debug_assert!(offset + len <= self.initialized.load(Ordering::Relaxed));
+        unsafe { std::ptr::write_bytes(self.as_ptr().add(offset), 0, len) };
+    }
+
+    // scrub is like zero, but uses volatile writes, so that the compiler
+    // can't skip zeroing bytes that nothing reads again, e.g. because the
+    // storage is about to be freed.
+    //
+    // Safety: same as `write`.
+    pub unsafe fn scrub(&self, offset: usize, len: usize) {
+        debug_assert!(offset + len <= self.span());
+        for i in offset..offset + len {
+            unsafe { self.as_ptr().add(i).write_volatile(0) };
+        }
+        std::sync::atomic::compiler_fence(Ordering::SeqCst);
+    }
+
+    // atomic_u64 views the 8 bytes at `offset` as an AtomicU64.
+    //
+    // Safety: `offset` must be 8-aligned relative to an 8-aligned allocation,
+    // the bytes must be initialized, and for as long as the reference lives
+    // they must only be accessed through it.
+    #[cfg(target_has_atomic = "64")]
+    pub unsafe fn atomic_u64(&self, offset: usize) -> &std::sync::atomic::AtomicU64 {
+        debug_assert!(offset + 8 <= self.initialized.load(Ordering::Relaxed));
+        unsafe { std::sync::atomic::AtomicU64::from_ptr(self.as_ptr().add(offset).cast()) }
+    }
+
+    // slice views `len` bytes starting at `offset`.
+    //
+    // Safety: the range must have been written, and nobody may write to it
+    // for the lifetime of the returned slice (i.e. the caller holds a read
+    // lease covering it).
+    pub unsafe fn slice(&self, offset: usize, len: usize) -> &[u8] {
+        debug_assert!(offset + len <= self.initialized.load(Ordering::Relaxed));
+        unsafe { std::slice::from_raw_parts(self.as_ptr().add(offset), len) }
+    }
+
+    // slice_mut views `len` bytes starting at `offset`, mutably.
+    //
+    // Safety: the range must have been written, and the caller must have
+    // exclusive access to it for the lifetime of the returned slice.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn slice_mut(&self, offset: usize, len: usize) -> &mut [u8] {
+        debug_assert!(offset + len <= self.initialized.load(Ordering::Relaxed));
+        unsafe { std::slice::from_raw_parts_mut(self.as_ptr().add(offset), len) }
+    }
+
+    // into_boxed returns the storage as a Box<[u8]>, zeroing any bytes that
+    // were never written. Storage with a non-default alignment is copied into
+    // a fresh Box.
+    pub fn into_boxed(mut self) -> Box<[u8]> {
+        self.munlock();
+        let initialized = self.initialized.load(Ordering::Relaxed);
+        unsafe {
+            std::ptr::write_bytes(self.as_ptr().add(initialized), 0, self.len - initialized);
+        }
+        let slice = std::ptr::slice_from_raw_parts_mut(self.as_ptr(), self.len);
+        match self.kind {
+            // A Box<[u8]> is exactly an allocation with u8's layout, so those
+            // can be handed over without copying.
+            Kind::Boxed => {}
+            Kind::Alloc(layout) if layout.align() == 1 => {}
+            _ => return unsafe { &*slice }.into(),
+        }
+        std::mem::forget(self);
+        unsafe { Box::from_raw(slice) }
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if self.scrub {
+            // Any mirror aliases what's already being scrubbed.
+            let initialized = self.initialized.load(Ordering::Relaxed);
+            unsafe { self.scrub(0, initialized.min(self.len)) };
+        }
+        self.munlock();
+        let slice = std::ptr::slice_from_raw_parts_mut(self.as_ptr(), self.len);
+        match self.kind {
+            Kind::Boxed => drop(unsafe { Box::from_raw(slice) }),
+            Kind::Alloc(layout) => unsafe { std::alloc::dealloc(self.as_ptr(), layout) },
+            Kind::Static => {}
+            #[cfg(all(feature = "mmap", unix))]
+            Kind::Mmap => unsafe {
+                sys::munmap(self.as_ptr().cast(), self.len);
+            },
+            #[cfg(all(feature = "mmap", target_os = "linux"))]
+            Kind::Mirrored => unsafe {
+                sys::munmap(self.as_ptr().cast(), 2 * self.len);
+            },
+        }
+    }
+}
+
+// Just enough of libc to lock and map memory. These constants agree across
+// Linux and the BSDs (including macOS), except where marked.
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    unsafe extern "C" {
+        pub fn mlock(addr: *const c_void, len: usize) -> c_int;
+        pub fn munlock(addr: *const c_void, len: usize) -> c_int;
+    }
+
+    #[cfg(feature = "mmap")]
+    pub use mmap::*;
+    #[cfg(feature = "mmap")]
+    mod mmap {
+        use super::*;
+
+        pub const PROT_READ: c_int = 1;
+        pub const PROT_WRITE: c_int = 2;
+        pub const MAP_SHARED: c_int = 1;
+        pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+        unsafe extern "C" {
+            pub fn mmap(
+                addr: *mut c_void,
+                len: usize,
+                prot: c_int,
+                flags: c_int,
+                fd: c_int,
+                offset: isize,
+            ) -> *mut c_void;
+            pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        }
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    pub use linux::*;
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    mod linux {
+        use super::*;
+        use std::ffi::{c_char, c_long, c_uint};
+
+        pub const PROT_NONE: c_int = 0;
+        pub const MAP_PRIVATE: c_int = 2;
+        pub const MAP_FIXED: c_int = 0x10;
+        pub const MAP_ANONYMOUS: c_int = 0x20;
+        pub const _SC_PAGESIZE: c_int = 30;
+
+        unsafe extern "C" {
+            pub fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+            pub fn ftruncate(fd: c_int, length: isize) -> c_int;
+            pub fn close(fd: c_int) -> c_int;
+            pub fn sysconf(name: c_int) -> c_long;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aligned_allocations() {
+        for align in [1, 64, 4096] {
+            let s = Storage::alloc(100, align).unwrap();
+            assert_eq!(s.as_ptr() as usize % align, 0);
+            assert_eq!(s.len(), 100);
+            assert_eq!(&*s.into_boxed(), &[0; 100]);
+        }
+    }
+
+    #[test]
+    fn alloc_rejects_bad_alignment() {
+        assert_eq!(
+            Storage::alloc(100, 3).err(),
+            Some(CreateError::InvalidAlignment(3))
+        );
+        assert_eq!(
+            Storage::alloc(100, 0).err(),
+            Some(CreateError::InvalidAlignment(0))
+        );
+        assert_eq!(Storage::alloc(0, 64).err(), Some(CreateError::ZeroCapacity));
+    }
+
+    #[test]
+    fn boxed_round_trip() {
+        let b: Box<[u8]> = Box::new(*b"asdf");
+        let ptr = b.as_ptr();
+        let s = Storage::boxed(b);
+        assert_eq!(s.len(), 4);
+        let b = s.into_boxed();
+        assert_eq!(b.as_ptr(), ptr);
+        assert_eq!(&*b, b"asdf");
+    }
+
+    #[test]
+    fn borrowed_is_not_freed() {
+        let region: &'static mut [u8] = Box::leak(Box::new(*b"asdf"));
+        let ptr = region.as_ptr();
+        let s = Storage::borrowed(region);
+        unsafe {
+            s.write(0, b"xy");
+            assert_eq!(s.slice(0, 4), b"xydf");
+        }
+        let copy = s.into_boxed();
+        assert_ne!(copy.as_ptr(), ptr);
+        // The original region is still alive (and was written through).
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 4) }, b"xydf");
+    }
+
+    #[test]
+    fn scrubbed_on_drop() {
+        let region: &'static mut [u8] = Box::leak(Box::new(*b"asdf"));
+        let ptr = region.as_ptr();
+        let mut s = Storage::borrowed(region);
+        s.scrub_on_drop();
+        unsafe { s.scrub(1, 2) };
+        assert_eq!(unsafe { s.slice(0, 4) }, b"a\0\0f");
+        drop(s);
+        assert_eq!(unsafe { std::slice::from_raw_parts(ptr, 4) }, [0; 4]);
+    }
+
+    #[test]
+    fn prefault_initializes_everything() {
+        let mut s = Storage::alloc(3 * 4096 + 10, 1).unwrap();
+        s.prefault();
+        unsafe {
+            assert!(s.slice(0, s.len()).iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn mlock_is_attempted() {
+        let mut s = Storage::alloc(4096, 4096).unwrap();
+        match s.mlock() {
+            Ok(()) => assert!(s.locked),
+            // Unprivileged processes may be over their RLIMIT_MEMLOCK, but
+            // either way the syscall should have been made.
+            Err(err) => assert!(err.raw_os_error().is_some() || cfg!(not(unix))),
+        }
+        unsafe {
+            s.write(0, b"asdf");
+            assert_eq!(s.slice(0, 4), b"asdf");
+        }
+    }
+
+    #[test]
+    fn uninit_writes_and_reads() {
+        let s = Storage::alloc(10, 1).unwrap();
+        unsafe {
+            s.write(0, b"asdf");
+            s.write(4, b"pq");
+            assert_eq!(s.slice(0, 6), b"asdfpq");
+            assert_eq!(s.slice(2, 3), b"dfp");
+            s.write(0, b"xy");
+            assert_eq!(s.slice(0, 6), b"xydfpq");
+        }
+        assert_eq!(&*s.into_boxed(), b"xydfpq\0\0\0\0");
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn mmap_extends_file() {
+        let path = std::env::temp_dir().join(format!("bbuf-storage-{}", std::process::id()));
+        {
+            let s = Storage::mmap(&path, 8192).unwrap();
+            unsafe {
+                s.write(4096, b"asdf");
+                assert_eq!(s.slice(4096, 4), b"asdf");
+            }
+        }
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents.len(), 8192);
+        assert_eq!(&contents[4096..4100], b"asdf");
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn mirrored_aliases_start() {
+        let s = Storage::mirrored(100).unwrap();
+        assert!(s.is_mirrored());
+        assert_eq!(s.len() % 4096, 0);
+        let len = s.len();
+        unsafe {
+            s.write(len - 2, b"asdf");
+            assert_eq!(s.slice(len - 2, 4), b"asdf");
+            assert_eq!(s.slice(0, 2), b"df");
+        }
+    }
+
+    #[test]
+    fn uninit_gap_is_zeroed() {
+        let s = Storage::alloc(10, 1).unwrap();
+        unsafe {
+            s.write(6, b"zz");
+            assert_eq!(s.slice(0, 8), b"\0\0\0\0\0\0zz");
+        }
+    }
+}
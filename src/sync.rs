@@ -0,0 +1,30 @@
+// sync is where the buffers that share state between threads get their
+// synchronization primitives from, all in one place.
+
+#[cfg(target_has_atomic = "64")]
+pub(crate) use std::sync::atomic::AtomicU64;
+pub(crate) use std::{
+    hint::spin_loop,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+// lock locks `m`, even if another thread panicked while holding it. The
+// buffers never leave the state behind a lock half-updated (nothing they do
+// while holding one can panic, short of a bug), so a panic elsewhere, e.g.
+// in the middle of handling a lease, is no reason to take every other user
+// of the buffer down with it.
+pub(crate) fn lock<T: ?Sized>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+// try_lock is like lock, but gives up instead of waiting.
+pub(crate) fn try_lock<T: ?Sized>(m: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match m.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(std::sync::TryLockError::Poisoned(err)) => Some(err.into_inner()),
+        Err(std::sync::TryLockError::WouldBlock) => None,
+    }
+}
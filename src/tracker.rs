@@ -1,4 +1,101 @@
-use std::ops::Range;
+use core::ops::Range;
+
+// pow2 is an alternative tracker for power-of-two capacities, based on
+// monotonic counters instead of an inversion marker.
+#[cfg(feature = "std")]
+mod pow2;
+#[cfg(feature = "std")]
+pub(crate) use pow2::Pow2Tracker;
+
+// AnyTracker is whichever tracker a buffer was created with.
+#[cfg(feature = "std")]
+pub(crate) enum AnyTracker {
+    Bip(Tracker),
+    Pow2(Pow2Tracker),
+}
+#[cfg(feature = "std")]
+impl AnyTracker {
+    pub fn capacity(&self) -> usize {
+        match self {
+            AnyTracker::Bip(t) => t.capacity(),
+            AnyTracker::Pow2(t) => t.capacity(),
+        }
+    }
+    pub fn clear(&mut self) {
+        match self {
+            AnyTracker::Bip(t) => t.clear(),
+            AnyTracker::Pow2(t) => t.clear(),
+        }
+    }
+    pub fn is_idle(&self) -> bool {
+        match self {
+            AnyTracker::Bip(t) => t.is_idle(),
+            AnyTracker::Pow2(t) => t.is_idle(),
+        }
+    }
+    pub fn is_inverted(&self) -> bool {
+        match self {
+            AnyTracker::Bip(t) => t.is_inverted(),
+            AnyTracker::Pow2(t) => t.is_inverted(),
+        }
+    }
+    pub fn shortfall(&self, sz: usize) -> Option<usize> {
+        match self {
+            AnyTracker::Bip(t) => t.shortfall(sz),
+            AnyTracker::Pow2(t) => t.shortfall(sz),
+        }
+    }
+    pub fn available(&self) -> usize {
+        match self {
+            AnyTracker::Bip(t) => t.available(),
+            AnyTracker::Pow2(t) => t.available(),
+        }
+    }
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn contiguous(&self) -> usize {
+        match self {
+            AnyTracker::Bip(t) => t.contiguous(),
+            AnyTracker::Pow2(t) => t.contiguous(),
+        }
+    }
+    pub fn write(&mut self, sz: usize) -> Option<WriteLease> {
+        match self {
+            AnyTracker::Bip(t) => t.write(sz),
+            AnyTracker::Pow2(t) => t.write(sz),
+        }
+    }
+    pub fn read(&mut self) -> Option<ReadLease> {
+        match self {
+            AnyTracker::Bip(t) => t.read(),
+            AnyTracker::Pow2(t) => t.read(),
+        }
+    }
+    pub fn commit(&mut self, w: WriteLease) {
+        match self {
+            AnyTracker::Bip(t) => t.commit(w),
+            AnyTracker::Pow2(t) => t.commit(w),
+        }
+    }
+    pub fn release(&mut self, r: ReadLease) {
+        match self {
+            AnyTracker::Bip(t) => t.release(r),
+            AnyTracker::Pow2(t) => t.release(r),
+        }
+    }
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn truncate(&mut self, w: WriteLease, len: usize) {
+        match self {
+            AnyTracker::Bip(t) => t.truncate(w, len),
+            AnyTracker::Pow2(t) => t.truncate(w, len),
+        }
+    }
+    pub fn unread(&self) -> [Range<usize>; 2] {
+        match self {
+            AnyTracker::Bip(t) => t.unread(),
+            AnyTracker::Pow2(t) => t.unread(),
+        }
+    }
+}
 
 pub(crate) struct Tracker {
     capacity: usize,
@@ -10,6 +107,10 @@ pub(crate) struct Tracker {
     // inverted it indicates where the last write ended (i.e., where the next
     // read should end).
     inverted_at: usize,
+    // mirrored means offsets in capacity..2*capacity alias 0..capacity, so
+    // leases may run off the end of the buffer instead of inverting. When a
+    // mirrored tracker is inverted, inverted_at is always `capacity`.
+    mirrored: bool,
 }
 impl Tracker {
     pub fn new(capacity: usize) -> Self {
@@ -18,8 +119,111 @@ impl Tracker {
             write_offset: 0,
             read_offset: 0,
             inverted_at: 0,
+            mirrored: false,
         }
     }
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn new_mirrored(capacity: usize) -> Self {
+        Self {
+            mirrored: true,
+            ..Self::new(capacity)
+        }
+    }
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // clear forgets all data, returning the tracker to its initial state.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn clear(&mut self) {
+        self.write_offset = 0;
+        self.read_offset = 0;
+        self.inverted_at = 0;
+    }
+
+    // is_idle means the buffer is empty and the next write will start at
+    // offset 0.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn is_idle(&self) -> bool {
+        self.write_offset == 0 && self.read_offset == 0 && self.inverted_at == 0
+    }
+
+    // is_inverted means the unread data wraps around the end of the buffer.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn is_inverted(&self) -> bool {
+        self.inverted_at > 0
+    }
+
+    // shortfall is how many of the oldest unread bytes would have to be
+    // released for a write of `sz` bytes to fit, or None if it can't fit even
+    // in an empty buffer.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn shortfall(&self, sz: usize) -> Option<usize> {
+        if sz > self.capacity {
+            return None;
+        }
+        let (r, w) = (self.read_offset, self.write_offset);
+        if self.mirrored {
+            // The unread data is always contiguous, so this is just the
+            // total amount of space.
+            let used = if self.inverted_at > 0 {
+                self.capacity + w - r
+            } else {
+                w - r
+            };
+            return Some((used + sz).saturating_sub(self.capacity));
+        }
+        if self.inverted_at == 0 {
+            return Some(self.shortfall_uninverted(r, w, sz));
+        }
+        if w + sz <= r {
+            Some(0)
+        } else if w + sz < self.inverted_at {
+            Some(w + sz - r)
+        } else {
+            // Releasing everything up to inverted_at un-inverts the buffer,
+            // which leaves the data at `0..w`.
+            Some(self.inverted_at - r + self.shortfall_uninverted(0, w, sz))
+        }
+    }
+
+    // available is the size of the largest write that would fit right now.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn available(&self) -> usize {
+        let (r, w) = (self.read_offset, self.write_offset);
+        match (self.inverted_at > 0, self.mirrored) {
+            (true, _) => r - w,
+            (false, true) => self.capacity - w + r,
+            // Either at the end of the buffer, or by inverting.
+            (false, false) => (self.capacity - w).max(r),
+        }
+    }
+
+    // contiguous is the size of the largest write that would continue right
+    // where the last one ended, without inverting.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn contiguous(&self) -> usize {
+        let (r, w) = (self.read_offset, self.write_offset);
+        match (self.inverted_at > 0, self.mirrored) {
+            (true, _) => r - w,
+            (false, true) => self.capacity - w + r,
+            (false, false) => self.capacity - w,
+        }
+    }
+
+    fn shortfall_uninverted(&self, r: usize, w: usize, sz: usize) -> usize {
+        if w + sz <= self.capacity {
+            0
+        } else if sz <= w {
+            // Make enough room at the start of the buffer to invert.
+            sz.saturating_sub(r)
+        } else {
+            // Only an empty buffer, which starts over at 0, has room.
+            w - r
+        }
+    }
+
     pub fn write(&mut self, sz: usize) -> Option<WriteLease> {
         // inverted means that there is still data for the reader to read towards
         // the end of the buffer, but free space towards the beginning of the buffer
@@ -38,6 +242,15 @@ impl Tracker {
         let start = if self.write_offset + sz <= write_cap {
             // Simple case: there's enough space contiguous with our current cursor.
             self.write_offset
+        } else if self.mirrored
+            && !already_inverted
+            && self.write_offset + sz <= self.capacity + self.read_offset
+        {
+            // Mirrored case: the write runs off the end of the buffer and into
+            // the mirror of its start. The reader will find the end of the
+            // data at `capacity + write_offset`.
+            self.inverted_at = self.capacity;
+            self.write_offset
         } else if !already_inverted && sz <= self.read_offset {
             // Complex case: we don't have space at our current cursor, but if
             // we invert then we'll have enough space at the start of the
@@ -53,12 +266,14 @@ impl Tracker {
             return None;
         };
 
-        return Some(WriteLease::new(start..start + sz));
+        Some(WriteLease::new(start..start + sz))
     }
 
     pub fn read(&mut self) -> Option<ReadLease> {
         let start = self.read_offset;
-        let end = if self.inverted_at > 0 {
+        let end = if self.inverted_at > 0 && self.mirrored {
+            self.capacity + self.write_offset
+        } else if self.inverted_at > 0 {
             self.inverted_at
         } else {
             self.write_offset
@@ -69,8 +284,30 @@ impl Tracker {
         Some(ReadLease::new(start..end))
     }
 
+    // unread is where all of the unread data is, oldest first, without
+    // reading it. The second range is empty unless the data wraps around the
+    // end of the buffer, which in a mirrored tracker it does physically even
+    // though `read` never shows it.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn unread(&self) -> [Range<usize>; 2] {
+        let (r, w) = (self.read_offset, self.write_offset);
+        if self.mirrored && self.inverted_at > 0 {
+            [r..self.capacity, 0..w]
+        } else if self.inverted_at > 0 {
+            [r..self.inverted_at, 0..w]
+        } else {
+            [r..w, 0..0]
+        }
+    }
+
     pub fn commit(&mut self, w: WriteLease) {
-        self.write_offset = w.start + w.len;
+        let end = w.start + w.len;
+        // Only mirrored writes can end past the end of the buffer.
+        self.write_offset = if end > self.capacity {
+            end - self.capacity
+        } else {
+            end
+        };
     }
 
     pub fn release(&mut self, r: ReadLease) {
@@ -79,6 +316,15 @@ impl Tracker {
             // Optimization: if we have caught up to the writer, reset everything
             self.read_offset = 0;
             self.write_offset = 0;
+        } else if self.mirrored && self.inverted_at > 0 && end >= self.capacity {
+            // The reader followed the data into the mirror, so it's now at the
+            // start of the buffer, right behind the writer.
+            self.read_offset = end - self.capacity;
+            self.inverted_at = 0;
+            if self.read_offset == self.write_offset {
+                self.read_offset = 0;
+                self.write_offset = 0;
+            }
         } else if end == self.inverted_at {
             // if the writer has already inverted and there is no more data to read
             // at the end of the buffer, move the reader to the start and clear the
@@ -89,6 +335,32 @@ impl Tracker {
             self.read_offset = end;
         }
     }
+
+    // truncate shrinks `w`, which must be the most recently committed write,
+    // down to its first `len` bytes. The reader must not have read past them.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn truncate(&mut self, w: WriteLease, len: usize) {
+        let end = w.start + len;
+        if self.mirrored && w.start + w.len > self.capacity && end <= self.capacity {
+            // It no longer runs off the end of the buffer.
+            self.inverted_at = 0;
+        } else if !self.mirrored && w.start == 0 && self.inverted_at > 0 && len == 0 {
+            // It was the write that inverted the buffer, and now it's gone,
+            // so the writer goes back to where it was.
+            self.write_offset = self.inverted_at;
+            self.inverted_at = 0;
+            return;
+        }
+        self.write_offset = if end > self.capacity {
+            end - self.capacity
+        } else {
+            end
+        };
+        if self.inverted_at == 0 && self.read_offset == self.write_offset {
+            self.read_offset = 0;
+            self.write_offset = 0;
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -244,4 +516,130 @@ mod test {
             t.release(r);
         }
     }
+
+    #[test]
+    fn unread_spans_inversion() {
+        let mut t = Tracker::new(10);
+        assert_eq!(t.unread(), [0..0, 0..0]);
+        let w = t.write(8).unwrap();
+        t.commit(w);
+        t.release(ReadLease::new(0..5));
+        let w = t.write(4).unwrap();
+        t.commit(w);
+        assert_eq!(t.unread(), [5..8, 0..4]);
+
+        let mut t = Tracker::new_mirrored(10);
+        let w = t.write(8).unwrap();
+        t.commit(w);
+        t.release(ReadLease::new(0..5));
+        let w = t.write(4).unwrap();
+        t.commit(w);
+        assert_eq!(t.read(), Some(ReadLease::new(5..12)));
+        assert_eq!(t.unread(), [5..10, 0..2]);
+    }
+
+    #[test]
+    fn mirrored_write_crosses_end() {
+        let mut t = Tracker::new_mirrored(10);
+        {
+            let w = t.write(6).unwrap();
+            t.commit(w);
+            let r = t.read().unwrap();
+            assert_eq!(r, ReadLease::new(0..6));
+            t.release(r);
+        }
+        {
+            let w = t.write(3).unwrap();
+            assert_eq!(w, WriteLease::new(0..3));
+            t.commit(w);
+            let r = t.read().unwrap();
+            // Leave 2..3 unread so the buffer doesn't reset.
+            t.release(ReadLease::new(r.start..2));
+        }
+        // 8 bytes don't fit before the end, but do fit if we run into the
+        // mirror; a regular tracker would have to reject this write.
+        {
+            let w = t.write(8).unwrap();
+            assert_eq!(w, WriteLease::new(3..11));
+            t.commit(w);
+        }
+        // The whole thing comes back as a single read.
+        let r = t.read().unwrap();
+        assert_eq!(r, ReadLease::new(2..11));
+        // Full: only 1 byte (1..2) is free.
+        assert_eq!(t.write(2), None);
+        t.release(r);
+        assert_eq!(t.read(), None);
+        let w = t.write(10).unwrap();
+        assert_eq!(w, WriteLease::new(0..10));
+    }
+
+    #[test]
+    fn mirrored_wraps_from_exact_end() {
+        let mut t = Tracker::new_mirrored(10);
+        {
+            let w = t.write(10).unwrap();
+            t.commit(w);
+        }
+        {
+            let r = t.read().unwrap();
+            t.release(ReadLease::new(r.start..4));
+        }
+        {
+            let w = t.write(4).unwrap();
+            assert_eq!(w, WriteLease::new(10..14));
+            t.commit(w);
+        }
+        {
+            let r = t.read().unwrap();
+            assert_eq!(r, ReadLease::new(4..14));
+            t.release(ReadLease::new(r.start..12));
+        }
+        let r = t.read().unwrap();
+        assert_eq!(r, ReadLease::new(2..4));
+        t.release(r);
+        assert_eq!(t.read(), None);
+    }
+
+    #[test]
+    fn truncate_undoes_inversion() {
+        let mut t = Tracker::new(10);
+        {
+            let w = t.write(8).unwrap();
+            t.commit(w);
+            t.release(ReadLease::new(0..4));
+        }
+        let w = t.write(3).unwrap();
+        assert_eq!(w, WriteLease::new(0..3));
+        t.commit(WriteLease::new(0..3));
+        t.truncate(w, 1);
+        assert_eq!(t.read(), Some(ReadLease::new(4..8)));
+        t.release(ReadLease::new(4..8));
+        assert_eq!(t.read(), Some(ReadLease::new(0..1)));
+        t.release(ReadLease::new(0..1));
+        assert!(t.is_idle());
+
+        let w = t.write(4).unwrap();
+        t.commit(WriteLease::new(0..4));
+        t.truncate(w, 0);
+        assert!(t.is_idle());
+    }
+
+    #[test]
+    fn truncate_to_nothing_uninverts() {
+        let mut t = Tracker::new(10);
+        let w = t.write(8).unwrap();
+        t.commit(w);
+        t.release(ReadLease::new(0..4));
+        assert_eq!(t.available(), 4);
+        assert_eq!(t.contiguous(), 2);
+        let w = t.write(4).unwrap();
+        assert_eq!(w, WriteLease::new(0..4));
+        t.commit(WriteLease::new(0..4));
+        assert!(t.is_inverted());
+        assert_eq!(t.available(), 0);
+        t.truncate(w, 0);
+        assert!(!t.is_inverted());
+        assert_eq!(t.write(2), Some(WriteLease::new(8..10)));
+    }
 }
@@ -0,0 +1,297 @@
+use core::ops::Range;
+
+use super::{ReadLease, WriteLease};
+
+// Pow2Tracker is an alternative to Tracker for power-of-two capacities. Its
+// offsets are monotonically increasing counters which are masked on access,
+// so the fill level is always just `write - read` and there are fewer
+// branches on the hot path.
+pub(crate) struct Pow2Tracker {
+    mask: u64,
+    // write is the total number of bytes ever written, including padding.
+    write: u64,
+    // read is the total number of bytes ever read, including padding.
+    read: u64,
+    // padding is the range of counter values (if any) that the writer skipped
+    // over because a write didn't fit at the end of the buffer. Only one such
+    // range can exist at a time: the writer can't get far enough ahead of the
+    // reader to need another.
+    padding: Option<(u64, u64)>,
+}
+impl Pow2Tracker {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two());
+        Self {
+            mask: capacity as u64 - 1,
+            write: 0,
+            read: 0,
+            padding: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        (self.mask + 1) as usize
+    }
+
+    pub fn clear(&mut self) {
+        self.write = 0;
+        self.read = 0;
+        self.padding = None;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.write == self.read && self.write & self.mask == 0 && self.padding.is_none()
+    }
+
+    // is_inverted means the unread data (including any padding) wraps around
+    // the end of the buffer.
+    pub fn is_inverted(&self) -> bool {
+        self.write != self.read && self.read & !self.mask != (self.write - 1) & !self.mask
+    }
+
+    pub fn shortfall(&self, sz: usize) -> Option<usize> {
+        let capacity = self.mask + 1;
+        let sz = sz as u64;
+        let offset = self.write & self.mask;
+        let pad = if offset + sz > capacity {
+            capacity - offset
+        } else {
+            0
+        };
+        if pad + sz > capacity {
+            return None;
+        }
+        let used = self.write - self.read;
+        Some((used + pad + sz).saturating_sub(capacity) as usize)
+    }
+
+    pub fn available(&self) -> usize {
+        let capacity = self.mask + 1;
+        let free = capacity - (self.write - self.read);
+        let to_end = capacity - (self.write & self.mask);
+        // A write that doesn't fit before the end of the buffer has to skip
+        // the rest of it.
+        free.min(to_end).max(free.saturating_sub(to_end)) as usize
+    }
+
+    // contiguous is Tracker::contiguous: the room before the end of the
+    // buffer.
+    pub fn contiguous(&self) -> usize {
+        let capacity = self.mask + 1;
+        let free = capacity - (self.write - self.read);
+        free.min(capacity - (self.write & self.mask)) as usize
+    }
+
+    pub fn write(&mut self, sz: usize) -> Option<WriteLease> {
+        let capacity = self.mask + 1;
+        let sz = sz as u64;
+        let used = self.write - self.read;
+        let offset = self.write & self.mask;
+        if offset + sz <= capacity {
+            if used + sz > capacity {
+                return None;
+            }
+            return Some(WriteLease {
+                start: offset as usize,
+                len: sz as usize,
+            });
+        }
+        // The write doesn't fit before the end of the buffer, so skip the
+        // rest of this lap and write at the start instead.
+        let pad = capacity - offset;
+        if used + pad + sz > capacity {
+            return None;
+        }
+        self.padding = Some((self.write, self.write + pad));
+        self.write += pad;
+        Some(WriteLease {
+            start: 0,
+            len: sz as usize,
+        })
+    }
+
+    pub fn read(&mut self) -> Option<ReadLease> {
+        if let Some((start, end)) = self.padding
+            && self.read == start
+        {
+            self.read = end;
+            self.padding = None;
+        }
+        if self.read == self.write {
+            return None;
+        }
+        // Stop at the padding, or at the end of the buffer.
+        let limit = self.padding.map_or(self.write, |(start, _)| start);
+        let lap_end = (self.read | self.mask) + 1;
+        let len = limit.min(lap_end) - self.read;
+        Some(ReadLease {
+            start: (self.read & self.mask) as usize,
+            len: len as usize,
+        })
+    }
+
+    // unread is Tracker::unread, skipping any padding.
+    pub fn unread(&self) -> [Range<usize>; 2] {
+        let skip = |at: u64| match self.padding {
+            Some((start, end)) if start == at => end,
+            _ => at,
+        };
+        let start = skip(self.read).min(self.write);
+        let limit = match self.padding {
+            Some((pad, _)) if pad >= start => pad,
+            _ => self.write,
+        };
+        let end = limit.min((start | self.mask) + 1);
+        let rest = skip(end);
+        let range = |a: u64, b: u64| {
+            if a == b {
+                return 0..0;
+            }
+            let offset = (a & self.mask) as usize;
+            offset..offset + (b - a) as usize
+        };
+        [range(start, end), range(rest, self.write)]
+    }
+
+    pub fn commit(&mut self, w: WriteLease) {
+        self.write += w.len as u64;
+    }
+
+    pub fn release(&mut self, r: ReadLease) {
+        self.read += r.len as u64;
+    }
+
+    // truncate is Tracker::truncate. Any padding in front of `w` stays, and
+    // the reader skips it as usual.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn truncate(&mut self, w: WriteLease, len: usize) {
+        self.write -= (w.len - len) as u64;
+        if len == 0
+            && let Some((start, end)) = self.padding
+            && end == self.write
+        {
+            // Nothing follows the padding anymore, so it can go too.
+            self.write = start;
+            self.padding = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn w(start: usize, end: usize) -> WriteLease {
+        WriteLease::new(start..end)
+    }
+    fn r(start: usize, end: usize) -> ReadLease {
+        ReadLease::new(start..end)
+    }
+
+    #[test]
+    fn basic_write_then_read() {
+        let mut t = Pow2Tracker::new(8);
+        assert_eq!(t.read(), None);
+        let l = t.write(4).unwrap();
+        assert_eq!(l, w(0, 4));
+        t.commit(l);
+        let l = t.read().unwrap();
+        assert_eq!(l, r(0, 4));
+        t.release(l);
+        assert_eq!(t.read(), None);
+        // Unlike Tracker, offsets keep going rather than resetting.
+        assert_eq!(t.write(2).unwrap(), w(4, 6));
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut t = Pow2Tracker::new(8);
+        assert_eq!(t.write(9), None);
+        let l = t.write(8).unwrap();
+        t.commit(l);
+        assert_eq!(t.write(1), None);
+    }
+
+    #[test]
+    fn wraparound_pads_the_tail() {
+        let mut t = Pow2Tracker::new(8);
+        let l = t.write(5).unwrap();
+        t.commit(l);
+        let l = t.read().unwrap();
+        assert_eq!(l, r(0, 5));
+        t.release(l);
+
+        // 3 bytes left at the end of the buffer; a 4 byte write skips them.
+        let l = t.write(4).unwrap();
+        assert_eq!(l, w(0, 4));
+        t.commit(l);
+        // The padding counts as used: 3 + 4 of 8 bytes.
+        assert_eq!(t.write(2), None);
+        let l = t.write(1).unwrap();
+        assert_eq!(l, w(4, 5));
+        t.commit(l);
+
+        let l = t.read().unwrap();
+        assert_eq!(l, r(0, 5));
+        t.release(l);
+        assert_eq!(t.read(), None);
+    }
+
+    #[test]
+    fn unread_skips_padding() {
+        let mut t = Pow2Tracker::new(8);
+        let l = t.write(6).unwrap();
+        t.commit(l);
+        t.release(r(0, 5));
+        assert_eq!(t.unread(), [5..6, 0..0]);
+        // Pads 6..8.
+        let l = t.write(3).unwrap();
+        t.commit(l);
+        assert_eq!(t.unread(), [5..6, 0..3]);
+        t.release(r(5, 6));
+        assert_eq!(t.unread(), [0..3, 0..0]);
+    }
+
+    #[test]
+    fn reads_stop_at_the_end_of_the_buffer() {
+        let mut t = Pow2Tracker::new(8);
+        let l = t.write(6).unwrap();
+        t.commit(l);
+        let l = t.read().unwrap();
+        t.release(l);
+        // Exactly fills the tail, so no padding is needed.
+        let l = t.write(2).unwrap();
+        assert_eq!(l, w(6, 8));
+        t.commit(l);
+        let l = t.write(3).unwrap();
+        assert_eq!(l, w(0, 3));
+        t.commit(l);
+
+        let l = t.read().unwrap();
+        assert_eq!(l, r(6, 8));
+        t.release(l);
+        let l = t.read().unwrap();
+        assert_eq!(l, r(0, 3));
+        t.release(l);
+        assert_eq!(t.read(), None);
+    }
+
+    #[test]
+    fn truncate_to_nothing_drops_the_padding() {
+        let mut t = Pow2Tracker::new(8);
+        let l = t.write(5).unwrap();
+        t.commit(l);
+        t.release(r(0, 5));
+        let l = t.write(2).unwrap();
+        t.commit(l);
+        assert_eq!(t.available(), 5);
+        assert_eq!(t.contiguous(), 1);
+        let l = t.write(5).unwrap();
+        assert_eq!(l, w(0, 5));
+        t.commit(w(0, 5));
+        t.truncate(l, 0);
+        assert!(!t.is_inverted());
+        assert_eq!(t.available(), 5);
+        assert_eq!(t.write(1).unwrap(), w(7, 8));
+    }
+}
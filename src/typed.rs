@@ -0,0 +1,153 @@
+use std::{marker::PhantomData, mem::size_of, ops::Deref};
+
+use crate::{buffer, error::CreateError};
+
+// Element is a type that can be stored in a typed buffer: one that can be
+// copied in and out of it as raw bytes.
+//
+// Safety: the type must be Copy and have no padding, so that every byte of
+// a value is initialized.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe trait Element: Copy {}
+macro_rules! elements {
+    ($($t:ty),*) => {
+        $(unsafe impl Element for $t {})*
+    };
+}
+elements!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+unsafe impl<T: Element, const N: usize> Element for [T; N] {}
+
+// Reader and Writer are the halves of a typed buffer. Every write is a whole
+// number of elements, and the storage is aligned for T, so every Lease is a
+// properly aligned &[T].
+pub struct Reader<T>(buffer::Reader, PhantomData<T>);
+pub struct Writer<T>(buffer::Writer, PhantomData<T>);
+
+// try_create creates a typed buffer with room for `capacity` elements.
+pub fn try_create<T: Element>(capacity: usize) -> Result<(Reader<T>, Writer<T>), CreateError> {
+    let bytes = capacity
+        .checked_mul(size_of::<T>())
+        .ok_or(CreateError::CapacityTooLarge(capacity))?;
+    let (reader, writer) = buffer::try_create_framed(bytes, size_of::<T>(), align_of::<T>())?;
+    Ok((Reader(reader, PhantomData), Writer(writer, PhantomData)))
+}
+
+// create is like try_create, but panics if the capacity is invalid.
+pub fn create<T: Element>(capacity: usize) -> (Reader<T>, Writer<T>) {
+    match try_create(capacity) {
+        Ok(pair) => pair,
+        Err(err) => panic!("invalid buffer capacity: {err}"),
+    }
+}
+
+// bytes views `p` as its raw bytes.
+fn bytes<T: Element>(p: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(p.as_ptr().cast(), size_of_val(p)) }
+}
+
+impl<T: Element> Writer<T> {
+    pub fn try_write(&mut self, p: &[T]) -> bool {
+        self.0.try_write(bytes(p))
+    }
+
+    // write_retry is buffer::Writer::write_retry, for elements.
+    pub fn write_retry(&mut self, p: &[T]) -> Result<(), crate::WriteError> {
+        self.0.write_retry(bytes(p))
+    }
+}
+
+impl<T: Element> Reader<T> {
+    pub fn read(&mut self) -> Option<Lease<'_, T>> {
+        let lease = self.0.read()?;
+        let view = lease.view;
+        debug_assert!((view.as_ptr() as usize).is_multiple_of(align_of::<T>()));
+        debug_assert!(view.len().is_multiple_of(size_of::<T>()));
+        // Everything in the buffer was written as whole, aligned elements.
+        let view = unsafe {
+            std::slice::from_raw_parts(view.as_ptr().cast(), view.len() / size_of::<T>())
+        };
+        Some(Lease {
+            _lease: lease,
+            view,
+        })
+    }
+}
+
+// Lease is buffer::Lease, viewed as elements.
+pub struct Lease<'a, T> {
+    _lease: buffer::Lease<'a>,
+    view: &'a [T],
+}
+impl<T> Deref for Lease<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.view
+    }
+}
+
+impl<T> std::fmt::Debug for Reader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl<T> std::fmt::Debug for Writer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn u64_round_trip_across_the_wrap() {
+        let (mut reader, mut writer) = create::<u64>(10);
+        assert!(writer.try_write(&[1, 2, 3, 4, 5]));
+        let l = reader.read().unwrap();
+        assert!(writer.try_write(&[6, 7, 8, 9]));
+        drop(l);
+        assert!(writer.try_write(&[10, 11, 12]));
+        assert!(!writer.try_write(&[13, 14, 15]));
+        assert!(format!("{writer:?}").contains("inverted: true"));
+        assert_eq!(&*reader.read().unwrap(), &[6, 7, 8, 9]);
+        assert_eq!(&*reader.read().unwrap(), &[10, 11, 12]);
+        assert!(reader.read().is_none());
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Sample {
+        at: u64,
+        left: f32,
+        right: f32,
+    }
+    unsafe impl Element for Sample {}
+
+    #[test]
+    fn structs_stay_aligned() {
+        let sample = |i: u64| Sample {
+            at: i,
+            left: i as f32,
+            right: -(i as f32),
+        };
+        let (mut reader, mut writer) = create::<Sample>(4);
+        for round in 0..10 {
+            let samples: Vec<_> = (0..3).map(|i| sample(round * 3 + i)).collect();
+            assert!(writer.try_write(&samples));
+            let l = reader.read().unwrap();
+            assert!((l.as_ptr() as usize).is_multiple_of(align_of::<Sample>()));
+            assert_eq!(&*l, &samples[..]);
+        }
+    }
+
+    #[test]
+    fn capacity_overflow() {
+        assert_eq!(
+            try_create::<u64>(usize::MAX).err(),
+            Some(CreateError::CapacityTooLarge(usize::MAX))
+        );
+    }
+}